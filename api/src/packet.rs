@@ -8,6 +8,7 @@ use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+use anyhow::anyhow;
 use tinyvec::TinyVec;
 
 use serde::ser::SerializeSeq;
@@ -15,9 +16,17 @@ use serde::{Serialize, Serializer};
 
 use super::classifiers::matched::Rule;
 
+pub mod ppp;
+pub mod sctp;
+pub mod tcp;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
+/// Packet direction within its session
 pub enum Direction {
+    /// From the session initiator to the responder
     LEFT = 0,
+    /// From the session responder to the initiator
     RIGHT = 1,
 }
 
@@ -48,10 +57,20 @@ pub struct Layers {
     pub network: Layer,
     pub trans: Layer,
     pub app: Layer,
+    /// Outermost tunnel layer, only set when packet is tunnelled
+    pub tunnel: Layer,
+    /// Network layer encapsulating the outermost tunnel
+    pub outer_network: Layer,
+    /// Transport layer encapsulating the outermost tunnel
+    pub outer_trans: Layer,
+    /// Outermost MPLS label stack
+    pub mpls: Layer,
+    /// Outermost PPPoE header
+    pub pppoe: Layer,
 }
 
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PacketHashMethod {
     /// Normal 5 tuple hash
     FiveTuple,
@@ -60,7 +79,188 @@ pub enum PacketHashMethod {
     MacOnly,
 }
 
-#[derive(Debug, Eq)]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Which tuple of a tunnelled packet is used to build its PacketHashKey
+pub enum TunnelHashMode {
+    /// Use the encapsulated (inner) 5 tuple
+    Inner,
+    /// Use the encapsulating (outer) 5 tuple
+    Outer,
+    /// Use the inner 5 tuple, the outer ip addresses and the tunnel identifier
+    Both,
+}
+
+impl Default for TunnelHashMode {
+    fn default() -> Self {
+        TunnelHashMode::Inner
+    }
+}
+
+impl TryFrom<&str> for TunnelHashMode {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "inner" => Ok(TunnelHashMode::Inner),
+            "outer" => Ok(TunnelHashMode::Outer),
+            "both" => Ok(TunnelHashMode::Both),
+            _ => Err(anyhow!("Unknown tunnel hash mode: {}", value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Tunnel hash mode of each tunnel protocol
+pub struct TunnelHashModes {
+    pub gre: TunnelHashMode,
+    pub gtp: TunnelHashMode,
+    pub l2tp: TunnelHashMode,
+    pub mpls: TunnelHashMode,
+    pub ppp: TunnelHashMode,
+    pub pppoe: TunnelHashMode,
+    pub vxlan: TunnelHashMode,
+    pub ipip: TunnelHashMode,
+    pub ip6in4: TunnelHashMode,
+    pub teredo: TunnelHashMode,
+    pub wireguard: TunnelHashMode,
+}
+
+impl TunnelHashModes {
+    /// Get hash mode by the outermost tunnel protocol
+    pub fn get(&self, protocol: Protocol) -> TunnelHashMode {
+        match protocol {
+            Protocol::GRE => self.gre,
+            Protocol::GTP => self.gtp,
+            Protocol::L2TP => self.l2tp,
+            Protocol::MPLS => self.mpls,
+            Protocol::PPP => self.ppp,
+            Protocol::PPPOE => self.pppoe,
+            Protocol::VXLAN => self.vxlan,
+            Protocol::IPIP => self.ipip,
+            Protocol::IP6IN4 => self.ip6in4,
+            Protocol::TEREDO => self.teredo,
+            Protocol::WIREGUARD => self.wireguard,
+            _ => TunnelHashMode::Inner,
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    match buf.get(pos..pos + 2) {
+        Some(b) => (b[0] as u16) << 8 | b[1] as u16,
+        None => 0,
+    }
+}
+
+#[inline]
+pub(crate) fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    match buf.get(pos..pos + 4) {
+        Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        None => 0,
+    }
+}
+
+/// Get a layer's src and dst port, larger one first
+#[inline]
+fn sorted_ports(raw: &[u8], trans: &Layer) -> (u16, u16) {
+    match trans.protocol {
+        Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
+            let src_port = read_u16(raw, trans.offset as usize);
+            let dst_port = read_u16(raw, trans.offset as usize + 2);
+            if src_port > dst_port {
+                (src_port, dst_port)
+            } else {
+                (dst_port, src_port)
+            }
+        }
+        _ => (0, 0),
+    }
+}
+
+/// Get an Ethernet layer's src and dst mac address, larger one first
+#[inline]
+fn sorted_macs(raw: &[u8], data_link: &Layer) -> ([u8; 6], [u8; 6]) {
+    let pos = data_link.offset as usize;
+    match (raw.get(pos..pos + 6), raw.get(pos + 6..pos + 12)) {
+        (Some(dst), Some(src)) => {
+            let (src, dst) = if src > dst { (src, dst) } else { (dst, src) };
+            (
+                <[u8; 6]>::try_from(src).unwrap(),
+                <[u8; 6]>::try_from(dst).unwrap(),
+            )
+        }
+        _ => ([0; 6], [0; 6]),
+    }
+}
+
+/// Get a layer's src and dst ip address
+#[inline]
+fn ips(raw: &[u8], network: &Layer) -> (IpAddr, IpAddr) {
+    match network.protocol {
+        Protocol::IPV4 => {
+            let pos = network.offset as usize;
+            (
+                IpAddr::V4(Ipv4Addr::from(read_u32(raw, pos + 12))),
+                IpAddr::V4(Ipv4Addr::from(read_u32(raw, pos + 16))),
+            )
+        }
+        Protocol::IPV6 => {
+            let pos = network.offset as usize;
+            match (raw.get(pos + 8..pos + 24), raw.get(pos + 24..pos + 40)) {
+                (Some(src), Some(dst)) => (
+                    IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(src).unwrap())),
+                    IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(dst).unwrap())),
+                ),
+                _ => (IpAddr::V4(Ipv4Addr::from(0)), IpAddr::V4(Ipv4Addr::from(0))),
+            }
+        }
+        _ => (IpAddr::V4(Ipv4Addr::from(0)), IpAddr::V4(Ipv4Addr::from(0))),
+    }
+}
+
+/// Get the upper layer protocol number of a network layer and the offset of its header
+///
+/// IPv6 extension headers are skipped, AH and ESP are taken as upper layer protocols
+/// like they are in IPv4
+pub(crate) fn ip_payload(raw: &[u8], network: &Layer) -> Option<(u8, usize)> {
+    let pos = network.offset as usize;
+    match network.protocol {
+        Protocol::IPV4 => {
+            let ihl = (*raw.get(pos)? & 0x0f) as usize * 4;
+            Some((*raw.get(pos + 9)?, pos + ihl))
+        }
+        Protocol::IPV6 => {
+            let mut next = *raw.get(pos + 6)?;
+            let mut pos = pos + 40;
+            loop {
+                let len = match next {
+                    // hop-by-hop, routing, destination options, mobility, HIP, shim6
+                    0 | 43 | 60 | 135 | 139 | 140 => (*raw.get(pos + 1)? as usize + 1) * 8,
+                    // fragment
+                    44 => 8,
+                    _ => return Some((next, pos)),
+                };
+                next = *raw.get(pos)?;
+                pos += len;
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Get a layer's src and dst ip address, larger one first
+#[inline]
+fn sorted_ips(raw: &[u8], network: &Layer) -> (IpAddr, IpAddr) {
+    let (src_ip, dst_ip) = ips(raw, network);
+    if src_ip > dst_ip {
+        (src_ip, dst_ip)
+    } else {
+        (dst_ip, src_ip)
+    }
+}
+
+#[derive(Clone, Debug, Eq)]
 pub struct PacketHashKey {
     pub hash_method: PacketHashMethod,
     pub network_proto: Protocol,
@@ -69,9 +269,20 @@ pub struct PacketHashKey {
     pub dst_port: u16,
     pub src_ip: IpAddr,
     pub dst_ip: IpAddr,
-    #[cfg(feature = "pkt-hash-mac")]
+    /// Outermost tunnel protocol, only set in TunnelHashMode::Both
+    pub tunnel_proto: Protocol,
+    /// Outermost tunnel identifier, only set in TunnelHashMode::Both
+    pub tunnel_id: u32,
+    /// Outer src ip address, only set in TunnelHashMode::Both
+    pub outer_src_ip: IpAddr,
+    /// Outer dst ip address, only set in TunnelHashMode::Both
+    pub outer_dst_ip: IpAddr,
+    /// ESP security parameter index, the packet thread replaces it with
+    /// the SPI identifying the SA pair, so both directions share one session
+    pub spi: u32,
+    /// Ethernet addresses, larger one first, only set for the MacOnly hash method
+    /// or non IP PPPoE payload
     pub src_mac: [u8; 6],
-    #[cfg(feature = "pkt-hash-mac")]
     pub dst_mac: [u8; 6],
 }
 
@@ -85,9 +296,12 @@ impl Default for PacketHashKey {
             dst_port: 0,
             src_ip: IpAddr::V4(Ipv4Addr::from(0)),
             dst_ip: IpAddr::V4(Ipv4Addr::from(0)),
-            #[cfg(feature = "pkt-hash-mac")]
+            tunnel_proto: Protocol::default(),
+            tunnel_id: 0,
+            outer_src_ip: IpAddr::V4(Ipv4Addr::from(0)),
+            outer_dst_ip: IpAddr::V4(Ipv4Addr::from(0)),
+            spi: 0,
             src_mac: [0; 6],
-            #[cfg(feature = "pkt-hash-mac")]
             dst_mac: [0; 6],
         }
     }
@@ -95,51 +309,73 @@ impl Default for PacketHashKey {
 
 impl From<&dyn Packet> for PacketHashKey {
     fn from(pkt: &dyn Packet) -> Self {
+        Self::new(pkt, &TunnelHashModes::default())
+    }
+}
+
+impl PacketHashKey {
+    /// Create a packet hash key, tunnelled packets are keyed according to
+    /// their outermost tunnel protocol's hash mode
+    pub fn new(pkt: &dyn Packet, modes: &TunnelHashModes) -> Self {
         let mut key = Self::default();
-        key.network_proto = pkt.layers().network.protocol;
-        key.trans_proto = pkt.layers().trans.protocol;
-
-        unsafe {
-            match key.trans_proto {
-                Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
-                    if pkt.src_port() > pkt.dst_port() {
-                        key.src_port = pkt.src_port();
-                        key.dst_port = pkt.dst_port();
-                    } else {
-                        key.src_port = pkt.dst_port();
-                        key.dst_port = pkt.src_port();
-                    }
-                }
-                _ => {}
-            };
-        }
+        let layers = pkt.layers();
 
-        match key.network_proto {
-            Protocol::IPV4 => {
-                let src_ip = unsafe { pkt.src_ipv4() };
-                let dst_ip = unsafe { pkt.dst_ipv4() };
-                if src_ip > dst_ip {
-                    key.src_ip = IpAddr::V4(Ipv4Addr::from(src_ip));
-                    key.dst_ip = IpAddr::V4(Ipv4Addr::from(dst_ip));
-                } else {
-                    key.src_ip = IpAddr::V4(Ipv4Addr::from(dst_ip));
-                    key.dst_ip = IpAddr::V4(Ipv4Addr::from(src_ip));
-                }
+        let mode = if pkt.tunnel().is_empty() {
+            TunnelHashMode::Inner
+        } else {
+            modes.get(layers.tunnel.protocol)
+        };
+
+        // Tunnels like MPLS or PPPoE may have no outer network layer at all,
+        // in that case the inner tuple is the only one we have. Encrypted tunnels
+        // like WireGuard have no inner network layer, so they're keyed by the outer one
+        let (network, trans) = match mode {
+            TunnelHashMode::Outer if layers.outer_network.protocol != Protocol::UNKNOWN => {
+                (&layers.outer_network, &layers.outer_trans)
             }
-            Protocol::IPV6 => {
-                let src_ip = unsafe { *pkt.src_ipv6() };
-                let dst_ip = unsafe { *pkt.dst_ipv6() };
-                if src_ip > dst_ip {
-                    key.src_ip = IpAddr::V6(Ipv6Addr::from(src_ip));
-                    key.dst_ip = IpAddr::V6(Ipv6Addr::from(dst_ip));
-                } else {
-                    key.src_ip = IpAddr::V6(Ipv6Addr::from(dst_ip));
-                    key.dst_ip = IpAddr::V6(Ipv6Addr::from(src_ip));
-                }
+            _ if layers.network.protocol == Protocol::UNKNOWN => {
+                (&layers.outer_network, &layers.outer_trans)
             }
-            _ => {}
+            _ => (&layers.network, &layers.trans),
         };
 
+        key.network_proto = network.protocol;
+        key.trans_proto = trans.protocol;
+
+        let (src_port, dst_port) = sorted_ports(pkt.raw(), trans);
+        key.src_port = src_port;
+        key.dst_port = dst_port;
+
+        let (src_ip, dst_ip) = sorted_ips(pkt.raw(), network);
+        key.src_ip = src_ip;
+        key.dst_ip = dst_ip;
+
+        if trans.protocol == Protocol::ESP {
+            key.spi = read_u32(pkt.raw(), trans.offset as usize);
+        }
+
+        // Non IP payload, e.g. PPP control packets, is keyed by its tunnel
+        if network.protocol == Protocol::UNKNOWN && !pkt.tunnel().is_empty() {
+            key.tunnel_proto = layers.tunnel.protocol;
+            key.tunnel_id = pkt.tunnel_id();
+            // PPPoE session ids are only unique between a client and its access concentrator
+            if layers.tunnel.protocol == Protocol::PPPOE
+                && layers.data_link.protocol == Protocol::ETHERNET
+            {
+                let (src_mac, dst_mac) = sorted_macs(pkt.raw(), &layers.data_link);
+                key.src_mac = src_mac;
+                key.dst_mac = dst_mac;
+            }
+        }
+
+        if mode == TunnelHashMode::Both {
+            key.tunnel_proto = layers.tunnel.protocol;
+            key.tunnel_id = pkt.tunnel_id();
+            let (src_ip, dst_ip) = sorted_ips(pkt.raw(), &layers.outer_network);
+            key.outer_src_ip = src_ip;
+            key.outer_dst_ip = dst_ip;
+        }
+
         #[cfg(feature = "pkt-hash-mac")]
         {
             match key.hash_method {
@@ -171,6 +407,13 @@ impl Hash for PacketHashKey {
                 self.dst_port.hash(state);
                 self.src_ip.hash(state);
                 self.dst_ip.hash(state);
+                self.tunnel_proto.hash(state);
+                self.tunnel_id.hash(state);
+                self.outer_src_ip.hash(state);
+                self.outer_dst_ip.hash(state);
+                self.spi.hash(state);
+                self.src_mac.hash(state);
+                self.dst_mac.hash(state);
             }
             #[cfg(feature = "pkt-hash-mac")]
             PacketHashMethod::MacOnly => {
@@ -187,12 +430,23 @@ impl PartialEq for PacketHashKey {
             return false;
         }
 
+        if self.tunnel_proto != other.tunnel_proto
+            || self.tunnel_id != other.tunnel_id
+            || self.outer_src_ip != other.outer_src_ip
+            || self.outer_dst_ip != other.outer_dst_ip
+            || self.spi != other.spi
+            || self.src_mac != other.src_mac
+            || self.dst_mac != other.dst_mac
+        {
+            return false;
+        }
+
         match self.trans_proto {
             Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
                 let self_src_port = self.src_port;
                 let self_dst_port = self.dst_port;
-                let other_src_port = self.src_port;
-                let other_dst_port = self.dst_port;
+                let other_src_port = other.src_port;
+                let other_dst_port = other.dst_port;
 
                 let self_cmp = self_src_port > self_dst_port;
                 let other_cmp = other_src_port > other_dst_port;
@@ -266,12 +520,27 @@ pub trait Packet: Send {
 
     fn clone_box(&self) -> Box<dyn Packet + '_>;
 
+    /// Packet direction, assigned by the session this packet belongs to
+    fn direction(&self) -> Direction;
+    fn direction_mut(&mut self) -> &mut Direction;
+
+    /// Data link type of the capture this packet comes from, a pcap DLT number
+    ///
+    /// Defaults to Ethernet, DLT_EN10MB
+    #[inline]
+    fn link_type(&self) -> u16 {
+        1
+    }
+
     #[inline]
     fn data_len(&self) -> u16 {
         match self.layers().trans.protocol {
-            Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
-                self.caplen() as u16 - self.layers().app.offset
-            }
+            Protocol::SCTP => self
+                .sctp_chunks()
+                .filter_map(|chunk| chunk.data())
+                .map(|(_, len)| len)
+                .sum(),
+            Protocol::TCP | Protocol::UDP => self.caplen() as u16 - self.layers().app.offset,
             _ => self.caplen() as u16 - self.layers().trans.offset,
         }
     }
@@ -294,6 +563,103 @@ pub trait Packet: Send {
         (*(self.raw().as_ptr().add(dst_port_pos) as *const u16)).to_be()
     }
 
+    /// Get src and dst ip address of network layer
+    ///
+    /// Returns unspecified ipv4 addresses if network layer is neither IPV4 nor IPV6
+    #[inline]
+    fn ips(&self) -> (IpAddr, IpAddr) {
+        ips(self.raw(), &self.layers().network)
+    }
+
+    /// Get src and dst port of transport layer
+    ///
+    /// Returns zeros if transport layer is neither TCP, UDP nor SCTP
+    #[inline]
+    fn ports(&self) -> (u16, u16) {
+        let trans = &self.layers().trans;
+        match trans.protocol {
+            Protocol::TCP | Protocol::UDP | Protocol::SCTP => (
+                read_u16(self.raw(), trans.offset as usize),
+                read_u16(self.raw(), trans.offset as usize + 2),
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    /// Get IP protocol number carried by network layer
+    ///
+    /// Returns 0 if network layer is neither IPV4 nor IPV6. For IPV6 it's the protocol
+    /// following the extension headers.
+    #[inline]
+    fn ip_protocol(&self) -> u8 {
+        ip_payload(self.raw(), &self.layers().network).map_or(0, |(protocol, _)| protocol)
+    }
+
+    /// Get ESP security parameter index
+    ///
+    /// It's the caller's duty to guarantee transport layer is ESP
+    #[inline]
+    fn esp_spi(&self) -> u32 {
+        read_u32(self.raw(), self.layers().trans.offset as usize)
+    }
+
+    /// Get TCP header length
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_hdr_len(&self) -> u16 {
+        let pos = self.layers().trans.offset as usize + 12;
+        self.raw().get(pos).map_or(0, |b| (b >> 4) as u16 * 4)
+    }
+
+    /// Get TCP flags
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_flags(&self) -> tcp::Flags {
+        let pos = self.layers().trans.offset as usize + 13;
+        self.raw()
+            .get(pos)
+            .map_or(tcp::Flags::empty(), |b| tcp::Flags::from_bits_truncate(*b))
+    }
+
+    /// Get TCP sequence number
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_seq(&self) -> u32 {
+        read_u32(self.raw(), self.layers().trans.offset as usize + 4)
+    }
+
+    /// Get TCP acknowledgment number
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_ack(&self) -> u32 {
+        read_u32(self.raw(), self.layers().trans.offset as usize + 8)
+    }
+
+    /// Get TCP window size, without window scaling
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_window(&self) -> u16 {
+        read_u16(self.raw(), self.layers().trans.offset as usize + 14)
+    }
+
+    /// Get parsed TCP options
+    ///
+    /// It's the caller's duty to guarantee transport layer is TCP
+    #[inline]
+    fn tcp_options(&self) -> tcp::Options {
+        let start = self.layers().trans.offset as usize + tcp::MIN_HDR_LEN;
+        let end = self.layers().trans.offset as usize + self.tcp_hdr_len() as usize;
+        match self.raw().get(start..end) {
+            Some(buf) => tcp::Options::parse(buf),
+            None => tcp::Options::default(),
+        }
+    }
+
     /// Get src ipv4 address
     ///
     /// It's the caller's duty to guarantee network layer is IPV4
@@ -346,44 +712,167 @@ pub trait Packet: Send {
         <&[u8; 6]>::try_from(&self.raw()[0..6]).unwrap()
     }
 
+    /// Get labels of the outermost MPLS label stack, from top to bottom
     #[inline]
-    /// Get packet's application layer payload
-    fn payload(&self) -> &[u8] {
-        &self.raw()[self.layers().app.offset as usize..]
+    fn mpls_labels(&self) -> TinyVec<[u32; 4]> {
+        let mut labels = TinyVec::new();
+        if self.layers().mpls.protocol != Protocol::MPLS {
+            return labels;
+        }
+
+        let mut pos = self.layers().mpls.offset as usize;
+        while pos + 4 <= self.raw().len() {
+            let entry = read_u32(self.raw(), pos);
+            labels.push(entry >> 12);
+            // bottom of stack bit
+            if entry & 0x100 != 0 {
+                break;
+            }
+            pos += 4;
+        }
+        labels
     }
 
+    /// Get PPPoE session id of the outermost PPPoE header
+    ///
+    /// Returns None if the packet is not PPPoE encapsulated
     #[inline]
-    fn direction(&self) -> Direction {
-        match self.layers().trans.protocol {
-            Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
-                if unsafe { self.src_port() > self.dst_port() } {
-                    return Direction::LEFT;
-                } else {
-                    return Direction::RIGHT;
-                }
-            }
-            _ => {}
+    fn pppoe_session_id(&self) -> Option<u16> {
+        let layer = &self.layers().pppoe;
+        if layer.protocol != Protocol::PPPOE {
+            return None;
+        }
+        Some(read_u16(self.raw(), layer.offset as usize + 2))
+    }
+
+    /// Get PPPoE discovery code and tags
+    ///
+    /// Returns None if the packet is not a PPPoE discovery packet
+    #[inline]
+    fn pppoe_discovery(&self) -> Option<(u8, ppp::Tlvs<'_>)> {
+        let layer = &self.layers().pppoe;
+        if layer.protocol != Protocol::PPPOE {
+            return None;
+        }
+        let buf = self.raw().get(layer.offset as usize..)?;
+        if buf.len() < ppp::PPPOE_HDR_LEN || buf[1] == ppp::PPPOE_SESSION {
+            return None;
+        }
+        let len = read_u16(buf, 4) as usize;
+        let tags = buf.get(ppp::PPPOE_HDR_LEN..ppp::PPPOE_HDR_LEN + len)?;
+        Some((buf[1], ppp::Tlvs::tags(tags)))
+    }
+
+    /// Get PPP protocol and control packet carried by a PPPoE session packet
+    ///
+    /// Returns None if the packet is not a PPPoE session packet of LCP/IPCP/IPV6CP/PAP/CHAP
+    #[inline]
+    fn ppp_control(&self) -> Option<(u16, ppp::ControlPacket<'_>)> {
+        let layer = &self.layers().pppoe;
+        if layer.protocol != Protocol::PPPOE {
+            return None;
+        }
+        let buf = self.raw().get(layer.offset as usize..)?;
+        if buf.len() < ppp::PPPOE_HDR_LEN + 2 || buf[1] != ppp::PPPOE_SESSION {
+            return None;
+        }
+        let protocol = read_u16(buf, ppp::PPPOE_HDR_LEN);
+        if !ppp::is_control(protocol) {
+            return None;
+        }
+        let pkt = ppp::ControlPacket::parse(&buf[ppp::PPPOE_HDR_LEN + 2..])?;
+        Some((protocol, pkt))
+    }
+
+    /// Get the identifier of the outermost tunnel,
+    /// e.g. GRE key, VXLAN VNI, GTP TEID or MPLS label
+    ///
+    /// Returns 0 if the packet is not tunnelled or the tunnel carries no identifier
+    #[inline]
+    fn tunnel_id(&self) -> u32 {
+        let layer = &self.layers().tunnel;
+        let buf = match self.raw().get(layer.offset as usize..) {
+            Some(buf) => buf,
+            None => return 0,
         };
 
-        match self.layers().network.protocol {
-            Protocol::IPV4 => {
-                if unsafe { self.src_ipv4() > self.src_ipv4() } {
-                    return Direction::LEFT;
-                } else {
-                    return Direction::RIGHT;
+        match layer.protocol {
+            Protocol::MPLS => read_u32(buf, 0) >> 12,
+            Protocol::GRE => {
+                // key present bit
+                if buf.is_empty() || buf[0] & 0x20 == 0 {
+                    return 0;
                 }
-            }
-            Protocol::IPV6 => {
-                if unsafe { *self.src_ipv6() > *self.src_ipv6() } {
-                    return Direction::LEFT;
+                // checksum present bit
+                if buf[0] & 0x80 == 0 {
+                    read_u32(buf, 4)
                 } else {
-                    return Direction::RIGHT;
+                    read_u32(buf, 8)
                 }
             }
-            _ => {}
+            Protocol::GTP => read_u32(buf, 4),
+            Protocol::VXLAN => read_u32(buf, 4) >> 8,
+            Protocol::L2TP => match read_u16(buf, 0) & 0x000f {
+                2 => {
+                    // length bit
+                    let pos = if read_u16(buf, 0) & 0x4000 == 0 { 2 } else { 4 };
+                    read_u32(buf, pos)
+                }
+                3 => read_u32(buf, 4),
+                _ => 0,
+            },
+            Protocol::PPPOE => read_u16(buf, 2) as u32,
+            _ => 0,
         }
+    }
 
-        Direction::LEFT
+    /// Get SCTP chunks
+    ///
+    /// It's the caller's duty to guarantee transport layer is SCTP
+    #[inline]
+    fn sctp_chunks(&self) -> sctp::Chunks<'_> {
+        sctp::Chunks::new(self.raw(), self.layers().trans.offset)
+    }
+
+    /// Get SCTP stream identifier of current DATA chunk
+    ///
+    /// It's the caller's duty to guarantee application layer is a SCTP DATA chunk's payload
+    #[inline]
+    fn sctp_stream_id(&self) -> u16 {
+        read_u16(
+            self.raw(),
+            (self.layers().app.offset as usize).saturating_sub(8),
+        )
+    }
+
+    /// Get SCTP payload protocol identifier of current DATA chunk
+    ///
+    /// It's the caller's duty to guarantee application layer is a SCTP DATA chunk's payload
+    #[inline]
+    fn sctp_ppid(&self) -> u32 {
+        read_u32(
+            self.raw(),
+            (self.layers().app.offset as usize).saturating_sub(4),
+        )
+    }
+
+    #[inline]
+    /// Get packet's application layer payload
+    ///
+    /// For SCTP, it's the payload of the DATA chunk application layer points to
+    fn payload(&self) -> &[u8] {
+        let start = self.layers().app.offset as usize;
+        match self.layers().trans.protocol {
+            Protocol::SCTP => {
+                if self.layers().app.protocol != Protocol::APPLICATION {
+                    return &[];
+                }
+                let chunk_start = start.saturating_sub(sctp::DATA_HDR_LEN);
+                let end = chunk_start + read_u16(self.raw(), chunk_start + 2) as usize;
+                &self.raw()[start..end.min(self.raw().len()).max(start)]
+            }
+            _ => &self.raw()[start..],
+        }
     }
 }
 
@@ -417,10 +906,24 @@ pub enum Protocol {
     PPP,
     MPLS,
     PPPOE,
+    /// Cisco HDLC
+    CHDLC,
+    FRELAY,
+    /// Solaris ipnet
+    IPNET,
+    /// OpenBSD pflog
+    PFLOG,
 
     // Tunnel protocols
     GRE,
     L2TP,
+    VXLAN,
+    GTP,
+    /// IPv4/IPv6 encapsulated in IPv4/IPv6, except IPv6 in IPv4
+    IPIP,
+    /// IPv6 encapsulated in IPv4, ip protocol 41
+    IP6IN4,
+    TEREDO,
 
     // Network layer protocols
     IPV4,
@@ -433,6 +936,7 @@ pub enum Protocol {
     IGMP,
     IPX,
     ESP,
+    AH,
     OSPF,
     PIM,
     RIP,
@@ -461,15 +965,19 @@ impl Default for Protocol {
 }
 
 bitflags! {
-    pub struct Tunnel: u8 {
+    pub struct Tunnel: u16 {
         const NONE = 0;
-        const GRE = 0b00000001;
-        const PPPOE = 0b00000010;
-        const MPLS = 0b00000100;
-        const PPP = 0b00001000;
-        const GTP = 0b00010000;
-        const VXLAN = 0b00100000;
-        const L2TP = 0b01000000;
+        const GRE = 0b00000000_00000001;
+        const PPPOE = 0b00000000_00000010;
+        const MPLS = 0b00000000_00000100;
+        const PPP = 0b00000000_00001000;
+        const GTP = 0b00000000_00010000;
+        const VXLAN = 0b00000000_00100000;
+        const L2TP = 0b00000000_01000000;
+        const IPIP = 0b00000000_10000000;
+        const IP6IN4 = 0b00000001_00000000;
+        const TEREDO = 0b00000010_00000000;
+        const WIREGUARD = 0b00000100_00000000;
     }
 }
 
@@ -506,6 +1014,18 @@ impl Serialize for Tunnel {
         if self.contains(Tunnel::VXLAN) {
             s.serialize_element("vxlan")?;
         }
+        if self.contains(Tunnel::IPIP) {
+            s.serialize_element("ipip")?;
+        }
+        if self.contains(Tunnel::IP6IN4) {
+            s.serialize_element("6in4")?;
+        }
+        if self.contains(Tunnel::TEREDO) {
+            s.serialize_element("teredo")?;
+        }
+        if self.contains(Tunnel::WIREGUARD) {
+            s.serialize_element("wireguard")?;
+        }
         s.end()
     }
 }
@@ -535,6 +1055,58 @@ pub mod test {
         unsafe { assert_eq!(pkt.dst_port(), 5353) };
     }
 
+    #[test]
+    fn test_ip_protocol() {
+        let mut pkt = Packet::default();
+        let mut raw = vec![0x60, 0x00, 0x00, 0x00, 0x00, 0x24, 0x00, 0x40]; // ipv6, hop-by-hop
+        raw.extend_from_slice(&[0; 32]);
+        raw.extend_from_slice(&[0x2c, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00]); // hop-by-hop
+        raw.extend_from_slice(&[0x06, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01]); // fragment
+        raw.extend_from_slice(&[0x00, 0x50, 0xc3, 0x50]); // tcp
+        pkt.raw = Box::new(raw);
+        pkt.layers_mut().network = Layer {
+            protocol: Protocol::IPV6,
+            offset: 0,
+        };
+        assert_eq!(pkt.ip_protocol(), 6);
+        assert_eq!(ip_payload(pkt.raw(), &pkt.layers().network), Some((6, 56)));
+
+        // truncated extension header chain
+        pkt.raw.truncate(48);
+        assert_eq!(pkt.ip_protocol(), 0);
+
+        pkt.raw[6] = 17;
+        assert_eq!(pkt.ip_protocol(), 17);
+    }
+
+    #[test]
+    fn test_tcp_header() {
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![
+            0xe2, 0xb2, 0x01, 0xbb, 0x2b, 0xd5, 0x16, 0xf7, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02,
+            0xfa, 0xf0, 0x8a, 0xcf, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a,
+            0x00, 0x9c, 0x2f, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+        ]);
+        pkt.layers_mut().trans = Layer {
+            protocol: Protocol::TCP,
+            offset: 0,
+        };
+        assert_eq!(pkt.tcp_hdr_len(), 40);
+        assert_eq!(pkt.tcp_flags(), tcp::Flags::SYN);
+        assert_eq!(pkt.tcp_seq(), 0x2bd516f7);
+        assert_eq!(pkt.tcp_ack(), 0);
+        assert_eq!(pkt.tcp_window(), 64240);
+        let opts = pkt.tcp_options();
+        assert_eq!(opts.mss, Some(1460));
+        assert_eq!(opts.window_scale, Some(7));
+        assert!(opts.sack_permitted);
+        assert_eq!(opts.timestamp.unwrap().value, 0x009c2f0e);
+
+        // options exceed captured data
+        pkt.raw.truncate(30);
+        assert_eq!(pkt.tcp_options(), tcp::Options::default());
+    }
+
     #[test]
     fn test_src_ipv4() {
         let mut pkt = Packet::default();
@@ -583,6 +1155,191 @@ pub mod test {
         unsafe { assert_eq!(pkt.dst_ipv6().to_be(), 0xff0200000000000000000000000000fb) };
     }
 
+    fn gre_pkt(outer_dst: u8, key: u8) -> Packet {
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![
+            0x45, 0x00, 0x00, 0x40, 0x00, 0x00, 0x40, 0x00, 0x40, 0x2f, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, outer_dst, // outer ipv4
+            0x20, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, key, // gre
+            0x45, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x01, 0x0a, 0x00, 0x00, 0x02, // inner ipv4
+            0xe2, 0xb2, 0x01, 0xbb, // tcp ports
+        ]);
+        pkt.layers.outer_network = Layer {
+            offset: 0,
+            protocol: Protocol::IPV4,
+        };
+        pkt.layers.tunnel = Layer {
+            offset: 20,
+            protocol: Protocol::GRE,
+        };
+        pkt.layers.network = Layer {
+            offset: 28,
+            protocol: Protocol::IPV4,
+        };
+        pkt.layers.trans = Layer {
+            offset: 48,
+            protocol: Protocol::TCP,
+        };
+        pkt.tunnel = Tunnel::GRE;
+        pkt
+    }
+
+    #[test]
+    fn tunnel_id() {
+        let pkt = gre_pkt(2, 42);
+        assert_eq!(pkt.tunnel_id(), 42);
+
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![0x00, 0x01, 0xd1, 0xff]);
+        pkt.layers.tunnel.protocol = Protocol::MPLS;
+        assert_eq!(pkt.tunnel_id(), 29);
+    }
+
+    #[test]
+    fn tunnel_hash_mode() {
+        let pkt_a = gre_pkt(2, 1);
+        let pkt_b = gre_pkt(3, 1);
+        let pkt_c = gre_pkt(2, 2);
+
+        let mut modes = TunnelHashModes::default();
+        let key_a = PacketHashKey::new(&pkt_a, &modes);
+        assert_eq!(key_a.trans_proto, Protocol::TCP);
+        assert_eq!(key_a.src_port, 58034);
+        assert_eq!(key_a.dst_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(key_a, PacketHashKey::new(&pkt_b, &modes));
+
+        modes.gre = TunnelHashMode::Outer;
+        let key_a = PacketHashKey::new(&pkt_a, &modes);
+        assert_eq!(key_a.network_proto, Protocol::IPV4);
+        assert_eq!(key_a.trans_proto, Protocol::UNKNOWN);
+        assert_eq!(key_a.src_ip, IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)));
+        assert_ne!(key_a, PacketHashKey::new(&pkt_b, &modes));
+        assert_eq!(key_a, PacketHashKey::new(&pkt_c, &modes));
+
+        modes.gre = TunnelHashMode::Both;
+        let key_a = PacketHashKey::new(&pkt_a, &modes);
+        assert_eq!(key_a.tunnel_proto, Protocol::GRE);
+        assert_eq!(key_a.tunnel_id, 1);
+        assert_ne!(key_a, PacketHashKey::new(&pkt_b, &modes));
+        assert_ne!(key_a, PacketHashKey::new(&pkt_c, &modes));
+    }
+
+    #[test]
+    fn wireguard_hash_key() {
+        let wg_pkt = |src_port: u8| {
+            let mut pkt = Packet::default();
+            pkt.raw = Box::new(vec![
+                0x45, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+                0x00, 0x01, 0xc0, 0xa8, 0x00, 0x02, // ipv4
+                0xca, src_port, 0xca, 0x6c, 0x00, 0x28, 0x00, 0x00, // udp
+                0x04, 0x00, 0x00, 0x00, // wireguard transport data
+            ]);
+            pkt.layers.outer_network = Layer {
+                offset: 0,
+                protocol: Protocol::IPV4,
+            };
+            pkt.layers.outer_trans = Layer {
+                offset: 20,
+                protocol: Protocol::UDP,
+            };
+            pkt.layers.tunnel = Layer {
+                offset: 28,
+                protocol: Protocol::WIREGUARD,
+            };
+            pkt.tunnel = Tunnel::WIREGUARD;
+            pkt
+        };
+
+        // no inner layers, keyed by outer tuple whatever the hash mode is
+        let modes = TunnelHashModes::default();
+        let key = PacketHashKey::new(&wg_pkt(0x6c), &modes);
+        assert_eq!(key.network_proto, Protocol::IPV4);
+        assert_eq!(key.trans_proto, Protocol::UDP);
+        assert_eq!(key.src_port, 51820);
+        assert_ne!(key, PacketHashKey::new(&wg_pkt(0x6d), &modes));
+    }
+
+    #[test]
+    fn pppoe_control_hash_key() {
+        let lcp_pkt = |src_mac: u8, dst_mac: u8, session_id: u8| {
+            let mut pkt = Packet::default();
+            pkt.raw = Box::new(vec![
+                0x00, 0x00, 0x00, 0x00, 0x00, dst_mac, 0x00, 0x00, 0x00, 0x00, 0x00, src_mac, 0x88,
+                0x64, // ethernet
+                0x11, 0x00, 0x00, session_id, 0x00, 0x06, 0xc0, 0x21, // pppoe & ppp
+                0x09, 0x01, 0x00, 0x04, // LCP Echo-Request
+            ]);
+            pkt.layers.data_link = Layer {
+                offset: 0,
+                protocol: Protocol::ETHERNET,
+            };
+            let layer = Layer {
+                offset: 14,
+                protocol: Protocol::PPPOE,
+            };
+            pkt.layers.tunnel = layer;
+            pkt.layers.pppoe = layer;
+            pkt.tunnel = Tunnel::PPPOE;
+            pkt
+        };
+
+        let modes = TunnelHashModes::default();
+        let key = PacketHashKey::new(&lcp_pkt(1, 2, 1), &modes);
+        assert_eq!(key.tunnel_proto, Protocol::PPPOE);
+        assert_eq!(key.tunnel_id, 1);
+        assert_eq!(key.src_mac, [0, 0, 0, 0, 0, 2]);
+        assert_eq!(key, PacketHashKey::new(&lcp_pkt(2, 1, 1), &modes));
+        assert_ne!(key, PacketHashKey::new(&lcp_pkt(1, 2, 2), &modes));
+        // the same session id of another client
+        assert_ne!(key, PacketHashKey::new(&lcp_pkt(3, 2, 1), &modes));
+        assert_eq!(lcp_pkt(1, 2, 2).pppoe_session_id(), Some(2));
+        assert_eq!(lcp_pkt(1, 2, 2).ppp_control().unwrap().0, ppp::LCP);
+    }
+
+    #[test]
+    fn esp_hash_key() {
+        let esp_pkt = |src: u8, dst: u8, spi: u8| {
+            let mut pkt = Packet::default();
+            pkt.raw = Box::new(vec![
+                0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x32, 0x00, 0x00, 0x0a, 0x00,
+                0x00, src, 0x0a, 0x00, 0x00, dst, // ipv4
+                0x00, 0x00, 0x00, spi, 0x00, 0x00, 0x00, 0x01, // esp
+            ]);
+            pkt.layers_mut().network = Layer {
+                protocol: Protocol::IPV4,
+                offset: 0,
+            };
+            pkt.layers_mut().trans = Layer {
+                protocol: Protocol::ESP,
+                offset: 20,
+            };
+            pkt
+        };
+
+        let pkt = esp_pkt(1, 2, 0x10);
+        assert_eq!(
+            pkt.ips(),
+            (
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))
+            )
+        );
+        assert_eq!(pkt.esp_spi(), 0x10);
+
+        let key = PacketHashKey::from(&pkt as &dyn PacketTrait);
+        assert_eq!(key.trans_proto, Protocol::ESP);
+        assert_eq!(key.spi, 0x10);
+        assert_eq!((key.src_port, key.dst_port), (0, 0));
+
+        let pkt_reverse = esp_pkt(2, 1, 0x20);
+        let mut key_reverse = PacketHashKey::from(&pkt_reverse as &dyn PacketTrait);
+        assert_ne!(key, key_reverse);
+        // both directions are keyed by the canonical spi of the SA pair
+        key_reverse.spi = 0x10;
+        assert_eq!(key, key_reverse);
+    }
+
     #[test]
     fn serialize_tunnel() {
         let tunnel = Tunnel::GRE;
@@ -593,5 +1350,11 @@ pub mod test {
             "[\"gre\",\"l2tp\"]",
             serde_json::to_string(&tunnel).unwrap()
         );
+
+        let tunnel = Tunnel::IP6IN4 | Tunnel::TEREDO | Tunnel::WIREGUARD;
+        assert_eq!(
+            "[\"6in4\",\"teredo\",\"wireguard\"]",
+            serde_json::to_string(&tunnel).unwrap()
+        );
     }
 }
@@ -0,0 +1,251 @@
+//! PPP and PPPoE helpers
+
+/// PPPoE header length, version & type, code, session id and payload length
+pub const PPPOE_HDR_LEN: usize = 6;
+/// PPPoE session stage code
+pub const PPPOE_SESSION: u8 = 0x00;
+/// PPPoE Active Discovery Initiation
+pub const PADI: u8 = 0x09;
+/// PPPoE Active Discovery Offer
+pub const PADO: u8 = 0x07;
+/// PPPoE Active Discovery Request
+pub const PADR: u8 = 0x19;
+/// PPPoE Active Discovery Session-confirmation
+pub const PADS: u8 = 0x65;
+/// PPPoE Active Discovery Terminate
+pub const PADT: u8 = 0xa7;
+
+/// Discovery tag header length, tag type and tag length
+pub const TAG_HDR_LEN: usize = 4;
+pub const TAG_END_OF_LIST: u16 = 0x0000;
+pub const TAG_SERVICE_NAME: u16 = 0x0101;
+pub const TAG_AC_NAME: u16 = 0x0102;
+
+// PPP protocols
+pub const IPV4: u16 = 0x0021;
+pub const IPV6: u16 = 0x0057;
+/// Link Control Protocol
+pub const LCP: u16 = 0xc021;
+/// IP Control Protocol
+pub const IPCP: u16 = 0x8021;
+/// IPv6 Control Protocol
+pub const IPV6CP: u16 = 0x8057;
+/// Password Authentication Protocol
+pub const PAP: u16 = 0xc023;
+/// Challenge Handshake Authentication Protocol
+pub const CHAP: u16 = 0xc223;
+
+/// Control packet header length, code, identifier and length
+pub const CONTROL_HDR_LEN: usize = 4;
+
+// LCP/IPCP codes
+pub const CONFIGURE_REQUEST: u8 = 1;
+pub const CONFIGURE_ACK: u8 = 2;
+pub const CONFIGURE_NAK: u8 = 3;
+pub const CONFIGURE_REJECT: u8 = 4;
+pub const TERMINATE_REQUEST: u8 = 5;
+pub const TERMINATE_ACK: u8 = 6;
+
+// PAP codes
+pub const PAP_REQUEST: u8 = 1;
+pub const PAP_ACK: u8 = 2;
+pub const PAP_NAK: u8 = 3;
+
+// CHAP codes
+pub const CHAP_CHALLENGE: u8 = 1;
+pub const CHAP_RESPONSE: u8 = 2;
+pub const CHAP_SUCCESS: u8 = 3;
+pub const CHAP_FAILURE: u8 = 4;
+
+// LCP/IPCP configuration options
+pub const LCP_OPT_MRU: u8 = 1;
+pub const LCP_OPT_AUTH_PROTOCOL: u8 = 3;
+pub const IPCP_OPT_IP_ADDRESS: u8 = 3;
+
+/// Whether a PPP protocol is a control or authentication protocol
+#[inline]
+pub fn is_control(protocol: u16) -> bool {
+    matches!(protocol, LCP | IPCP | IPV6CP | PAP | CHAP)
+}
+
+/// Iterator over type-length-value items, PPPoE discovery tags or LCP/IPCP options
+pub struct Tlvs<'a> {
+    buf: &'a [u8],
+    /// Tag header length, 4 for PPPoE discovery tags and 2 for LCP/IPCP options
+    hdr_len: usize,
+}
+
+impl<'a> Tlvs<'a> {
+    /// Create an iterator over PPPoE discovery tags
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - PPPoE payload, right after the PPPoE header
+    pub fn tags(buf: &'a [u8]) -> Self {
+        Tlvs {
+            buf,
+            hdr_len: TAG_HDR_LEN,
+        }
+    }
+
+    /// Create an iterator over LCP/IPCP configuration options
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - Control packet data, right after the control packet header
+    pub fn options(buf: &'a [u8]) -> Self {
+        Tlvs { buf, hdr_len: 2 }
+    }
+}
+
+impl<'a> Iterator for Tlvs<'a> {
+    /// Item type and value
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < self.hdr_len {
+            return None;
+        }
+
+        let (tlv_type, value_len) = if self.hdr_len == TAG_HDR_LEN {
+            (
+                (self.buf[0] as u16) << 8 | self.buf[1] as u16,
+                ((self.buf[2] as u16) << 8 | self.buf[3] as u16) as usize,
+            )
+        } else {
+            // option length includes the option header
+            match (self.buf[1] as usize).checked_sub(2) {
+                Some(len) => (self.buf[0] as u16, len),
+                None => return None,
+            }
+        };
+
+        let value = self.buf.get(self.hdr_len..self.hdr_len + value_len)?;
+        self.buf = &self.buf[self.hdr_len + value_len..];
+        if self.hdr_len == TAG_HDR_LEN && tlv_type == TAG_END_OF_LIST {
+            self.buf = &[];
+            return None;
+        }
+        Some((tlv_type, value))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A LCP/IPCP/PAP/CHAP packet
+pub struct ControlPacket<'a> {
+    pub code: u8,
+    pub identifier: u8,
+    /// Data after the control packet header, excluding padding
+    pub data: &'a [u8],
+}
+
+impl<'a> ControlPacket<'a> {
+    /// Parse a control packet, returns None if it's truncated
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - PPP payload, right after the PPP protocol field
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < CONTROL_HDR_LEN {
+            return None;
+        }
+        let len = ((buf[2] as u16) << 8 | buf[3] as u16) as usize;
+        if len < CONTROL_HDR_LEN {
+            return None;
+        }
+        Some(ControlPacket {
+            code: buf[0],
+            identifier: buf[1],
+            data: buf.get(CONTROL_HDR_LEN..len)?,
+        })
+    }
+
+    /// Get LCP/IPCP configuration options
+    #[inline]
+    pub fn options(&self) -> Tlvs<'a> {
+        Tlvs::options(self.data)
+    }
+
+    /// Get peer id of a PAP Authenticate-Request
+    pub fn pap_peer_id(&self) -> Option<&'a [u8]> {
+        if self.code != PAP_REQUEST {
+            return None;
+        }
+        let len = *self.data.first()? as usize;
+        self.data.get(1..1 + len)
+    }
+
+    /// Get name of a CHAP Response
+    pub fn chap_name(&self) -> Option<&'a [u8]> {
+        if self.code != CHAP_RESPONSE {
+            return None;
+        }
+        let value_len = *self.data.first()? as usize;
+        self.data.get(1 + value_len..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_tags() {
+        let buf = [
+            0x01, 0x01, 0x00, 0x00, // service name, any service
+            0x01, 0x02, 0x00, 0x04, b'b', b'r', b'a', b's', // ac name
+            0x00, 0x00, 0x00, 0x00, // end of list
+            0x01, 0x03, 0x00, 0x00, // host uniq after end of list
+        ];
+        let tags: Vec<(u16, &[u8])> = Tlvs::tags(&buf).collect();
+        assert_eq!(
+            tags,
+            vec![(TAG_SERVICE_NAME, &b""[..]), (TAG_AC_NAME, &b"bras"[..])]
+        );
+
+        // truncated tag
+        let buf = [0x01, 0x02, 0x00, 0x08, b'b', b'r'];
+        assert_eq!(Tlvs::tags(&buf).count(), 0);
+    }
+
+    #[test]
+    fn lcp_options() {
+        let buf = [
+            0x01, 0x01, 0x00, 0x0c, // Configure-Request
+            0x01, 0x04, 0x05, 0xd4, // mru 1492
+            0x03, 0x04, 0xc0, 0x23, // pap
+            0x00, 0x00, // padding
+        ];
+        let pkt = ControlPacket::parse(&buf).unwrap();
+        assert_eq!(pkt.code, CONFIGURE_REQUEST);
+        assert_eq!(pkt.data.len(), 8);
+        let opts: Vec<(u16, &[u8])> = pkt.options().collect();
+        assert_eq!(
+            opts,
+            vec![
+                (LCP_OPT_MRU as u16, &[0x05, 0xd4][..]),
+                (LCP_OPT_AUTH_PROTOCOL as u16, &[0xc0, 0x23][..])
+            ]
+        );
+
+        let buf = [0x01, 0x01, 0x00, 0x0c, 0x01, 0x04, 0x05, 0xd4];
+        assert!(ControlPacket::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn auth() {
+        let buf = [
+            0x01, 0x01, 0x00, 0x0e, 0x04, b'u', b's', b'e', b'r', 0x04, b'p', b'a', b's', b's',
+        ];
+        let pkt = ControlPacket::parse(&buf).unwrap();
+        assert_eq!(pkt.pap_peer_id(), Some(&b"user"[..]));
+        assert_eq!(pkt.chap_name(), None);
+
+        let buf = [
+            0x02, 0x01, 0x00, 0x0b, 0x02, 0xaa, 0xbb, b'u', b's', b'e', b'r',
+        ];
+        let pkt = ControlPacket::parse(&buf).unwrap();
+        assert_eq!(pkt.chap_name(), Some(&b"user"[..]));
+        assert_eq!(pkt.pap_peer_id(), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! SCTP chunk helpers
+
+/// Payload data chunk
+pub const DATA: u8 = 0;
+/// Initiation chunk
+pub const INIT: u8 = 1;
+/// Initiation acknowledgement chunk
+pub const INIT_ACK: u8 = 2;
+/// Selective acknowledgement chunk
+pub const SACK: u8 = 3;
+/// Abort chunk
+pub const ABORT: u8 = 6;
+/// Shutdown chunk
+pub const SHUTDOWN: u8 = 7;
+
+/// SCTP common header length
+pub const COMMON_HDR_LEN: usize = 12;
+/// Chunk header length, chunk type, flags and length
+pub const CHUNK_HDR_LEN: usize = 4;
+/// DATA chunk header length, including TSN, stream id, stream sequence number and ppid
+pub const DATA_HDR_LEN: usize = 16;
+/// Minimal INIT/INIT ACK chunk length
+pub const INIT_LEN: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A single SCTP chunk
+pub struct Chunk {
+    /// Chunk start offset, relative to the start of the packet
+    pub offset: u16,
+    pub chunk_type: u8,
+    pub flags: u8,
+    /// Chunk length, excluding padding
+    pub len: u16,
+}
+
+impl Chunk {
+    /// Application payload offset and length, only available for DATA chunks
+    #[inline]
+    pub fn data(&self) -> Option<(u16, u16)> {
+        if self.chunk_type != DATA || (self.len as usize) < DATA_HDR_LEN {
+            return None;
+        }
+        Some((
+            self.offset + DATA_HDR_LEN as u16,
+            self.len - DATA_HDR_LEN as u16,
+        ))
+    }
+}
+
+/// Iterator over chunks of a SCTP packet
+///
+/// Iteration stops at the first chunk shorter than a chunk header or exceeding the packet,
+/// so trailing link layer padding is ignored
+pub struct Chunks<'a> {
+    raw: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Chunks<'a> {
+    /// Create a chunk iterator
+    ///
+    /// # Arguments
+    ///
+    /// * `raw` - Raw packet data
+    ///
+    /// * `offset` - Start offset of the SCTP common header
+    pub fn new(raw: &'a [u8], offset: u16) -> Self {
+        Chunks {
+            raw,
+            offset: offset as usize + COMMON_HDR_LEN,
+        }
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset + CHUNK_HDR_LEN > self.raw.len() {
+            return None;
+        }
+
+        let buf = &self.raw[self.offset..];
+        let len = (buf[2] as u16) << 8 | buf[3] as u16;
+        if (len as usize) < CHUNK_HDR_LEN || len as usize > buf.len() {
+            return None;
+        }
+
+        let chunk = Chunk {
+            offset: self.offset as u16,
+            chunk_type: buf[0],
+            flags: buf[1],
+            len,
+        };
+        // chunks are padded to 4 bytes
+        self.offset += (len as usize + 3) & !3;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x0e, 0x50, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x00, 0x03, 0x00, 0x13, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x2e, 0x01, 0x02, 0x03, 0x00, // DATA with 3 bytes payload & padding
+            0x03, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00,
+            0x00, 0x00, // SACK
+            0x00, 0x00, 0x00, 0x00, // link layer padding
+        ];
+        let chunks: Vec<Chunk> = Chunks::new(&buf, 0).collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type, DATA);
+        assert_eq!(chunks[0].offset, 12);
+        assert_eq!(chunks[0].data(), Some((28, 3)));
+        assert_eq!(chunks[1].chunk_type, SACK);
+        assert_eq!(chunks[1].offset, 32);
+        assert_eq!(chunks[1].data(), None);
+    }
+
+    #[test]
+    fn chunk_exceeds_packet() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x0e, 0x50, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01,
+        ];
+        assert_eq!(Chunks::new(&buf, 0).count(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! TCP header helpers
+
+/// Minimal TCP header length
+pub const MIN_HDR_LEN: usize = 20;
+
+const OPT_EOL: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+const OPT_WINDOW_SCALE: u8 = 3;
+const OPT_SACK_PERMITTED: u8 = 4;
+const OPT_TIMESTAMP: u8 = 8;
+
+bitflags! {
+    /// TCP control flags
+    pub struct Flags: u8 {
+        const FIN = 0b00000001;
+        const SYN = 0b00000010;
+        const RST = 0b00000100;
+        const PSH = 0b00001000;
+        const ACK = 0b00010000;
+        const URG = 0b00100000;
+        const ECE = 0b01000000;
+        const CWR = 0b10000000;
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// TCP timestamp option
+pub struct Timestamp {
+    pub value: u32,
+    pub echo_reply: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Parsed TCP options, unknown options are skipped
+pub struct Options {
+    /// Maximum segment size
+    pub mss: Option<u16>,
+    /// Window scale shift count
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    pub timestamp: Option<Timestamp>,
+}
+
+impl Options {
+    /// Parse TCP options
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - Options part of TCP header, right after the 20 bytes fixed header
+    pub fn parse(buf: &[u8]) -> Self {
+        let mut opts = Options::default();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let kind = buf[pos];
+            match kind {
+                OPT_EOL => break,
+                OPT_NOP => {
+                    pos += 1;
+                    continue;
+                }
+                _ => {}
+            };
+
+            if pos + 1 >= buf.len() {
+                break;
+            }
+            let len = buf[pos + 1] as usize;
+            if len < 2 || pos + len > buf.len() {
+                // malformed option, stop parsing
+                break;
+            }
+
+            let opt = &buf[pos..pos + len];
+            match (kind, len) {
+                (OPT_MSS, 4) => opts.mss = Some((opt[2] as u16) << 8 | opt[3] as u16),
+                (OPT_WINDOW_SCALE, 3) => opts.window_scale = Some(opt[2]),
+                (OPT_SACK_PERMITTED, 2) => opts.sack_permitted = true,
+                (OPT_TIMESTAMP, 10) => {
+                    opts.timestamp = Some(Timestamp {
+                        value: u32::from_be_bytes([opt[2], opt[3], opt[4], opt[5]]),
+                        echo_reply: u32::from_be_bytes([opt[6], opt[7], opt[8], opt[9]]),
+                    })
+                }
+                _ => {}
+            };
+            pos += len;
+        }
+
+        opts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syn_options() {
+        let buf = [
+            0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0x00, 0x9c, 0x2f, 0x0e, 0x00, 0x00,
+            0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+        ];
+        let opts = Options::parse(&buf);
+        assert_eq!(opts.mss, Some(1460));
+        assert_eq!(opts.window_scale, Some(7));
+        assert!(opts.sack_permitted);
+        assert_eq!(
+            opts.timestamp,
+            Some(Timestamp {
+                value: 0x009c2f0e,
+                echo_reply: 0
+            })
+        );
+    }
+
+    #[test]
+    fn eol() {
+        let buf = [0x01, 0x01, 0x00, 0x02, 0x04, 0x05, 0xb4];
+        assert_eq!(Options::parse(&buf), Options::default());
+    }
+
+    #[test]
+    fn malformed() {
+        // mss option claims more bytes than available
+        let buf = [0x04, 0x02, 0x02, 0x08, 0x05, 0xb4];
+        let opts = Options::parse(&buf);
+        assert!(opts.sack_permitted);
+        assert_eq!(opts.mss, None);
+
+        // zero length option
+        let buf = [0x03, 0x00, 0x04, 0x02];
+        assert_eq!(Options::parse(&buf), Options::default());
+    }
+}
@@ -3,6 +3,7 @@ use std::ops::{Deref, DerefMut};
 use anyhow::Result;
 
 use crate::classifiers::ClassifierManager;
+use crate::session::field::FieldRegistry;
 use crate::{packet, session};
 
 pub type ParserID = u8;
@@ -18,6 +19,16 @@ pub type ParserInitFunc = fn() -> Result<()>;
 // Release parser required global resources
 pub type ParserExitFunc = fn() -> Result<()>;
 
+/// A piece of a reassembled TCP stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamData<'a> {
+    /// Contiguous, in-order stream bytes
+    Data(&'a [u8]),
+    /// Amount of bytes missing from the stream, either not captured or dropped
+    /// because the session ran out of reassembly memory
+    Gap(u32),
+}
+
 pub struct ProtocolParser {
     parser: Box<Box<dyn ProtocolParserTrait>>,
 }
@@ -67,6 +78,11 @@ pub trait ProtocolParserTrait: Send + Sync {
     /// Register protocol classify rules
     fn register_classify_rules(&mut self, manager: &mut ClassifierManager) -> Result<()>;
 
+    /// Declare the custom session fields this parser adds
+    fn register_fields(&self, _registry: &mut FieldRegistry) -> Result<()> {
+        Ok(())
+    }
+
     /// Parse a single packet and maybe update session information
     fn parse_pkt(
         &mut self,
@@ -83,6 +99,29 @@ pub trait ProtocolParserTrait: Send + Sync {
         Ok(())
     }
 
+    /// Whether this parser wants TCP sessions' in-order stream data through parse_stream
+    fn stream_parser(&self) -> bool {
+        false
+    }
+
+    /// Parse contiguous, in-order TCP stream data of one direction
+    ///
+    /// parse_pkt is still called for each packet, before the packet's stream data is delivered
+    fn parse_stream(
+        &mut self,
+        _data: StreamData,
+        _direction: packet::Direction,
+        _ses: &mut session::Session,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Approximate heap and inline bytes held by this parser's per-session state,
+    /// parsers buffering data should override it
+    fn memory(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
     /// Check whether the session is classfied as this protocol
     fn is_classified(&self) -> bool;
 
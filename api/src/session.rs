@@ -1,12 +1,23 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr};
 use std::os::raw::c_long;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Once;
 
-use serde::ser::SerializeStruct;
+use anyhow::anyhow;
+use serde::ser::{SerializeSeq, SerializeStruct};
 use serde::{Serialize, Serializer};
 
 use crate::packet;
+use crate::packet::{ip_payload, ppp, read_u16, read_u32, sctp, tcp};
+use crate::utils::community_id::community_id;
 use crate::utils::timeval::{precision, TimeVal};
 
+pub mod field;
+
+pub use field::Fields;
+
 #[allow(dead_code)]
 fn packets_serialize<S>(packets: &[u32; 2], s: S) -> Result<S::Ok, S::Error>
 where
@@ -40,6 +51,444 @@ where
     state.end()
 }
 
+#[allow(dead_code)]
+fn src_geo_serialize<S>(geo: &Option<Box<Geo>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    geo_serialize(geo, ["srcGEO", "srcGEOCity", "srcASN"], s)
+}
+
+#[allow(dead_code)]
+fn dst_geo_serialize<S>(geo: &Option<Box<Geo>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    geo_serialize(geo, ["dstGEO", "dstGEOCity", "dstASN"], s)
+}
+
+/// Serialize geolocation as Arkime's country, city and "AS<number> <organization>" fields
+#[allow(dead_code)]
+fn geo_serialize<S>(
+    geo: &Option<Box<Geo>>,
+    keys: [&'static str; 3],
+    s: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let geo = match geo {
+        Some(geo) => geo,
+        None => return s.serialize_struct("", 0)?.end(),
+    };
+    let mut state = s.serialize_struct("", 3)?;
+    if let Some(country) = &geo.country {
+        state.serialize_field(keys[0], country)?;
+    }
+    if let Some(city) = &geo.city {
+        state.serialize_field(keys[1], city)?;
+    }
+    if let Some(asn) = geo.asn {
+        let asn = match &geo.org {
+            Some(org) => format!("AS{} {}", asn, org),
+            None => format!("AS{}", asn),
+        };
+        state.serialize_field(keys[2], &asn)?;
+    }
+    state.end()
+}
+
+fn macs_serialize<S>(macs: &[[u8; 6]], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut seq = s.serialize_seq(Some(macs.len()))?;
+    for mac in macs {
+        seq.serialize_element(&format!(
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        ))?;
+    }
+    seq.end()
+}
+
+#[inline]
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Base64 alphabet in ascii order, so encoded ids sort the same as their bytes
+const ID_ALPHABET: &[u8; 64] = b"-0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+/// IP protocol numbers
+mod ip_proto {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+    pub const ICMPV6: u8 = 58;
+    pub const SCTP: u8 = 132;
+}
+
+/// Sequence number of generated session ids
+static ID_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+/// Node component of generated session ids, see id_node
+static ID_NODE: AtomicU32 = AtomicU32::new(0);
+static ID_NODE_INIT: Once = Once::new();
+
+/// 24 bits hashed from the host name and process id, so capture processes
+/// sharing an index don't generate the same ids
+fn id_node() -> u32 {
+    ID_NODE_INIT.call_once(|| {
+        use std::hash::{Hash, Hasher};
+
+        let mut name = [0u8; 256];
+        let ret = unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) };
+        let len = match ret {
+            0 => name.iter().position(|b| *b == 0).unwrap_or(name.len()),
+            _ => 0,
+        };
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        name[..len].hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+        ID_NODE.store(hasher.finish() as u32 & 0xffffff, Ordering::Relaxed);
+    });
+    ID_NODE.load(Ordering::Relaxed)
+}
+
+/// SCTP association information
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "arkime", serde(rename_all = "camelCase"))]
+pub struct Sctp {
+    /// Verification tag carried by packets of each direction
+    pub verification_tags: [u32; 2],
+    /// Number of outbound streams announced by INIT/INIT ACK of each direction
+    pub outbound_streams: [u16; 2],
+    /// Number of inbound streams announced by INIT/INIT ACK of each direction
+    pub inbound_streams: [u16; 2],
+    /// Streams which have carried DATA chunks
+    pub data_streams: BTreeSet<u16>,
+    /// Total DATA chunks
+    pub data_chunks: u32,
+    /// Whether a SHUTDOWN chunk is seen
+    pub shutdown: bool,
+    /// Whether an ABORT chunk is seen
+    pub aborted: bool,
+}
+
+/// TCP connection state
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TcpState {
+    /// No packet is seen yet
+    New,
+    /// SYN is seen
+    SynSent,
+    /// SYN-ACK is seen
+    SynReceived,
+    /// Handshake is done, or the connection is picked up midstream
+    Established,
+    /// FIN is seen from one side
+    Closing,
+    /// FIN is seen from both sides
+    Closed,
+    /// RST is seen
+    Reset,
+}
+
+impl Default for TcpState {
+    fn default() -> Self {
+        TcpState::New
+    }
+}
+
+impl TryFrom<&str> for TcpState {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "new" => Ok(TcpState::New),
+            "syn_sent" => Ok(TcpState::SynSent),
+            "syn_received" => Ok(TcpState::SynReceived),
+            "established" => Ok(TcpState::Established),
+            "closing" => Ok(TcpState::Closing),
+            "closed" => Ok(TcpState::Closed),
+            "reset" => Ok(TcpState::Reset),
+            _ => Err(anyhow!("Unknown TCP state: {}", value)),
+        }
+    }
+}
+
+/// Why a TCP connection ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloseReason {
+    /// Closed by FIN from both sides
+    Fin,
+    /// Reset by either side
+    Rst,
+    /// Idle until the session times out
+    Timeout,
+    /// Evicted because the session table is full
+    Evicted,
+}
+
+/// TCP connection information
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "arkime", serde(rename_all = "camelCase"))]
+pub struct Tcp {
+    pub state: TcpState,
+    /// Whether SYN, SYN-ACK and ACK of the three-way handshake are all seen
+    pub handshake_complete: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<CloseReason>,
+    /// Whether SYN is seen
+    #[serde(skip_serializing)]
+    pub syn: bool,
+    /// Direction of the SYN-ACK
+    #[serde(skip_serializing)]
+    pub responder: Option<packet::Direction>,
+    /// Whether FIN is seen from each direction
+    #[serde(skip_serializing)]
+    pub fin: [bool; 2],
+}
+
+impl Tcp {
+    /// Whether the connection is closed by FIN from both sides or reset
+    #[inline]
+    pub fn closed(&self) -> bool {
+        matches!(self.state, TcpState::Closed | TcpState::Reset)
+    }
+}
+
+/// Running min/max/mean/standard deviation of a series of values
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunningStat {
+    count: u64,
+    min: u64,
+    max: u64,
+    mean: f64,
+    /// Sum of squared differences from the mean, by Welford's algorithm
+    m2: f64,
+}
+
+impl RunningStat {
+    pub fn add(&mut self, value: u64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        let delta = value as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value as f64 - self.mean);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[inline]
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    #[inline]
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    #[inline]
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population standard deviation
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.m2 / self.count as f64).sqrt()
+    }
+}
+
+impl Serialize for RunningStat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("RunningStat", 4)?;
+        state.serialize_field("min", &self.min)?;
+        state.serialize_field("max", &self.max)?;
+        state.serialize_field("mean", &self.mean)?;
+        state.serialize_field("stddev", &self.stddev())?;
+        state.end()
+    }
+}
+
+/// Amount of packets carrying each TCP flag
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TcpFlagCounts {
+    pub syn: u32,
+    pub syn_ack: u32,
+    pub ack: u32,
+    pub psh: u32,
+    pub fin: u32,
+    pub rst: u32,
+    pub urg: u32,
+    pub ece: u32,
+    pub cwr: u32,
+}
+
+impl TcpFlagCounts {
+    fn add(&mut self, flags: tcp::Flags) {
+        let syn = flags.contains(tcp::Flags::SYN);
+        let ack = flags.contains(tcp::Flags::ACK);
+        match (syn, ack) {
+            (true, true) => self.syn_ack += 1,
+            (true, false) => self.syn += 1,
+            (false, true) => self.ack += 1,
+            _ => {}
+        };
+        let count = |flag, cnt: &mut u32| {
+            if flags.contains(flag) {
+                *cnt += 1;
+            }
+        };
+        count(tcp::Flags::PSH, &mut self.psh);
+        count(tcp::Flags::FIN, &mut self.fin);
+        count(tcp::Flags::RST, &mut self.rst);
+        count(tcp::Flags::URG, &mut self.urg);
+        count(tcp::Flags::ECE, &mut self.ece);
+        count(tcp::Flags::CWR, &mut self.cwr);
+    }
+}
+
+/// Per-session flow feature statistics, only collected if enabled
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "arkime", serde(rename_all = "camelCase"))]
+pub struct FlowStats {
+    /// Captured packet size of each direction
+    pub pkt_size: [RunningStat; 2],
+    /// Packet inter-arrival time of each direction in microseconds
+    pub iat: [RunningStat; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_flags: Option<TcpFlagCounts>,
+    /// Sizes of the first packets, negative for the responder's
+    pub first_pkts: Vec<i32>,
+    /// Microseconds from SYN to the ACK completing the handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_rtt: Option<u64>,
+    /// Max amount of first_pkts
+    #[serde(skip_serializing)]
+    max_first_pkts: usize,
+    /// Timestamp of the last packet of each direction in microseconds
+    #[serde(skip_serializing)]
+    last_ts: [Option<u64>; 2],
+    /// Timestamp of the SYN in microseconds
+    #[serde(skip_serializing)]
+    syn_ts: Option<u64>,
+}
+
+impl FlowStats {
+    /// # Arguments
+    ///
+    /// * `max_first_pkts` - Amount of the first packets whose size is recorded
+    pub fn new(max_first_pkts: usize) -> Self {
+        FlowStats {
+            max_first_pkts,
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, pkt: &dyn packet::Packet, dir: packet::Direction, tcp: Option<&Tcp>) {
+        let i = dir as usize;
+        let ts = pkt.ts().tv_sec as u64 * 1000000 + pkt.ts().tv_usec as u64;
+        let size = pkt.caplen();
+
+        self.pkt_size[i].add(size as u64);
+        if let Some(last) = self.last_ts[i] {
+            self.iat[i].add(ts.saturating_sub(last));
+        }
+        self.last_ts[i] = Some(ts);
+
+        if self.first_pkts.len() < self.max_first_pkts {
+            match dir {
+                packet::Direction::LEFT => self.first_pkts.push(size as i32),
+                packet::Direction::RIGHT => self.first_pkts.push(-(size as i32)),
+            };
+        }
+
+        if let Some(tcp) = tcp {
+            let flags = pkt.tcp_flags();
+            self.tcp_flags
+                .get_or_insert_with(Default::default)
+                .add(flags);
+            if flags & (tcp::Flags::SYN | tcp::Flags::ACK) == tcp::Flags::SYN {
+                self.syn_ts.get_or_insert(ts);
+            }
+            if tcp.handshake_complete && self.handshake_rtt.is_none() {
+                self.handshake_rtt = self.syn_ts.map(|syn| ts.saturating_sub(syn));
+            }
+        }
+    }
+
+    /// Start over for a new session segment, keeping the inter-arrival time continuous
+    fn reset(&mut self) {
+        *self = FlowStats {
+            max_first_pkts: self.max_first_pkts,
+            last_ts: self.last_ts,
+            ..Default::default()
+        };
+    }
+}
+
+/// Geolocation and autonomous system of an ip address
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Geo {
+    /// ISO 3166-1 country code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// City name in English
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// Autonomous system number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// Autonomous system organization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+}
+
+/// PPPoE session information
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "arkime", serde(rename_all = "camelCase"))]
+pub struct Pppoe {
+    /// PPPoE session id, 0 until it's assigned by PADS
+    pub session_id: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    /// Access concentrator name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ac_name: Option<String>,
+    /// Authentication protocol negotiated by LCP, pap or chap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_protocol: Option<String>,
+    /// Subscriber user name of PAP/CHAP authentication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Whether PAP/CHAP authentication succeeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticated: Option<bool>,
+    /// IPv4 address negotiated by IPCP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Ipv4Addr>,
+    /// Whether a PADT or LCP Terminate-Request is seen
+    pub terminated: bool,
+}
+
 /// Network session
 #[derive(Clone, Default, Serialize)]
 #[cfg_attr(feature = "arkime", serde(rename_all = "camelCase"))]
@@ -47,11 +496,70 @@ pub struct Session {
     #[serde(skip_serializing_if = "String::is_empty")]
     pub id: Box<String>,
 
+    /// Id of the first segment, only set if this session is mid-saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_id: Option<Box<String>>,
+
+    /// Segment number of a mid-saved session, starts from 1
+    #[serde(skip_serializing_if = "is_zero")]
+    pub segment_cnt: u32,
+
     /// Some session only contains one direction's packets
     /// Some protocols may work in that way
     /// but network problems could cause single direction
     pub single_direction: bool,
 
+    /// Initiator ip address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_ip: Option<IpAddr>,
+
+    /// Responder ip address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_ip: Option<IpAddr>,
+
+    /// Initiator port, 0 if transport layer has no port
+    pub src_port: u16,
+
+    /// Responder port, 0 if transport layer has no port
+    pub dst_port: u16,
+
+    /// Initiator mac addresses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(serialize_with = "macs_serialize")]
+    pub src_mac: Vec<[u8; 6]>,
+
+    /// Responder mac addresses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(serialize_with = "macs_serialize")]
+    pub dst_mac: Vec<[u8; 6]>,
+
+    /// Initiator geolocation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arkime", serde(flatten))]
+    #[cfg_attr(feature = "arkime", serde(serialize_with = "src_geo_serialize"))]
+    pub src_geo: Option<Box<Geo>>,
+
+    /// Responder geolocation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arkime", serde(flatten))]
+    #[cfg_attr(feature = "arkime", serde(serialize_with = "dst_geo_serialize"))]
+    pub dst_geo: Option<Box<Geo>>,
+
+    /// Labels of the configured CIDR ranges containing the initiator ip address
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub src_labels: Vec<String>,
+
+    /// Labels of the configured CIDR ranges containing the responder ip address
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dst_labels: Vec<String>,
+
+    /// Community ID flow hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub community_id: Option<String>,
+
+    /// IP protocol number
+    pub ip_protocol: u8,
+
     /// session total packets
     #[cfg_attr(feature = "arkime", serde(flatten))]
     #[cfg_attr(feature = "arkime", serde(serialize_with = "packets_serialize"))]
@@ -84,7 +592,7 @@ pub struct Session {
     pub parse_finished: bool,
     /// custom fields
     #[serde(flatten)]
-    pub fields: Box<serde_json::Value>,
+    pub fields: Fields,
 
     /// Tags
     tags: Box<HashSet<String>>,
@@ -95,31 +603,362 @@ pub struct Session {
     /// Tunnel Protocols
     #[serde(skip_serializing_if = "packet::Tunnel::is_empty")]
     tunnels: packet::Tunnel,
+
+    /// SCTP association information
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sctp: Option<Box<Sctp>>,
+
+    /// Distinct MPLS label stacks seen in this session, each from top to bottom
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mpls_labels: Vec<Vec<u32>>,
+
+    /// ESP security parameter indexes seen in this session
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub esp_spis: Vec<u32>,
+
+    /// PPPoE session information
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pppoe: Option<Box<Pppoe>>,
+
+    /// TCP connection information
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<Box<Tcp>>,
+
+    /// Ids of the pcap files holding this session's packets
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub file_id: Vec<u32>,
+
+    /// Byte offsets of this session's packets in pcap files,
+    /// offsets of each file are led by the negated file id
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packet_pos: Vec<i64>,
+
+    /// Flow feature statistics, collected once it's set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Box<FlowStats>>,
 }
 
 impl Session {
     pub fn new() -> Session {
-        let mut ses = Session::default();
-        ses.fields = Box::new(serde_json::json!({}));
-        ses
+        Session::default()
+    }
+
+    /// Generate a unique session id, Arkime style
+    ///
+    /// Ids are the UTC date of the session's first packet as yymmdd, a dash, then 20
+    /// base64 characters encoding 7 bytes of the first packet time in microseconds,
+    /// 3 bytes of node id and 5 bytes of process wide sequence number, so ids sort by
+    /// session start time and don't collide across capture processes.
+    pub fn new_id(ts: &libc::timeval) -> String {
+        let micros = ts.tv_sec as u64 * 1000000 + ts.tv_usec as u64;
+        let seq = ID_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let mut bytes = [0u8; 15];
+        bytes[..7].copy_from_slice(&micros.to_be_bytes()[1..]);
+        bytes[7..10].copy_from_slice(&id_node().to_be_bytes()[1..]);
+        bytes[10..].copy_from_slice(&seq.to_be_bytes()[3..]);
+
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_micros(micros);
+        let date = chrono::DateTime::<chrono::Utc>::from(time);
+        let mut id = date.format("%y%m%d-").to_string();
+        for chunk in bytes.chunks(3) {
+            let n = (chunk[0] as u32) << 16 | (chunk[1] as u32) << 8 | chunk[2] as u32;
+            for shift in [18, 12, 6, 0].iter() {
+                id.push(ID_ALPHABET[(n >> shift) as usize & 0x3f] as char);
+            }
+        }
+        id
     }
 
     #[inline]
     /// update session information
     pub fn update(&mut self, pkt: &dyn packet::Packet) {
-        match pkt.direction() {
-            packet::Direction::LEFT => {
-                self.pkt_count[0] += 1;
-                self.bytes[0] += pkt.caplen() as u64;
-                self.data_bytes[0] += pkt.data_len() as u64;
+        if self.src_ip.is_none() {
+            self.set_endpoints(pkt);
+        }
+
+        let dir = self.direction(pkt);
+        let i = dir as usize;
+        self.pkt_count[i] += 1;
+        self.bytes[i] += pkt.caplen() as u64;
+        self.data_bytes[i] += pkt.data_len() as u64;
+        if pkt.layers().data_link.protocol == packet::Protocol::ETHERNET && pkt.raw().len() >= 12 {
+            let (src_mac, dst_mac) = unsafe { (*pkt.src_mac(), *pkt.dst_mac()) };
+            let (src, dst) = match dir {
+                packet::Direction::LEFT => (&mut self.src_mac, &mut self.dst_mac),
+                packet::Direction::RIGHT => (&mut self.dst_mac, &mut self.src_mac),
+            };
+            if !src.contains(&src_mac) {
+                src.push(src_mac);
             }
-            packet::Direction::RIGHT => {
-                self.pkt_count[1] += 1;
-                self.bytes[1] += pkt.caplen() as u64;
-                self.data_bytes[1] += pkt.data_len() as u64;
+            if !dst.contains(&dst_mac) {
+                dst.push(dst_mac);
             }
         }
         self.end_time = TimeVal::new(*pkt.ts());
+        self.tunnels |= pkt.tunnel();
+
+        if pkt.layers().trans.protocol == packet::Protocol::TCP {
+            self.update_tcp(pkt, dir);
+        }
+
+        if pkt.layers().trans.protocol == packet::Protocol::SCTP {
+            self.update_sctp(pkt, dir);
+        }
+
+        if let Some(stats) = self.stats.as_mut() {
+            stats.update(pkt, dir, self.tcp.as_deref());
+        }
+
+        if pkt.layers().trans.protocol == packet::Protocol::ESP {
+            let spi = pkt.esp_spi();
+            if !self.esp_spis.contains(&spi) {
+                self.esp_spis.push(spi);
+                self.add_protocol(&"esp");
+            }
+        }
+
+        if pkt.layers().pppoe.protocol == packet::Protocol::PPPOE {
+            self.update_pppoe(pkt);
+        }
+
+        if pkt.tunnel().contains(packet::Tunnel::MPLS) {
+            let labels = pkt.mpls_labels();
+            if !self.mpls_labels.iter().any(|stack| stack[..] == labels[..]) {
+                self.mpls_labels.push(labels.to_vec());
+            }
+        }
+    }
+
+    /// Record initiator and responder from the session's first IP packet
+    ///
+    /// The sender of the first packet is the initiator, unless it's a SYN-ACK
+    fn set_endpoints(&mut self, pkt: &dyn packet::Packet) {
+        match pkt.layers().network.protocol {
+            packet::Protocol::IPV4 | packet::Protocol::IPV6 => {}
+            _ => return,
+        };
+
+        let (src_ip, dst_ip) = pkt.ips();
+        let (src_port, dst_port) = pkt.ports();
+        let syn_ack = tcp::Flags::SYN | tcp::Flags::ACK;
+        if pkt.layers().trans.protocol == packet::Protocol::TCP && pkt.tcp_flags().contains(syn_ack)
+        {
+            self.src_ip = Some(dst_ip);
+            self.dst_ip = Some(src_ip);
+            self.src_port = dst_port;
+            self.dst_port = src_port;
+        } else {
+            self.src_ip = Some(src_ip);
+            self.dst_ip = Some(dst_ip);
+            self.src_port = src_port;
+            self.dst_port = dst_port;
+        }
+        self.ip_protocol = pkt.ip_protocol();
+    }
+
+    /// Record where a packet of this session is written
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - Id of the pcap file, starts from 1
+    /// * `pos` - Byte offset of the packet's record in the pcap file
+    pub fn add_packet_pos(&mut self, file_id: u32, pos: u64) {
+        if self.file_id.last() != Some(&file_id) {
+            self.file_id.push(file_id);
+            self.packet_pos.push(-(file_id as i64));
+        }
+        self.packet_pos.push(pos as i64);
+    }
+
+    /// Compute Community ID once the session's endpoints are known
+    ///
+    /// ICMP type and code are read from the session's first IP packet
+    pub fn update_community_id(&mut self, pkt: &dyn packet::Packet, seed: u16) {
+        let (src_ip, dst_ip) = match (self.src_ip, self.dst_ip) {
+            (Some(src_ip), Some(dst_ip)) => (src_ip, dst_ip),
+            _ => return,
+        };
+        let ports = match self.ip_protocol {
+            ip_proto::TCP | ip_proto::UDP | ip_proto::SCTP => Some((self.src_port, self.dst_port)),
+            ip_proto::ICMP | ip_proto::ICMPV6 => {
+                // ICMP isn't decoded into the transport layer, locate it after the IP header
+                let pos = match ip_payload(pkt.raw(), &pkt.layers().network) {
+                    Some((_, pos)) => pos,
+                    None => return,
+                };
+                match pkt.raw().get(pos..pos + 2) {
+                    Some(icmp) => Some((icmp[0] as u16, icmp[1] as u16)),
+                    None => return,
+                }
+            }
+            _ => None,
+        };
+        self.community_id = Some(community_id(seed, src_ip, dst_ip, self.ip_protocol, ports));
+    }
+
+    /// Get a packet's direction within this session, packets from the initiator are LEFT
+    ///
+    /// Packets of a non IP session are always LEFT
+    #[inline]
+    pub fn direction(&self, pkt: &dyn packet::Packet) -> packet::Direction {
+        let src_ip = match self.src_ip {
+            Some(ip) => ip,
+            None => return packet::Direction::LEFT,
+        };
+        if pkt.ips().0 == src_ip && pkt.ports().0 == self.src_port {
+            packet::Direction::LEFT
+        } else {
+            packet::Direction::RIGHT
+        }
+    }
+
+    /// update TCP connection state
+    fn update_tcp(&mut self, pkt: &dyn packet::Packet, dir: packet::Direction) {
+        let flags = pkt.tcp_flags();
+        let tcp = self.tcp.get_or_insert_with(Box::default);
+
+        if flags.contains(tcp::Flags::RST) {
+            tcp.state = TcpState::Reset;
+            tcp.close_reason.get_or_insert(CloseReason::Rst);
+            return;
+        }
+
+        let syn = flags.contains(tcp::Flags::SYN);
+        let ack = flags.contains(tcp::Flags::ACK);
+        match (tcp.state, syn, ack) {
+            (TcpState::New, true, false) => {
+                tcp.state = TcpState::SynSent;
+                tcp.syn = true;
+            }
+            (TcpState::New, true, true) | (TcpState::SynSent, true, true) => {
+                tcp.state = TcpState::SynReceived;
+                tcp.responder = Some(dir);
+            }
+            (TcpState::SynReceived, false, true) if tcp.responder != Some(dir) => {
+                tcp.state = TcpState::Established;
+                tcp.handshake_complete = tcp.syn;
+            }
+            // picked up midstream, or SYN-ACK is missing
+            (TcpState::New, false, _) | (TcpState::SynSent, false, true) => {
+                tcp.state = TcpState::Established;
+            }
+            _ => {}
+        };
+
+        if flags.contains(tcp::Flags::FIN) && tcp.state != TcpState::Reset {
+            tcp.fin[dir as usize] = true;
+            if tcp.fin[0] && tcp.fin[1] {
+                tcp.state = TcpState::Closed;
+                tcp.close_reason.get_or_insert(CloseReason::Fin);
+            } else {
+                tcp.state = TcpState::Closing;
+            }
+        }
+    }
+
+    /// Whether this session's TCP connection is closed by FIN from both sides or reset
+    #[inline]
+    pub fn tcp_closed(&self) -> bool {
+        self.tcp.as_ref().map_or(false, |tcp| tcp.closed())
+    }
+
+    /// update SCTP association information
+    fn update_sctp(&mut self, pkt: &dyn packet::Packet, dir: packet::Direction) {
+        let dir = dir as usize;
+        let raw = pkt.raw();
+        let sctp = self.sctp.get_or_insert_with(Box::default);
+
+        let vtag = read_u32(raw, pkt.layers().trans.offset as usize + 4);
+        if vtag != 0 {
+            // INIT chunk carries a zero verification tag
+            sctp.verification_tags[dir] = vtag;
+        }
+
+        for chunk in pkt.sctp_chunks() {
+            let buf = &raw[chunk.offset as usize..(chunk.offset + chunk.len) as usize];
+            match chunk.chunk_type {
+                sctp::INIT | sctp::INIT_ACK if buf.len() >= sctp::INIT_LEN => {
+                    // initiate tag is the verification tag of the peer's packets
+                    sctp.verification_tags[dir ^ 1] = read_u32(buf, 4);
+                    sctp.outbound_streams[dir] = read_u16(buf, 12);
+                    sctp.inbound_streams[dir] = read_u16(buf, 14);
+                }
+                sctp::DATA if buf.len() >= sctp::DATA_HDR_LEN => {
+                    sctp.data_chunks += 1;
+                    sctp.data_streams.insert(read_u16(buf, 8));
+                }
+                sctp::SHUTDOWN => sctp.shutdown = true,
+                sctp::ABORT => sctp.aborted = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// update PPPoE session information
+    fn update_pppoe(&mut self, pkt: &dyn packet::Packet) {
+        let pppoe = self.pppoe.get_or_insert_with(Box::default);
+        match pkt.pppoe_session_id() {
+            Some(id) if id != 0 => pppoe.session_id = id,
+            _ => {}
+        };
+
+        let to_string = |value: &[u8]| String::from_utf8_lossy(value).to_string();
+
+        if let Some((code, tags)) = pkt.pppoe_discovery() {
+            for (tag, value) in tags {
+                match tag {
+                    // empty service name means any service
+                    ppp::TAG_SERVICE_NAME if !value.is_empty() => {
+                        pppoe.service_name = Some(to_string(value))
+                    }
+                    ppp::TAG_AC_NAME => pppoe.ac_name = Some(to_string(value)),
+                    _ => {}
+                }
+            }
+            if code == ppp::PADT {
+                pppoe.terminated = true;
+            }
+            return;
+        }
+
+        let (protocol, ctrl) = match pkt.ppp_control() {
+            Some(control) => control,
+            None => return,
+        };
+        match (protocol, ctrl.code) {
+            (ppp::LCP, ppp::CONFIGURE_ACK) => {
+                for (opt, value) in ctrl.options() {
+                    if opt != ppp::LCP_OPT_AUTH_PROTOCOL as u16 || value.len() < 2 {
+                        continue;
+                    }
+                    match read_u16(value, 0) {
+                        ppp::PAP => pppoe.auth_protocol = Some("pap".to_string()),
+                        ppp::CHAP => pppoe.auth_protocol = Some("chap".to_string()),
+                        _ => {}
+                    };
+                }
+            }
+            (ppp::LCP, ppp::TERMINATE_REQUEST) => pppoe.terminated = true,
+            (ppp::IPCP, ppp::CONFIGURE_ACK) => {
+                for (opt, value) in ctrl.options() {
+                    if opt == ppp::IPCP_OPT_IP_ADDRESS as u16 && value.len() == 4 {
+                        pppoe.ip = Some(Ipv4Addr::from(read_u32(value, 0)));
+                    }
+                }
+            }
+            (ppp::PAP, ppp::PAP_REQUEST) | (ppp::CHAP, ppp::CHAP_RESPONSE) => {
+                if let Some(name) = ctrl.pap_peer_id().or_else(|| ctrl.chap_name()) {
+                    pppoe.username = Some(to_string(name));
+                }
+            }
+            (ppp::PAP, ppp::PAP_ACK) | (ppp::CHAP, ppp::CHAP_SUCCESS) => {
+                pppoe.authenticated = Some(true)
+            }
+            (ppp::PAP, ppp::PAP_NAK) | (ppp::CHAP, ppp::CHAP_FAILURE) => {
+                pppoe.authenticated = Some(false)
+            }
+            _ => {}
+        };
     }
 
     #[inline]
@@ -143,22 +982,16 @@ impl Session {
         self.protocols.contains(protocol.as_ref())
     }
 
-    /// Add tag
+    /// Tunnel protocols this session is encapsulated in
     #[inline]
-    pub fn add_tag<S: AsRef<str>>(&mut self, tag: &S) {
-        self.tags.insert(tag.as_ref().to_string());
+    pub fn tunnels(&self) -> packet::Tunnel {
+        self.tunnels
     }
 
-    /// Add field
+    /// Add tag
     #[inline]
-    pub fn add_field<S: AsRef<str>>(&mut self, key: &S, value: &serde_json::Value) {
-        match self.fields.as_mut() {
-            serde_json::Value::Object(obj) => match obj.get(key.as_ref()) {
-                None => obj.insert(key.as_ref().to_string(), value.clone()),
-                Some(_) => todo!(),
-            },
-            _ => todo!("need to guarantee fields is an object in Session initialization"),
-        };
+    pub fn add_tag<S: AsRef<str>>(&mut self, tag: &S) {
+        self.tags.insert(tag.as_ref().to_string());
     }
 
     /// Whether this session needs to do a middle save operation
@@ -186,12 +1019,590 @@ impl Session {
         }
     }
 
-    /// Reset mid saved session
+    /// Split this session at a middle save, returns the segment to save
+    ///
+    /// This session continues as the next segment, sharing the same root id
+    pub fn mid_save(&mut self, save_time: u64) -> Box<Session> {
+        if self.root_id.is_none() {
+            self.root_id = Some(self.id.clone());
+            self.segment_cnt = 1;
+        }
+        let segment = Box::new(self.clone());
+        self.mid_save_reset(save_time);
+        segment
+    }
+
+    /// Reset mid saved session as the next segment
     #[inline]
     pub fn mid_save_reset(&mut self, save_time: u64) {
         self.pkt_count = [0, 0];
         self.bytes = [0, 0];
         self.data_bytes = [0, 0];
         self.save_time = save_time;
+        self.start_time = self.end_time.clone();
+        self.id = Box::new(Session::new_id(&self.start_time));
+        self.segment_cnt += 1;
+        self.file_id.clear();
+        self.packet_pos.clear();
+        if let Some(stats) = self.stats.as_mut() {
+            stats.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Layer, Packet as PacketTrait, Protocol};
+    use crate::utils::packet::Packet;
+
+    fn sctp_pkt(raw: Vec<u8>) -> Packet {
+        let mut pkt = Packet::default();
+        pkt.caplen = raw.len() as u32;
+        pkt.raw = Box::new(raw);
+        pkt.layers_mut().trans = Layer {
+            protocol: Protocol::SCTP,
+            offset: 0,
+        };
+        pkt
+    }
+
+    #[test]
+    fn update_sctp() {
+        let mut ses = Session::new();
+
+        // INIT, from port 2905 to 36412
+        let pkt = sctp_pkt(vec![
+            0x0b, 0x59, 0x8e, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x01, 0x00, 0x00, 0x14, 0x11, 0x22, 0x33, 0x44, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0a,
+            0x00, 0x05, 0x00, 0x00, 0x00, 0x01, // INIT
+        ]);
+        ses.update(&pkt);
+        let dir = pkt.direction() as usize;
+        let sctp = ses.sctp.as_ref().unwrap();
+        assert_eq!(sctp.verification_tags[dir], 0);
+        assert_eq!(sctp.verification_tags[dir ^ 1], 0x11223344);
+        assert_eq!(sctp.outbound_streams[dir], 10);
+        assert_eq!(sctp.inbound_streams[dir], 5);
+        assert_eq!(ses.data_bytes, [0, 0]);
+
+        // DATA on stream 2 and DATA on stream 3
+        let pkt = sctp_pkt(vec![
+            0x0b, 0x59, 0x8e, 0x3c, 0x55, 0x66, 0x77, 0x88, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x00, 0x03, 0x00, 0x13, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x12, 0x01, 0x02, 0x03, 0x00, // DATA
+            0x00, 0x03, 0x00, 0x12, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x12, 0x04, 0x05, 0x00, 0x00, // DATA
+        ]);
+        ses.update(&pkt);
+        let sctp = ses.sctp.as_ref().unwrap();
+        assert_eq!(sctp.verification_tags[dir], 0x55667788);
+        assert_eq!(sctp.data_chunks, 2);
+        assert_eq!(
+            sctp.data_streams.iter().copied().collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(ses.data_bytes[dir], 5);
+
+        // ABORT
+        let pkt = sctp_pkt(vec![
+            0x0b, 0x59, 0x8e, 0x3c, 0x55, 0x66, 0x77, 0x88, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x06, 0x00, 0x00, 0x04,
+        ]);
+        ses.update(&pkt);
+        assert!(ses.sctp.as_ref().unwrap().aborted);
+        assert!(!ses.sctp.as_ref().unwrap().shutdown);
+    }
+
+    #[test]
+    fn sctp_payload() {
+        let mut pkt = sctp_pkt(vec![
+            0x0b, 0x59, 0x8e, 0x3c, 0x55, 0x66, 0x77, 0x88, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x00, 0x03, 0x00, 0x13, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x12, 0x01, 0x02, 0x03, 0x00, // DATA
+            0x00, 0x03, 0x00, 0x12, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x2e, 0x04, 0x05, 0x00, 0x00, // DATA
+        ]);
+        assert!(pkt.payload().is_empty());
+
+        pkt.layers_mut().app = Layer {
+            protocol: Protocol::APPLICATION,
+            offset: 28,
+        };
+        assert_eq!(pkt.payload(), &[0x01, 0x02, 0x03]);
+        assert_eq!(pkt.sctp_stream_id(), 2);
+        assert_eq!(pkt.sctp_ppid(), 0x12);
+
+        pkt.layers_mut().app.offset = 48;
+        assert_eq!(pkt.payload(), &[0x04, 0x05]);
+        assert_eq!(pkt.sctp_stream_id(), 3);
+        assert_eq!(pkt.sctp_ppid(), 46);
+    }
+
+    #[test]
+    fn update_mpls_labels() {
+        let mut ses = Session::new();
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![
+            0x00, 0x01, 0x20, 0xfe, // mpls, label 18
+            0x00, 0x01, 0x01, 0xff, // mpls, label 16
+            0x45, 0x00,
+        ]);
+        ses.update(&pkt);
+        assert!(ses.mpls_labels.is_empty());
+
+        pkt.tunnel = packet::Tunnel::MPLS;
+        pkt.layers_mut().mpls = Layer {
+            protocol: Protocol::MPLS,
+            offset: 0,
+        };
+        ses.update(&pkt);
+        ses.update(&pkt);
+        assert_eq!(ses.mpls_labels, vec![vec![18, 16]]);
+        assert_eq!(ses.tunnels(), packet::Tunnel::MPLS);
+
+        // same labels in a different order is another stack
+        pkt.raw = Box::new(vec![
+            0x00, 0x01, 0x00, 0xfe, // mpls, label 16
+            0x00, 0x01, 0x21, 0xff, // mpls, label 18
+            0x45, 0x00,
+        ]);
+        ses.update(&pkt);
+        ses.update(&pkt);
+        assert_eq!(ses.mpls_labels, vec![vec![18, 16], vec![16, 18]]);
+
+        pkt.tunnel = packet::Tunnel::MPLS | packet::Tunnel::IPIP;
+        ses.update(&pkt);
+        assert!(ses.tunnels().contains(packet::Tunnel::IPIP));
+    }
+
+    #[test]
+    fn update_esp_spis() {
+        let mut ses = Session::new();
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01]);
+        pkt.layers_mut().trans = Layer {
+            protocol: Protocol::ESP,
+            offset: 0,
+        };
+        ses.update(&pkt);
+        ses.update(&pkt);
+        pkt.raw[3] = 0x02;
+        ses.update(&pkt);
+        assert_eq!(ses.esp_spis, vec![0x1001, 0x1002]);
+        assert!(ses.has_protocol(&"esp"));
+    }
+
+    fn tcp_pkt(
+        src_ip: [u8; 4],
+        src_port: u16,
+        dst_ip: [u8; 4],
+        dst_port: u16,
+        flags: tcp::Flags,
+    ) -> Packet {
+        let mut pkt = Packet::default();
+        let mut raw = vec![
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, // ipv4
+        ];
+        raw.extend_from_slice(&src_ip);
+        raw.extend_from_slice(&dst_ip);
+        raw.extend_from_slice(&src_port.to_be_bytes());
+        raw.extend_from_slice(&dst_port.to_be_bytes());
+        raw.extend_from_slice(&[0; 8]);
+        raw.extend_from_slice(&[0x50, flags.bits(), 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        pkt.caplen = raw.len() as u32;
+        pkt.raw = Box::new(raw);
+        pkt.layers_mut().network = Layer {
+            protocol: Protocol::IPV4,
+            offset: 0,
+        };
+        pkt.layers_mut().trans = Layer {
+            protocol: Protocol::TCP,
+            offset: 20,
+        };
+        pkt.layers_mut().app = Layer {
+            protocol: Protocol::APPLICATION,
+            offset: 40,
+        };
+        pkt
+    }
+
+    #[test]
+    fn endpoints() {
+        let syn_ack = tcp::Flags::SYN | tcp::Flags::ACK;
+        let server = tcp_pkt([10, 0, 0, 2], 80, [10, 0, 0, 1], 50000, syn_ack);
+        let client = tcp_pkt([10, 0, 0, 1], 50000, [10, 0, 0, 2], 80, tcp::Flags::ACK);
+
+        // initiator is the receiver of the first SYN-ACK
+        let mut ses = Session::new();
+        ses.update(&server);
+        ses.update(&client);
+        assert_eq!(ses.src_ip, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert_eq!(ses.dst_ip, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+        assert_eq!((ses.src_port, ses.dst_port), (50000, 80));
+        assert_eq!(ses.ip_protocol, 6);
+        assert_eq!(ses.direction(&client), packet::Direction::LEFT);
+        assert_eq!(ses.direction(&server), packet::Direction::RIGHT);
+        assert_eq!(ses.pkt_count, [1, 1]);
+
+        // initiator has a larger port than the responder
+        let mut ses = Session::new();
+        ses.update(&tcp_pkt(
+            [10, 0, 0, 2],
+            80,
+            [10, 0, 0, 1],
+            50000,
+            tcp::Flags::SYN,
+        ));
+        assert_eq!((ses.src_port, ses.dst_port), (80, 50000));
+        assert_eq!(ses.direction(&client), packet::Direction::RIGHT);
+
+        let mut pkt = client.clone();
+        pkt.raw = Box::new([[0xaa; 6], [0xbb; 6]].concat());
+        pkt.raw.extend_from_slice(&[0x08, 0x00]);
+        pkt.raw.extend_from_slice(&client.raw);
+        pkt.layers.data_link.protocol = Protocol::ETHERNET;
+        pkt.layers.network.offset += 14;
+        pkt.layers.trans.offset += 14;
+        pkt.layers.app.offset += 14;
+        pkt.caplen += 14;
+        ses.update(&pkt);
+        assert_eq!(ses.dst_mac, vec![[0xbb; 6]]);
+        assert_eq!(ses.src_mac, vec![[0xaa; 6]]);
+        let json = serde_json::to_value(&ses).unwrap();
+        #[cfg(not(feature = "arkime"))]
+        {
+            assert_eq!(json["src_ip"], "10.0.0.2");
+            assert_eq!(json["dst_mac"], serde_json::json!(["bb:bb:bb:bb:bb:bb"]));
+        }
+        #[cfg(feature = "arkime")]
+        {
+            assert_eq!(json["srcIp"], "10.0.0.2");
+            assert_eq!(json["dstMac"], serde_json::json!(["bb:bb:bb:bb:bb:bb"]));
+            assert_eq!(json["ipProtocol"], 6);
+        }
+    }
+
+    #[test]
+    fn new_id() {
+        let ts = libc::timeval {
+            tv_sec: 1615766400,
+            tv_usec: 1,
+        };
+        let id = Session::new_id(&ts);
+        assert!(id.starts_with("210315-"));
+        assert_eq!(id.len(), 27);
+
+        let next = Session::new_id(&ts);
+        assert!(next > id);
+        // the same time and node, then the sequence number
+        assert_eq!(id[..20], next[..20]);
+        let later = Session::new_id(&libc::timeval {
+            tv_sec: 1615766400,
+            tv_usec: 2,
+        });
+        assert!(later > next);
+    }
+
+    #[test]
+    fn mid_save() {
+        let mut ses = Session::new();
+        ses.id = Box::new(Session::new_id(&ses.start_time));
+        ses.pkt_count = [3, 2];
+        ses.end_time.tv_sec = 100;
+
+        let first = ses.mid_save(200);
+        assert_eq!(first.root_id, Some(first.id.clone()));
+        assert_eq!(first.segment_cnt, 1);
+        assert_eq!(first.pkt_count, [3, 2]);
+        assert_eq!(ses.pkt_count, [0, 0]);
+        assert_eq!(ses.start_time.tv_sec, 100);
+        assert_eq!(ses.save_time, 200);
+
+        let second = ses.mid_save(300);
+        assert_ne!(second.id, first.id);
+        assert_eq!(second.root_id, first.root_id);
+        assert_eq!(second.segment_cnt, 2);
+        assert_eq!(ses.segment_cnt, 3);
+
+        let json = serde_json::to_value(&second).unwrap();
+        #[cfg(not(feature = "arkime"))]
+        assert_eq!(json["root_id"], first.id.as_str());
+        #[cfg(feature = "arkime")]
+        assert_eq!(json["rootId"], first.id.as_str());
+    }
+
+    #[test]
+    fn update_tcp() {
+        let client = |flags| tcp_pkt([10, 0, 0, 1], 50000, [10, 0, 0, 2], 80, flags);
+        let server = |flags| tcp_pkt([10, 0, 0, 2], 80, [10, 0, 0, 1], 50000, flags);
+
+        let mut ses = Session::new();
+        ses.update(&client(tcp::Flags::SYN));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::SynSent);
+        ses.update(&server(tcp::Flags::SYN | tcp::Flags::ACK));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::SynReceived);
+        // retransmitted SYN-ACK doesn't complete the handshake
+        ses.update(&server(tcp::Flags::SYN | tcp::Flags::ACK));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::SynReceived);
+        ses.update(&client(tcp::Flags::ACK));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::Established);
+        assert!(ses.tcp.as_ref().unwrap().handshake_complete);
+
+        ses.update(&client(tcp::Flags::FIN | tcp::Flags::ACK));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::Closing);
+        assert!(!ses.tcp_closed());
+        ses.update(&server(tcp::Flags::FIN | tcp::Flags::ACK));
+        assert!(ses.tcp_closed());
+        assert_eq!(
+            ses.tcp.as_ref().unwrap().close_reason,
+            Some(CloseReason::Fin)
+        );
+        // late RST keeps the close reason
+        ses.update(&client(tcp::Flags::RST));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::Reset);
+        assert_eq!(
+            ses.tcp.as_ref().unwrap().close_reason,
+            Some(CloseReason::Fin)
+        );
+
+        // picked up midstream then reset
+        let mut ses = Session::new();
+        ses.update(&server(tcp::Flags::ACK | tcp::Flags::PSH));
+        assert_eq!(ses.tcp.as_ref().unwrap().state, TcpState::Established);
+        assert!(!ses.tcp.as_ref().unwrap().handshake_complete);
+        ses.update(&client(tcp::Flags::RST | tcp::Flags::ACK));
+        assert!(ses.tcp_closed());
+        assert_eq!(
+            ses.tcp.as_ref().unwrap().close_reason,
+            Some(CloseReason::Rst)
+        );
+        let json = serde_json::to_value(ses.tcp.as_ref().unwrap()).unwrap();
+        assert_eq!(json["state"], "reset");
+        #[cfg(not(feature = "arkime"))]
+        assert_eq!(json["close_reason"], "rst");
+        #[cfg(feature = "arkime")]
+        assert_eq!(json["closeReason"], "rst");
+    }
+
+    #[test]
+    fn update_community_id() {
+        let mut ses = Session::new();
+        let pkt = tcp_pkt(
+            [10, 0, 0, 2],
+            80,
+            [10, 0, 0, 1],
+            50000,
+            tcp::Flags::SYN | tcp::Flags::ACK,
+        );
+        ses.update(&pkt);
+        ses.update_community_id(&pkt, 0);
+        let expected = community_id(
+            0,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            6,
+            Some((50000, 80)),
+        );
+        assert_eq!(ses.community_id, Some(expected));
+        let json = serde_json::to_value(&ses).unwrap();
+        #[cfg(not(feature = "arkime"))]
+        assert!(json["community_id"].as_str().unwrap().starts_with("1:"));
+        #[cfg(feature = "arkime")]
+        assert!(json["communityId"].as_str().unwrap().starts_with("1:"));
+
+        // ICMP echo reply
+        let mut pkt = Packet::default();
+        pkt.raw = Box::new(vec![
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x01, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0x59, // ipv4
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, // icmp
+        ]);
+        pkt.layers_mut().network = Layer {
+            protocol: Protocol::IPV4,
+            offset: 0,
+        };
+        let mut ses = Session::new();
+        ses.update(&pkt);
+        ses.update_community_id(&pkt, 0);
+        assert_eq!(
+            ses.community_id.as_deref(),
+            Some("1:X0snYXpgwiv9TZtqg64sgzUn6Dk=")
+        );
+
+        // non IP session
+        let mut ses = Session::new();
+        ses.update_community_id(&Packet::default(), 0);
+        assert_eq!(ses.community_id, None);
+    }
+
+    #[test]
+    fn fields() {
+        let mut ses = Session::new();
+        ses.fields.add_str("http.uri", "/index.html");
+        ses.fields.add_str("http.uri", "/index.html");
+        ses.fields.incr("http.requests", 2);
+        let json = serde_json::to_value(&ses).unwrap();
+        assert_eq!(json["http.uri"], serde_json::json!(["/index.html"]));
+        assert_eq!(json["http.requests"], 2);
+    }
+
+    #[test]
+    fn geo() {
+        let mut ses = Session::new();
+        ses.src_geo = Some(Box::new(Geo {
+            country: Some("US".to_string()),
+            city: None,
+            asn: Some(15169),
+            org: Some("Google LLC".to_string()),
+        }));
+        let json = serde_json::to_value(&ses).unwrap();
+        #[cfg(not(feature = "arkime"))]
+        {
+            assert_eq!(json["src_geo"]["country"], "US");
+            assert_eq!(json["src_geo"]["asn"], 15169);
+            assert!(json["src_geo"].get("city").is_none());
+            assert!(json.get("dst_geo").is_none());
+        }
+        #[cfg(feature = "arkime")]
+        {
+            assert_eq!(json["srcGEO"], "US");
+            assert_eq!(json["srcASN"], "AS15169 Google LLC");
+            assert!(json.get("srcGEOCity").is_none());
+            assert!(json.get("dstGEO").is_none());
+        }
+    }
+
+    #[test]
+    fn packet_pos() {
+        let mut ses = Session::new();
+        ses.add_packet_pos(1, 24);
+        ses.add_packet_pos(1, 100);
+        ses.add_packet_pos(2, 24);
+        assert_eq!(ses.file_id, vec![1, 2]);
+        assert_eq!(ses.packet_pos, vec![-1, 24, 100, -2, 24]);
+        let json = serde_json::to_value(&ses).unwrap();
+        #[cfg(not(feature = "arkime"))]
+        assert_eq!(json["packet_pos"], serde_json::json!([-1, 24, 100, -2, 24]));
+        #[cfg(feature = "arkime")]
+        assert_eq!(json["packetPos"], serde_json::json!([-1, 24, 100, -2, 24]));
+
+        // a new segment records its own packets
+        ses.mid_save_reset(0);
+        ses.add_packet_pos(2, 200);
+        assert_eq!(ses.file_id, vec![2]);
+        assert_eq!(ses.packet_pos, vec![-2, 200]);
+    }
+
+    #[test]
+    fn flow_stats() {
+        let pkt = |client: bool, flags, usec| {
+            let mut pkt = if client {
+                tcp_pkt([10, 0, 0, 1], 50000, [10, 0, 0, 2], 80, flags)
+            } else {
+                tcp_pkt([10, 0, 0, 2], 80, [10, 0, 0, 1], 50000, flags)
+            };
+            pkt.ts.tv_sec = 1;
+            pkt.ts.tv_usec = usec;
+            pkt
+        };
+
+        let mut ses = Session::new();
+        ses.stats = Some(Box::new(FlowStats::new(3)));
+        ses.update(&pkt(true, tcp::Flags::SYN, 0));
+        ses.update(&pkt(false, tcp::Flags::SYN | tcp::Flags::ACK, 1000));
+        ses.update(&pkt(true, tcp::Flags::ACK, 3000));
+        let mut data = pkt(true, tcp::Flags::ACK | tcp::Flags::PSH, 7000);
+        data.caplen = 140;
+        ses.update(&data);
+
+        let stats = ses.stats.as_ref().unwrap();
+        assert_eq!(stats.handshake_rtt, Some(3000));
+        assert_eq!(stats.first_pkts, vec![40, -40, 40]);
+        let tcp_flags = stats.tcp_flags.as_ref().unwrap();
+        assert_eq!(
+            (
+                tcp_flags.syn,
+                tcp_flags.syn_ack,
+                tcp_flags.ack,
+                tcp_flags.psh
+            ),
+            (1, 1, 2, 1)
+        );
+
+        let size = &stats.pkt_size[0];
+        assert_eq!((size.count(), size.min(), size.max()), (3, 40, 140));
+        assert!((size.mean() - 220.0 / 3.0).abs() < 1e-9);
+        assert!((size.stddev() - 47.140452079).abs() < 1e-6);
+        let iat = &stats.iat[0];
+        assert_eq!((iat.count(), iat.min(), iat.max()), (2, 3000, 4000));
+        assert_eq!(stats.iat[1].count(), 0);
+
+        let json = serde_json::to_value(stats).unwrap();
+        assert_eq!(json["iat"][0]["mean"], 3500.0);
+        assert_eq!(json["iat"][0]["stddev"], 500.0);
+
+        // a new segment keeps inter-arrival time continuous
+        ses.mid_save_reset(0);
+        ses.update(&pkt(true, tcp::Flags::ACK, 9000));
+        let stats = ses.stats.as_ref().unwrap();
+        assert_eq!(stats.handshake_rtt, None);
+        assert_eq!(stats.first_pkts, vec![40]);
+        assert_eq!(stats.iat[0].min(), 2000);
+    }
+
+    #[test]
+    fn update_pppoe() {
+        let pppoe_pkt = |raw: Vec<u8>| {
+            let mut pkt = Packet::default();
+            pkt.raw = Box::new(raw);
+            pkt.layers_mut().pppoe = Layer {
+                protocol: Protocol::PPPOE,
+                offset: 0,
+            };
+            pkt
+        };
+        let mut ses = Session::new();
+
+        // PADS
+        ses.update(&pppoe_pkt(vec![
+            0x11, 0x65, 0x00, 0x2a, 0x00, 0x0c, // pppoe
+            0x01, 0x01, 0x00, 0x00, // service name
+            0x01, 0x02, 0x00, 0x04, b'b', b'r', b'a', b's', // ac name
+        ]));
+        let pppoe = ses.pppoe.as_ref().unwrap();
+        assert_eq!(pppoe.session_id, 42);
+        assert_eq!(pppoe.service_name, None);
+        assert_eq!(pppoe.ac_name.as_deref(), Some("bras"));
+
+        // LCP Configure-Ack with PAP
+        ses.update(&pppoe_pkt(vec![
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x0a, 0xc0, 0x21, // pppoe & ppp
+            0x02, 0x01, 0x00, 0x08, 0x03, 0x04, 0xc0, 0x23,
+        ]));
+        // PAP Authenticate-Request & Authenticate-Ack
+        ses.update(&pppoe_pkt(vec![
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x10, 0xc0, 0x23, // pppoe & ppp
+            0x01, 0x01, 0x00, 0x0e, 0x04, b'u', b's', b'e', b'r', 0x04, b'p', b'a', b's', b's',
+        ]));
+        ses.update(&pppoe_pkt(vec![
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x07, 0xc0, 0x23, // pppoe & ppp
+            0x02, 0x01, 0x00, 0x05, 0x00,
+        ]));
+        // IPCP Configure-Ack
+        ses.update(&pppoe_pkt(vec![
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x0c, 0x80, 0x21, // pppoe & ppp
+            0x02, 0x01, 0x00, 0x0a, 0x03, 0x06, 0x64, 0x40, 0x00, 0x07,
+        ]));
+        let pppoe = ses.pppoe.as_ref().unwrap();
+        assert_eq!(pppoe.auth_protocol.as_deref(), Some("pap"));
+        assert_eq!(pppoe.username.as_deref(), Some("user"));
+        assert_eq!(pppoe.authenticated, Some(true));
+        assert_eq!(pppoe.ip, Some(Ipv4Addr::new(100, 64, 0, 7)));
+        assert!(!pppoe.terminated);
+
+        // PADT
+        ses.update(&pppoe_pkt(vec![0x11, 0xa7, 0x00, 0x2a, 0x00, 0x00]));
+        assert!(ses.pppoe.as_ref().unwrap().terminated);
     }
 }
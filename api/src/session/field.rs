@@ -0,0 +1,451 @@
+//! Custom session fields added by protocol parsers
+//!
+//! Each field keeps its values by the semantics of the first method adding to it:
+//! a set of distinct strings, integers or ip addresses, a counter, a capped list,
+//! or a single first/last value. Values of another type are rejected.
+//!
+//! Parsers declare the fields they add in a [`FieldRegistry`], so outputs know each
+//! field's type and description.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem::{discriminant, size_of};
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+/// Default max values of a set or list field
+pub const MAX_VALUES: usize = 256;
+
+/// A single field value
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+    Ip(IpAddr),
+}
+
+impl Value {
+    /// Approximate bytes held by the value
+    #[inline]
+    fn memory(&self) -> usize {
+        match self {
+            Value::Str(s) => size_of::<Value>() + s.len(),
+            _ => size_of::<Value>(),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Str(v)
+    }
+}
+
+impl From<IpAddr> for Value {
+    fn from(v: IpAddr) -> Self {
+        Value::Ip(v)
+    }
+}
+
+/// Values of a field
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Field {
+    StrSet(BTreeSet<String>),
+    IntSet(BTreeSet<i64>),
+    IpSet(BTreeSet<IpAddr>),
+    Counter(u64),
+    /// Values in adding order, duplicates included
+    List(Vec<Value>),
+    Single(Value),
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    /// None until a value is added
+    field: Option<Field>,
+    /// Max values of a set or list field
+    limit: usize,
+}
+
+/// Custom fields of a session
+#[derive(Clone, Debug, Default)]
+pub struct Fields {
+    entries: BTreeMap<String, Entry>,
+    /// Approximate bytes held by the keys and values
+    memory: usize,
+}
+
+impl Fields {
+    #[inline]
+    fn entry(&mut self, key: &str) -> &mut Entry {
+        if !self.entries.contains_key(key) {
+            let entry = Entry {
+                field: None,
+                limit: MAX_VALUES,
+            };
+            self.memory += size_of::<String>() + key.len() + size_of::<Entry>();
+            self.entries.insert(key.to_string(), entry);
+        }
+        self.entries.get_mut(key).unwrap()
+    }
+
+    /// Approximate bytes held by the fields, excluding the Fields itself
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Values of a field
+    pub fn get(&self, key: &str) -> Option<&Field> {
+        self.entries.get(key).and_then(|entry| entry.field.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.values().all(|entry| entry.field.is_none())
+    }
+
+    /// Set max values of a set or list field, values beyond the limit are dropped
+    pub fn set_limit(&mut self, key: &str, limit: usize) {
+        self.entry(key).limit = limit;
+    }
+
+    /// Add a string to a set field, returns whether it's added
+    pub fn add_str(&mut self, key: &str, value: &str) -> bool {
+        let entry = self.entry(key);
+        let limit = entry.limit;
+        match entry
+            .field
+            .get_or_insert_with(|| Field::StrSet(BTreeSet::new()))
+        {
+            Field::StrSet(set) if set.len() < limit && !set.contains(value) => {
+                set.insert(value.to_string());
+            }
+            _ => return false,
+        }
+        self.memory += size_of::<String>() + value.len();
+        true
+    }
+
+    /// Add an integer to a set field, returns whether it's added
+    pub fn add_int(&mut self, key: &str, value: i64) -> bool {
+        let entry = self.entry(key);
+        let limit = entry.limit;
+        match entry
+            .field
+            .get_or_insert_with(|| Field::IntSet(BTreeSet::new()))
+        {
+            Field::IntSet(set) if set.len() < limit => {
+                if !set.insert(value) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+        self.memory += size_of::<i64>();
+        true
+    }
+
+    /// Add an ip address to a set field, returns whether it's added
+    pub fn add_ip(&mut self, key: &str, value: IpAddr) -> bool {
+        let entry = self.entry(key);
+        let limit = entry.limit;
+        match entry
+            .field
+            .get_or_insert_with(|| Field::IpSet(BTreeSet::new()))
+        {
+            Field::IpSet(set) if set.len() < limit => {
+                if !set.insert(value) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+        self.memory += size_of::<IpAddr>();
+        true
+    }
+
+    /// Increase a counter field
+    pub fn incr(&mut self, key: &str, n: u64) -> bool {
+        match self.entry(key).field.get_or_insert(Field::Counter(0)) {
+            Field::Counter(count) => {
+                *count = count.saturating_add(n);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Append a value to a list field, returns whether it's appended
+    pub fn append<V: Into<Value>>(&mut self, key: &str, value: V) -> bool {
+        let entry = self.entry(key);
+        let limit = entry.limit;
+        match entry.field.get_or_insert_with(|| Field::List(vec![])) {
+            Field::List(list) if list.len() < limit => {
+                let value = value.into();
+                let memory = value.memory();
+                list.push(value);
+                self.memory += memory;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set a single value field unless it's already set, returns whether it's set
+    pub fn set_first<V: Into<Value>>(&mut self, key: &str, value: V) -> bool {
+        let entry = self.entry(key);
+        match entry.field {
+            None => {
+                let value = value.into();
+                let memory = value.memory();
+                entry.field = Some(Field::Single(value));
+                self.memory += memory;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set a single value field, replacing its value of the same type
+    pub fn set_last<V: Into<Value>>(&mut self, key: &str, value: V) -> bool {
+        let value = value.into();
+        let memory = value.memory();
+        let entry = self.entry(key);
+        let replaced = match &mut entry.field {
+            None => {
+                entry.field = Some(Field::Single(value));
+                0
+            }
+            Some(Field::Single(v)) if discriminant(v) == discriminant(&value) => {
+                std::mem::replace(v, value).memory()
+            }
+            _ => return false,
+        };
+        self.memory = self.memory + memory - replaced;
+        true
+    }
+}
+
+impl Serialize for Fields {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for (key, entry) in &self.entries {
+            if let Some(field) = &entry.field {
+                map.serialize_entry(key, field)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// Type of a field's values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Str,
+    /// Integers, including counters
+    Int,
+    Ip,
+}
+
+/// Definition of a custom session field
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldDef {
+    /// Key of the field in sessions, e.g. http.uri
+    pub name: String,
+    pub field_type: FieldType,
+    /// Group of related fields, usually the protocol name
+    pub category: String,
+    /// Short name shown to users
+    pub friendly_name: String,
+    /// Description of the field
+    pub help: String,
+}
+
+impl FieldDef {
+    pub fn new(
+        name: &str,
+        field_type: FieldType,
+        category: &str,
+        friendly_name: &str,
+        help: &str,
+    ) -> Self {
+        FieldDef {
+            name: name.to_string(),
+            field_type,
+            category: category.to_string(),
+            friendly_name: friendly_name.to_string(),
+            help: help.to_string(),
+        }
+    }
+}
+
+/// Definitions of all custom session fields
+#[derive(Clone, Debug, Default)]
+pub struct FieldRegistry {
+    defs: BTreeMap<String, FieldDef>,
+}
+
+impl FieldRegistry {
+    /// Register a field
+    ///
+    /// Parsers sharing a field may register it repeatedly, but only with the same definition
+    pub fn register(&mut self, def: FieldDef) -> Result<()> {
+        match self.defs.get(&def.name) {
+            None => {
+                self.defs.insert(def.name.clone(), def);
+                Ok(())
+            }
+            Some(registered) if *registered == def => Ok(()),
+            Some(_) => Err(anyhow!(
+                "Field {} is registered with another definition",
+                def.name
+            )),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FieldDef> {
+        self.defs.get(name)
+    }
+
+    /// Registered fields ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = &FieldDef> {
+        self.defs.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn semantics() {
+        let mut fields = Fields::default();
+        assert!(fields.add_str("http.uri", "/a"));
+        assert!(fields.add_str("http.uri", "/b"));
+        assert!(!fields.add_str("http.uri", "/a"));
+        // type mismatch
+        assert!(!fields.add_int("http.uri", 1));
+        assert!(!fields.set_first("http.uri", "/c"));
+
+        fields.set_limit("http.status", 2);
+        assert!(fields.add_int("http.status", 200));
+        assert!(!fields.add_int("http.status", 200));
+        assert!(fields.add_int("http.status", 404));
+        assert!(!fields.add_int("http.status", 500));
+
+        assert!(fields.add_ip("dns.ip", "10.0.0.1".parse().unwrap()));
+        assert!(!fields.add_ip("dns.ip", "10.0.0.1".parse().unwrap()));
+
+        assert!(fields.incr("http.requests", 1));
+        assert!(fields.incr("http.requests", 2));
+
+        fields.set_limit("http.method", 3);
+        for method in ["GET", "GET", "POST", "GET"].iter() {
+            fields.append("http.method", *method);
+        }
+
+        assert!(fields.set_first("ike.version", "1.0"));
+        assert!(!fields.set_first("ike.version", "2.0"));
+        assert!(fields.set_last("user", "a"));
+        assert!(fields.set_last("user", "b"));
+        assert!(!fields.set_last("user", 1i64));
+
+        assert_eq!(fields.get("http.requests"), Some(&Field::Counter(3)));
+        assert_eq!(fields.get("missing"), None);
+        let json = serde_json::to_value(&fields).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "dns.ip": ["10.0.0.1"],
+                "http.method": ["GET", "GET", "POST"],
+                "http.requests": 3,
+                "http.status": [200, 404],
+                "http.uri": ["/a", "/b"],
+                "ike.version": "1.0",
+                "user": "b",
+            })
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let mut fields = Fields::default();
+        assert!(fields.is_empty());
+        assert_eq!(fields.memory(), 0);
+        fields.set_limit("http.uri", 1);
+        assert!(fields.is_empty());
+        assert_eq!(serde_json::to_string(&fields).unwrap(), "{}");
+    }
+
+    #[test]
+    fn memory() {
+        let mut fields = Fields::default();
+        let key = size_of::<String>() + "user".len() + size_of::<Entry>();
+        fields.add_str("user", "ab");
+        assert_eq!(fields.memory(), key + size_of::<String>() + 2);
+        fields.add_str("user", "ab");
+        assert_eq!(fields.memory(), key + size_of::<String>() + 2);
+
+        let mut fields = Fields::default();
+        fields.set_last("user", "abc");
+        fields.set_last("user", "a");
+        assert_eq!(fields.memory(), key + size_of::<Value>() + 1);
+    }
+
+    #[test]
+    fn registry() {
+        let mut registry = FieldRegistry::default();
+        let user = FieldDef::new("user", FieldType::Str, "general", "User", "User names");
+        registry.register(user.clone()).unwrap();
+        registry.register(user.clone()).unwrap();
+        registry
+            .register(FieldDef::new(
+                "ike.version",
+                FieldType::Str,
+                "ike",
+                "Version",
+                "IKE version",
+            ))
+            .unwrap();
+        let mut other = user.clone();
+        other.field_type = FieldType::Int;
+        assert!(registry.register(other).is_err());
+
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get("user"), Some(&user));
+        let names: Vec<_> = registry.iter().map(|def| def.name.as_str()).collect();
+        assert_eq!(names, vec!["ike.version", "user"]);
+    }
+}
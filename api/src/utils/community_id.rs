@@ -0,0 +1,127 @@
+//! Community ID flow hash, version 1
+//!
+//! See https://github.com/corelight/community-id-spec
+
+use std::net::IpAddr;
+
+use sha1::{Digest, Sha1};
+
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+
+/// ICMP message types and their counterparts of the other direction
+const ICMP_PAIRS: &[(u8, u8)] = &[(8, 0), (13, 14), (15, 16), (10, 9), (17, 18)];
+const ICMPV6_PAIRS: &[(u8, u8)] = &[
+    (128, 129),
+    (130, 131),
+    (133, 134),
+    (135, 136),
+    (139, 140),
+    (144, 145),
+];
+
+/// Map ICMP type and code to ports, returns whether the message is one way
+fn icmp_ports(ip_protocol: u8, icmp_type: u16, code: u16) -> (u16, u16, bool) {
+    let pairs = match ip_protocol {
+        ICMP => ICMP_PAIRS,
+        _ => ICMPV6_PAIRS,
+    };
+    for (a, b) in pairs {
+        if icmp_type == *a as u16 {
+            return (icmp_type, *b as u16, false);
+        }
+        if icmp_type == *b as u16 {
+            return (icmp_type, *a as u16, false);
+        }
+    }
+    (icmp_type, code, true)
+}
+
+/// Community ID of a flow
+///
+/// # Arguments
+///
+/// * `seed` - Seed shared by all sensors whose flows are joined
+/// * `ports` - Source and destination ports, or ICMP type and code, None if the protocol has no ports
+pub fn community_id(
+    seed: u16,
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    ip_protocol: u8,
+    ports: Option<(u16, u16)>,
+) -> String {
+    let (mut src_ip, mut dst_ip) = (src_ip, dst_ip);
+    let mut one_way = false;
+    let mut ports = ports;
+    if let (ICMP, Some((icmp_type, code))) | (ICMPV6, Some((icmp_type, code))) =
+        (ip_protocol, ports)
+    {
+        let (src_port, dst_port, icmp_one_way) = icmp_ports(ip_protocol, icmp_type, code);
+        ports = Some((src_port, dst_port));
+        one_way = icmp_one_way;
+    }
+
+    let (src_port, dst_port) = ports.unwrap_or((0, 0));
+    if !one_way && (src_ip, src_port) > (dst_ip, dst_port) {
+        std::mem::swap(&mut src_ip, &mut dst_ip);
+        ports = ports.map(|(src, dst)| (dst, src));
+    }
+
+    let mut buf = Vec::with_capacity(40);
+    buf.extend_from_slice(&seed.to_be_bytes());
+    for ip in [src_ip, dst_ip].iter() {
+        match ip {
+            IpAddr::V4(ip) => buf.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => buf.extend_from_slice(&ip.octets()),
+        };
+    }
+    buf.push(ip_protocol);
+    buf.push(0);
+    if let Some((src_port, dst_port)) = ports {
+        buf.extend_from_slice(&src_port.to_be_bytes());
+        buf.extend_from_slice(&dst_port.to_be_bytes());
+    }
+
+    format!("1:{}", base64::encode(Sha1::digest(&buf)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flows() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let tcp = community_id(
+            0,
+            ip("128.232.110.120"),
+            ip("66.35.250.204"),
+            6,
+            Some((34855, 80)),
+        );
+        assert_eq!(tcp, "1:LQU9qZlK+B5F3KDmev6m5PMibrg=");
+        // both directions share the same id
+        let reversed = community_id(
+            0,
+            ip("66.35.250.204"),
+            ip("128.232.110.120"),
+            6,
+            Some((80, 34855)),
+        );
+        assert_eq!(reversed, tcp);
+        let seeded = community_id(
+            1,
+            ip("128.232.110.120"),
+            ip("66.35.250.204"),
+            6,
+            Some((34855, 80)),
+        );
+        assert_ne!(seeded, tcp);
+
+        // echo request and reply
+        let request = community_id(0, ip("192.168.0.89"), ip("192.168.0.1"), 1, Some((8, 0)));
+        let reply = community_id(0, ip("192.168.0.1"), ip("192.168.0.89"), 1, Some((0, 0)));
+        assert_eq!(request, "1:X0snYXpgwiv9TZtqg64sgzUn6Dk=");
+        assert_eq!(request, reply);
+    }
+}
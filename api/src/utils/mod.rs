@@ -1,2 +1,3 @@
+pub mod community_id;
 pub mod packet;
 pub mod timeval;
@@ -1,5 +1,5 @@
 use crate::classifiers::matched::Rule;
-use crate::packet::{Layers, Packet as PacketTrait, Rules, Tunnel};
+use crate::packet::{Direction, Layers, Packet as PacketTrait, Rules, Tunnel};
 
 // Packet structure only for test use
 #[derive(Clone)]
@@ -16,6 +16,9 @@ pub struct Packet {
     pub hash: u64,
     pub rules: Rules,
     pub tunnel: Tunnel,
+    /// Data link type, a pcap DLT number
+    pub link_type: u16,
+    pub direction: Direction,
 }
 
 impl Default for Packet {
@@ -31,6 +34,8 @@ impl Default for Packet {
             hash: 0,
             rules: Rules::default(),
             tunnel: Tunnel::default(),
+            link_type: 1,
+            direction: Direction::default(),
         }
     }
 }
@@ -75,4 +80,16 @@ impl PacketTrait for Packet {
     fn clone_box(&self) -> Box<dyn PacketTrait + '_> {
         Box::new(self.clone())
     }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn direction_mut(&mut self) -> &mut Direction {
+        &mut self.direction
+    }
+
+    fn link_type(&self) -> u16 {
+        self.link_type
+    }
 }
@@ -1,4 +1,7 @@
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
+
+/// Name of the subcommand extracting a session's packets from a pcap file
+pub const EXTRACT: &str = "extract";
 
 /// Avaliable command line arguments
 pub enum CliArg {
@@ -29,6 +32,86 @@ impl CliArg {
     }
 }
 
+/// Avaliable command line arguments of the extract subcommand
+pub enum ExtractArg {
+    Input,
+    Output,
+    Proto,
+    Src,
+    Dst,
+    CommunityId,
+    Start,
+    End,
+}
+
+impl ExtractArg {
+    pub fn as_str(&self) -> &str {
+        match self {
+            &ExtractArg::Input => "input",
+            &ExtractArg::Output => "output",
+            &ExtractArg::Proto => "proto",
+            &ExtractArg::Src => "src",
+            &ExtractArg::Dst => "dst",
+            &ExtractArg::CommunityId => "community-id",
+            &ExtractArg::Start => "start",
+            &ExtractArg::End => "end",
+        }
+    }
+}
+
+/// Construct the extract subcommand
+fn new_extract_command<'a>() -> clap::App<'a, 'static> {
+    SubCommand::with_name(EXTRACT)
+        .about("Extract packets of a session from a pcap file into a new pcap file")
+        .args(&[
+            Arg::with_name(ExtractArg::Input.as_str())
+                .value_name("PCAP-FILE")
+                .help("Pcap file to extract packets from")
+                .required(true),
+            Arg::with_name(ExtractArg::Output.as_str())
+                .short("w")
+                .value_name("FILE")
+                .help("Pcap file to write extracted packets to")
+                .takes_value(true)
+                .required(true),
+            Arg::with_name(ExtractArg::Proto.as_str())
+                .long("proto")
+                .value_name("PROTO")
+                .help("Transport protocol of the session: tcp, udp, sctp or icmp")
+                .takes_value(true)
+                .requires_all(&[ExtractArg::Src.as_str(), ExtractArg::Dst.as_str()]),
+            Arg::with_name(ExtractArg::Src.as_str())
+                .long("src")
+                .value_name("IP[:PORT]")
+                .help("One endpoint of the session, of any port if it is omitted. IPv6 addresses with port are bracketed")
+                .takes_value(true)
+                .requires(ExtractArg::Proto.as_str()),
+            Arg::with_name(ExtractArg::Dst.as_str())
+                .long("dst")
+                .value_name("IP[:PORT]")
+                .help("The other endpoint of the session")
+                .takes_value(true)
+                .requires(ExtractArg::Proto.as_str()),
+            Arg::with_name(ExtractArg::CommunityId.as_str())
+                .long("community-id")
+                .value_name("ID")
+                .help(
+                    "Community ID of the session, hashed with community.id.seed of the config file",
+                )
+                .takes_value(true),
+            Arg::with_name(ExtractArg::Start.as_str())
+                .long("start")
+                .value_name("TIME")
+                .help("Only extract packets since this time, unix seconds or RFC 3339")
+                .takes_value(true),
+            Arg::with_name(ExtractArg::End.as_str())
+                .long("end")
+                .value_name("TIME")
+                .help("Only extract packets before this time, unix seconds or RFC 3339")
+                .takes_value(true),
+        ])
+}
+
 /// Construct a new clap root command
 pub fn new_root_command<'a>() -> clap::App<'a, 'static> {
     let root_cmd = App::new(crate_name!())
@@ -68,14 +151,15 @@ pub fn new_root_command<'a>() -> clap::App<'a, 'static> {
             Arg::with_name(CliArg::Tags.as_str())
                 .short("t")
                 .long("tags")
-                .help("Extra tags to add to all packets")
+                .help("Extra tags to add to all sessions")
                 .takes_value(true)
                 .multiple(true),
             Arg::with_name(CliArg::Verbose.as_str())
                 .short("v")
                 .long("verbose")
                 .help("Turn on all debugging"),
-        ]);
+        ])
+        .subcommand(new_extract_command());
 
     return root_cmd;
 }
@@ -1,10 +1,16 @@
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
 use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
 use yaml_rust::{Yaml, YamlLoader};
 
+use alphonse_api as api;
+use api::packet::{TunnelHashMode, TunnelHashModes};
+use api::session::TcpState;
+
 use super::commands::CliArg;
 
 #[derive(Default, Clone)]
@@ -12,11 +18,23 @@ pub struct Config {
     pub rx_backend: String,
     pub verbose_mode: bool,
     pub pkt_channel_size: u32,
-    pub default_timeout: u16,
+    pub default_timeout: u32,
+    /// Seed of Community ID flow hashes
+    pub community_id_seed: u16,
     pub delete: bool,
     pub dpdk_eal_args: Vec<String>,
     pub dry_run: bool,
+    /// CIDR ranges and their labels
+    pub cidr_labels: Vec<(String, String)>,
+    /// Path of the GeoLite2/GeoIP2 Country or City database
+    pub geoip_country: String,
+    /// Path of the GeoLite2/GeoIP2 ASN database
+    pub geoip_asn: String,
+    /// Seconds between checks whether GeoIP databases changed
+    pub geoip_reload_interval: u64,
     pub interfaces: Vec<String>,
+    /// Guess MPLS payload protocol, e.g. Ethernet over MPLS without control word
+    pub mpls_heuristic: bool,
     pub output_threads: u8,
     pub parsers: Vec<String>,
     pub pcap_file: String,
@@ -28,14 +46,42 @@ pub struct Config {
     pub rx_threads: u8,
     /// Max single session packets
     pub ses_max_packets: u16,
+    /// Max sessions held by the session table, 0 for unlimited
+    pub ses_max_sessions: usize,
+    /// Max bytes of all sessions, 0 for unlimited
+    pub ses_max_memory: usize,
+    /// Whether to collect flow feature statistics of each session
+    pub ses_stats: bool,
+    /// Amount of the first packets whose size and direction are recorded by flow statistics
+    pub ses_stats_first_pkts: usize,
     /// Max session connection duration
     pub ses_save_timeout: u16,
     pub ses_threads: u8,
-    pub sctp_timeout: u16,
+    pub sctp_timeout: u32,
     pub tags: Vec<String>,
-    pub tcp_timeout: u16,
+    /// Max bytes buffered for out of order TCP segments of a single session
+    pub tcp_reassembly_memory: usize,
+    pub tcp_timeout: u32,
+    /// Seconds a TCP session lingers after it's closed or reset
+    pub tcp_close_timeout: u32,
     pub timeout_interval: u64,
-    pub udp_timeout: u16,
+    /// Idle timeout overrides of application protocols, the first matching one wins
+    pub protocol_timeouts: Vec<(String, u32)>,
+    /// Idle timeout overrides of ports
+    pub port_timeouts: FnvHashMap<u16, u32>,
+    /// Idle timeout overrides of TCP connection states
+    pub tcp_state_timeouts: FnvHashMap<TcpState, u32>,
+    /// Session hash key mode of each tunnel protocol
+    pub tunnel_hash_modes: TunnelHashModes,
+    pub udp_timeout: u32,
+    /// Directory to write captured packets to, empty to not write packets
+    pub writer_dir: String,
+    /// Max bytes of a written pcap file
+    pub writer_file_size: u64,
+    /// Max seconds of packets in a written pcap file, 0 for unlimited
+    pub writer_file_time: u64,
+    /// Max bytes of all written pcap files, the oldest ones are deleted, 0 for unlimited
+    pub writer_disk: u64,
     pub docs: Vec<Yaml>,
 }
 
@@ -59,6 +105,26 @@ fn get_str(doc: &Yaml, key: &str, default: &str) -> String {
     }
 }
 
+fn get_bool(doc: &Yaml, key: &str, default: bool) -> bool {
+    match doc[key] {
+        Yaml::Boolean(b) => b,
+        Yaml::BadValue => {
+            println!(
+                "Option {} not found or bad boolean value, set {} to {}",
+                key, key, default
+            );
+            default
+        }
+        _ => {
+            println!(
+                "Wrong value type for {}, expecting boolean, set {} to {}",
+                key, key, default
+            );
+            default
+        }
+    }
+}
+
 fn get_integer(doc: &Yaml, key: &str, default: i64, min: i64, max: i64) -> i64 {
     match doc[key] {
         Yaml::Integer(i) => {
@@ -113,7 +179,71 @@ fn get_str_arr(doc: &Yaml, key: &str) -> Vec<String> {
     result
 }
 
+/// Max idle timeout in seconds
+const MAX_TIMEOUT: i64 = 30 * 24 * 3600;
+
+/// Get a hash of timeout overrides, e.g. `timeout.ports: {53: 5, 179: 86400}`
+fn get_timeouts(doc: &Yaml, key: &str) -> Vec<(Yaml, u32)> {
+    let mut result = vec![];
+    match &doc[key] {
+        Yaml::Hash(h) => {
+            for (k, v) in h {
+                match v {
+                    Yaml::Integer(i) if *i >= 0 && *i <= MAX_TIMEOUT => {
+                        result.push((k.clone(), *i as u32))
+                    }
+                    _ => println!(
+                        "Bad timeout value of {:?} in {}, expecting 0 to {} seconds",
+                        k, key, MAX_TIMEOUT
+                    ),
+                }
+            }
+        }
+        Yaml::BadValue => {}
+        _ => println!("Wrong value type for {}, expecting hash", key),
+    }
+    result
+}
+
+fn get_tunnel_hash_mode(doc: &Yaml, key: &str) -> TunnelHashMode {
+    let mode = get_str(doc, key, "inner");
+    match TunnelHashMode::try_from(mode.as_str()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            println!("{}, set {} to inner", e, key);
+            TunnelHashMode::Inner
+        }
+    }
+}
+
+/// Get a hash of string keys and string values, keeping its order
+fn get_str_map(doc: &Yaml, key: &str) -> Vec<(String, String)> {
+    let mut result = vec![];
+    match &doc[key] {
+        Yaml::Hash(h) => {
+            for (k, v) in h {
+                match (k.as_str(), v.as_str()) {
+                    (Some(k), Some(v)) => result.push((k.to_string(), v.to_string())),
+                    _ => println!(
+                        "Bad entry {:?}: {:?} in {}, expecting string to string",
+                        k, v, key
+                    ),
+                }
+            }
+        }
+        Yaml::BadValue => {}
+        _ => println!("Wrong value type for {}, expecting hash", key),
+    }
+    result
+}
+
 impl Config {
+    /// Whether reading packets from pcap files instead of network interfaces
+    #[inline]
+    pub fn offline(&self) -> bool {
+        !self.pcap_file.is_empty() || !self.pcap_dir.is_empty()
+    }
+
     pub fn get_integer(&self, key: &str, default: i64, min: i64, max: i64) -> i64 {
         get_integer(&self.docs[0], key, default, min, max)
     }
@@ -127,16 +257,20 @@ impl Config {
     }
 }
 
-/// Parse command line arguments and set configuration
-pub fn parse_args(root_cmd: clap::App) -> Result<Config> {
+/// Parse the configuration file given by command line arguments, if any
+pub fn parse_config(matches: &clap::ArgMatches) -> Result<Config> {
     let mut config: Config = Default::default();
-    let matches = root_cmd.get_matches();
-
     if let Some(config_file) = matches.value_of("config") {
         parse_config_file(config_file, &mut config)?;
     }
+    Ok(config)
+}
 
-    set_config_by_cli_args(&mut config, &matches);
+/// Parse command line arguments and set configuration
+pub fn parse_args(matches: &clap::ArgMatches) -> Result<Config> {
+    let mut config = parse_config(matches)?;
+
+    set_config_by_cli_args(&mut config, matches);
 
     if (config.pcap_dir.is_empty() || config.pcap_file.is_empty()) && config.interfaces.is_empty() {
         return Err(anyhow!(
@@ -166,14 +300,59 @@ fn parse_config_file(config_file: &str, config: &mut Config) -> Result<()> {
     config.pkt_channel_size =
         get_integer(doc, "channel.pkt.size", 1000000, 100000, 10000000) as u32;
     config.timeout_interval = get_integer(doc, "timeout.interval", 1, 1, 10) as u64;
-    config.default_timeout = get_integer(doc, "timeout.default", 60, 10, 180) as u16;
-    config.tcp_timeout = get_integer(doc, "timeout.tcp", 60, 10, 180) as u16;
-    config.udp_timeout = get_integer(doc, "timeout.udp", 60, 10, 180) as u16;
-    config.sctp_timeout = get_integer(doc, "timeout.sctp", 60, 10, 180) as u16;
+    config.default_timeout = get_integer(doc, "timeout.default", 60, 1, MAX_TIMEOUT) as u32;
+    config.tcp_timeout = get_integer(doc, "timeout.tcp", 60, 1, MAX_TIMEOUT) as u32;
+    config.tcp_close_timeout = get_integer(doc, "timeout.tcp.close", 5, 0, MAX_TIMEOUT) as u32;
+    config.udp_timeout = get_integer(doc, "timeout.udp", 60, 1, MAX_TIMEOUT) as u32;
+    config.sctp_timeout = get_integer(doc, "timeout.sctp", 60, 1, MAX_TIMEOUT) as u32;
+    for (key, timeout) in get_timeouts(doc, "timeout.protocols") {
+        match key {
+            Yaml::String(protocol) => config.protocol_timeouts.push((protocol, timeout)),
+            _ => println!("Wrong key type of timeout.protocols, expecting string"),
+        }
+    }
+    for (key, timeout) in get_timeouts(doc, "timeout.ports") {
+        match key {
+            Yaml::Integer(port) if port > 0 && port <= u16::MAX as i64 => {
+                config.port_timeouts.insert(port as u16, timeout);
+            }
+            _ => println!("Wrong key of timeout.ports, expecting port number"),
+        }
+    }
+    for (key, timeout) in get_timeouts(doc, "timeout.tcp.states") {
+        match key.as_str().map(TcpState::try_from) {
+            Some(Ok(state)) => {
+                config.tcp_state_timeouts.insert(state, timeout);
+            }
+            Some(Err(e)) => println!("{} in timeout.tcp.states", e),
+            None => println!("Wrong key type of timeout.tcp.states, expecting string"),
+        }
+    }
     config.ses_save_timeout = get_integer(doc, "timeout.ses.save", 180, 60, 360) as u16;
 
+    config.tcp_reassembly_memory =
+        get_integer(doc, "tcp.reassembly.memory", 1048576, 0, i32::MAX as i64) as usize;
+
     config.ses_max_packets =
         get_integer(doc, "ses.max.packets", 10000, 1000, u16::MAX as i64) as u16;
+    config.community_id_seed = get_integer(doc, "community.id.seed", 0, 0, u16::MAX as i64) as u16;
+    config.ses_stats = get_bool(doc, "ses.stats", false);
+    config.ses_stats_first_pkts = get_integer(doc, "ses.stats.first.packets", 20, 0, 1000) as usize;
+    config.ses_max_sessions =
+        get_integer(doc, "ses.max.sessions", 1000000, 0, i32::MAX as i64) as usize;
+    config.ses_max_memory = get_integer(doc, "ses.max.memory", 4294967296, 0, i64::MAX) as usize;
+
+    config.writer_dir = get_str(doc, "writer.pcap.dir", "");
+    config.writer_file_size =
+        get_integer(doc, "writer.pcap.file.size", 1073741824, 1048576, i64::MAX) as u64;
+    config.writer_file_time = get_integer(doc, "writer.pcap.file.time", 0, 0, i64::MAX) as u64;
+    config.writer_disk = get_integer(doc, "writer.pcap.disk", 0, 0, i64::MAX) as u64;
+
+    config.cidr_labels = get_str_map(doc, "cidr.labels");
+
+    config.geoip_country = get_str(doc, "geoip.country", "");
+    config.geoip_asn = get_str(doc, "geoip.asn", "");
+    config.geoip_reload_interval = get_integer(doc, "geoip.reload.interval", 60, 0, 86400) as u64;
 
     config.pkt_threads = get_integer(doc, "threads.pkt", 1, 1, 24) as u8;
     config.rx_threads = get_integer(doc, "threads.rx", 1, 1, 24) as u8;
@@ -193,6 +372,21 @@ fn parse_config_file(config_file: &str, config: &mut Config) -> Result<()> {
         }
     };
 
+    let modes = &mut config.tunnel_hash_modes;
+    modes.gre = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.gre");
+    modes.gtp = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.gtp");
+    modes.l2tp = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.l2tp");
+    modes.mpls = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.mpls");
+    modes.ppp = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.ppp");
+    modes.pppoe = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.pppoe");
+    modes.vxlan = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.vxlan");
+    modes.ipip = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.ipip");
+    modes.ip6in4 = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.6in4");
+    modes.teredo = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.teredo");
+    modes.wireguard = get_tunnel_hash_mode(doc, "pkt.hash.tunnel.wireguard");
+
+    config.mpls_heuristic = get_bool(doc, "pkt.mpls.heuristic", false);
+
     config.parsers = get_str_arr(doc, "parsers");
     config.interfaces = get_str_arr(doc, "interfaces");
 
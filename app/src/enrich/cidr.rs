@@ -0,0 +1,230 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::Config;
+
+/// Mask of the leading len bits
+#[inline]
+fn mask(len: u8) -> u128 {
+    match len {
+        0 => 0,
+        len => !0u128 << (128 - len as u32),
+    }
+}
+
+/// The bit following the leading len bits
+#[inline]
+fn bit(key: u128, len: u8) -> usize {
+    (key >> (127 - len as u32)) as usize & 1
+}
+
+struct Node<T> {
+    /// Prefix bits, left aligned
+    prefix: u128,
+    len: u8,
+    value: Option<T>,
+    children: [Option<Box<Node<T>>>; 2],
+}
+
+impl<T> Node<T> {
+    fn new(prefix: u128, len: u8, value: Option<T>) -> Box<Self> {
+        Box::new(Node {
+            prefix: prefix & mask(len),
+            len,
+            value,
+            children: [None, None],
+        })
+    }
+
+    #[inline]
+    fn contains(&self, key: u128) -> bool {
+        (key ^ self.prefix) & mask(self.len) == 0
+    }
+
+    fn insert(self: &mut Box<Self>, prefix: u128, len: u8, value: T) {
+        let common = ((prefix ^ self.prefix).leading_zeros() as u8)
+            .min(len)
+            .min(self.len);
+        if common == self.len {
+            if len == self.len {
+                self.value = Some(value);
+                return;
+            }
+            match &mut self.children[bit(prefix, self.len)] {
+                Some(child) => child.insert(prefix, len, value),
+                child => *child = Some(Node::new(prefix, len, Some(value))),
+            }
+            return;
+        }
+
+        // split this node at the common prefix
+        let mut parent = Node::new(prefix, common, None);
+        if len == common {
+            parent.value = Some(value);
+        } else {
+            parent.children[bit(prefix, common)] = Some(Node::new(prefix, len, Some(value)));
+        }
+        let node = std::mem::replace(self, parent);
+        let side = bit(node.prefix, common);
+        self.children[side] = Some(node);
+    }
+}
+
+/// Path compressed binary trie of IP prefixes
+///
+/// Nodes only exist where prefixes branch or hold a value, so lookups visit at
+/// most one node per distinct prefix on the path instead of one per bit.
+pub struct PrefixTrie<T> {
+    root: Box<Node<T>>,
+}
+
+impl<T> Default for PrefixTrie<T> {
+    fn default() -> Self {
+        PrefixTrie {
+            root: Node::new(0, 0, None),
+        }
+    }
+}
+
+/// IPv4 addresses are keyed as IPv4-mapped IPv6 addresses
+fn key(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(ip) => (u128::from(ip.to_ipv6_mapped()), 96),
+        IpAddr::V6(ip) => (u128::from(ip), 0),
+    }
+}
+
+impl<T> PrefixTrie<T> {
+    /// Insert a value at a prefix, replacing the prefix's previous value
+    pub fn insert(&mut self, ip: IpAddr, len: u8, value: T) {
+        let (key, offset) = key(ip);
+        self.root.insert(key, offset + len, value);
+    }
+
+    /// Values of all prefixes containing the ip address, from the shortest prefix to the longest
+    pub fn matches(&self, ip: IpAddr) -> Vec<&T> {
+        let (key, _) = key(ip);
+        let mut result = vec![];
+        let mut node = &self.root;
+        while node.contains(key) {
+            if let Some(value) = &node.value {
+                result.push(value);
+            }
+            if node.len == 128 {
+                break;
+            }
+            match &node.children[bit(key, node.len)] {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        result
+    }
+}
+
+/// Parse a CIDR like 10.1.0.0/16, a bare ip address is a full length prefix
+pub fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let mut parts = cidr.splitn(2, '/');
+    let ip = parts
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .parse::<IpAddr>()
+        .map_err(|_| anyhow!("Invalid CIDR: {}", cidr))?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let len = match parts.next() {
+        None => max,
+        Some(len) => match len.trim().parse::<u8>() {
+            Ok(len) if len <= max => len,
+            _ => return Err(anyhow!("Invalid CIDR prefix length: {}", cidr)),
+        },
+    };
+    Ok((ip, len))
+}
+
+/// Labels of configured CIDR ranges
+#[derive(Default)]
+pub struct CidrLabels {
+    trie: PrefixTrie<String>,
+}
+
+impl CidrLabels {
+    /// Build the configured labels, None if no label is configured
+    pub fn new(cfg: &Config) -> Option<Self> {
+        if cfg.cidr_labels.is_empty() {
+            return None;
+        }
+
+        let mut labels = CidrLabels::default();
+        for (cidr, label) in &cfg.cidr_labels {
+            match parse_cidr(cidr) {
+                Ok((ip, len)) => labels.trie.insert(ip, len, label.clone()),
+                Err(e) => println!("{} in cidr.labels", e),
+            }
+        }
+        Some(labels)
+    }
+
+    /// Labels of all ranges containing the ip address, from the widest range to the narrowest
+    pub fn lookup(&self, ip: IpAddr) -> Vec<String> {
+        self.trie.matches(ip).into_iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_trie() {
+        let mut trie = PrefixTrie::default();
+        for (cidr, value) in [
+            ("10.0.0.0/8", 1),
+            ("10.1.0.0/16", 2),
+            ("10.9.0.0/16", 3),
+            ("10.1.2.3", 4),
+            ("2001:db8::/32", 5),
+            ("0.0.0.0/0", 6),
+        ]
+        .iter()
+        {
+            let (ip, len) = parse_cidr(cidr).unwrap();
+            trie.insert(ip, len, *value);
+        }
+        // replace the value of an existing prefix
+        trie.insert(ip("10.9.0.0"), 16, 7);
+
+        assert_eq!(trie.matches(ip("10.1.2.3")), vec![&6, &1, &2, &4]);
+        assert_eq!(trie.matches(ip("10.1.2.4")), vec![&6, &1, &2]);
+        assert_eq!(trie.matches(ip("10.9.255.255")), vec![&6, &1, &7]);
+        assert_eq!(trie.matches(ip("10.10.0.0")), vec![&6, &1]);
+        assert_eq!(trie.matches(ip("192.168.0.1")), vec![&6]);
+        assert_eq!(trie.matches(ip("2001:db8::1")), vec![&5]);
+        assert!(trie.matches(ip("2001:db9::1")).is_empty());
+    }
+
+    #[test]
+    fn cidr_labels() {
+        assert!(parse_cidr("10.1.0.0/33").is_err());
+        assert!(parse_cidr("10.1.0/16").is_err());
+        assert_eq!(parse_cidr("::1").unwrap(), (ip("::1"), 128));
+
+        let mut cfg = Config::default();
+        assert!(CidrLabels::new(&cfg).is_none());
+        cfg.cidr_labels = vec![
+            ("10.1.0.0/16".to_string(), "dmz".to_string()),
+            ("10.9.0.0/16".to_string(), "scada".to_string()),
+            ("10.0.0.0/8".to_string(), "internal".to_string()),
+            ("bad".to_string(), "bad".to_string()),
+        ];
+        let labels = CidrLabels::new(&cfg).unwrap();
+        assert_eq!(labels.lookup(ip("10.9.0.1")), vec!["internal", "scada"]);
+        assert_eq!(labels.lookup(ip("10.2.0.1")), vec!["internal"]);
+        assert!(labels.lookup(ip("8.8.8.8")).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Result};
+use maxminddb::{geoip2, Reader};
+
+use alphonse_api as api;
+use api::session::Geo;
+
+use crate::config::Config;
+
+/// A MaxMind database file
+struct Database {
+    path: PathBuf,
+    /// Modification time and size of the loaded file
+    version: (SystemTime, u64),
+    reader: Reader<Vec<u8>>,
+}
+
+impl Database {
+    fn open(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let version = file_version(&path)?;
+        let reader = Reader::open_readfile(&path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+        Ok(Database {
+            path,
+            version,
+            reader,
+        })
+    }
+
+    /// Reopen the database if its file changed, returns whether it's reloaded
+    fn reload(&mut self) -> Result<bool> {
+        let version = file_version(&self.path)?;
+        if version == self.version {
+            return Ok(false);
+        }
+        self.reader = Reader::open_readfile(&self.path).map_err(|e| anyhow!("{}", e))?;
+        self.version = version;
+        Ok(true)
+    }
+}
+
+fn file_version(path: &PathBuf) -> Result<(SystemTime, u64)> {
+    let meta =
+        std::fs::metadata(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+/// Geolocation and ASN lookup from local GeoLite2/GeoIP2 databases
+///
+/// Databases are reloaded once their files change, a database failing to reload
+/// keeps serving its previous version.
+pub struct GeoDb {
+    country: Option<Database>,
+    asn: Option<Database>,
+    reload_interval: Duration,
+    last_check: Instant,
+}
+
+impl GeoDb {
+    /// Open the configured databases, None if no database is configured
+    pub fn new(cfg: &Config) -> Result<Option<Self>> {
+        if cfg.geoip_country.is_empty() && cfg.geoip_asn.is_empty() {
+            return Ok(None);
+        }
+
+        let open = |path: &String| match path.is_empty() {
+            true => Ok(None),
+            false => Database::open(path).map(Some),
+        };
+        Ok(Some(GeoDb {
+            country: open(&cfg.geoip_country)?,
+            asn: open(&cfg.geoip_asn)?,
+            reload_interval: Duration::from_secs(cfg.geoip_reload_interval),
+            last_check: Instant::now(),
+        }))
+    }
+
+    /// Reload changed databases, checked at most once per reload interval
+    pub fn reload(&mut self) {
+        if self.last_check.elapsed() < self.reload_interval {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        for db in self.country.iter_mut().chain(self.asn.iter_mut()) {
+            match db.reload() {
+                Ok(true) => println!("Reloaded GeoIP database {}", db.path.display()),
+                Ok(false) => {}
+                Err(e) => eprintln!(
+                    "Failed to reload GeoIP database {}: {}",
+                    db.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Look up an ip address, None if it's in none of the databases
+    pub fn lookup(&self, ip: IpAddr) -> Option<Geo> {
+        let mut geo = Geo::default();
+
+        if let Some(db) = &self.country {
+            // Country databases are decoded as City records without city information
+            if let Ok(city) = db.reader.lookup::<geoip2::City>(ip) {
+                let registered = city.registered_country;
+                geo.country = city
+                    .country
+                    .and_then(|c| c.iso_code)
+                    .or_else(|| registered.and_then(|c| c.iso_code))
+                    .map(String::from);
+                geo.city = city
+                    .city
+                    .and_then(|c| c.names)
+                    .and_then(|names| names.get("en").map(|name| name.to_string()));
+            }
+        }
+
+        if let Some(db) = &self.asn {
+            if let Ok(asn) = db.reader.lookup::<geoip2::Asn>(ip) {
+                geo.asn = asn.autonomous_system_number;
+                geo.org = asn.autonomous_system_organization.map(String::from);
+            }
+        }
+
+        if geo == Geo::default() {
+            None
+        } else {
+            Some(geo)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut buf = if s.len() < 29 {
+            vec![0x40 | s.len() as u8]
+        } else {
+            vec![0x40 | 29, (s.len() - 29) as u8]
+        };
+        buf.extend_from_slice(s.as_bytes());
+        buf
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut buf = vec![0xe0 | entries.len() as u8];
+        for (key, value) in entries {
+            buf.extend(string(key));
+            buf.extend_from_slice(value);
+        }
+        buf
+    }
+
+    fn uint16(n: u16) -> Vec<u8> {
+        let mut buf = vec![0xa2];
+        buf.extend_from_slice(&n.to_be_bytes());
+        buf
+    }
+
+    fn uint32(n: u32) -> Vec<u8> {
+        let mut buf = vec![0xc4];
+        buf.extend_from_slice(&n.to_be_bytes());
+        buf
+    }
+
+    /// An IPv4 MaxMind database holding a record for first_octet.0.0.0/8
+    fn mmdb(first_octet: u8, record: Vec<u8>) -> Vec<u8> {
+        let node_count = 8u32;
+        let mut buf = vec![];
+        for bit in 0..8 {
+            let not_found = node_count;
+            let next = if bit == 7 {
+                node_count + 16
+            } else {
+                bit as u32 + 1
+            };
+            let (left, right) = match first_octet >> (7 - bit) & 1 {
+                0 => (next, not_found),
+                _ => (not_found, next),
+            };
+            buf.extend_from_slice(&left.to_be_bytes()[1..]);
+            buf.extend_from_slice(&right.to_be_bytes()[1..]);
+        }
+        buf.extend_from_slice(&[0; 16]);
+        buf.extend(record);
+
+        buf.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+        buf.extend(map(&[
+            ("binary_format_major_version", uint16(2)),
+            ("binary_format_minor_version", uint16(0)),
+            ("build_epoch", vec![0x08, 0x02, 0, 0, 0, 0, 0, 0, 0, 0]),
+            ("database_type", string("Test")),
+            ("description", map(&[])),
+            ("ip_version", uint16(4)),
+            ("languages", vec![0x00, 0x04]),
+            ("node_count", uint32(node_count)),
+            ("record_size", uint16(24)),
+        ]));
+        buf
+    }
+
+    fn city(country: &str, city: &str) -> Vec<u8> {
+        map(&[
+            ("city", map(&[("names", map(&[("en", string(city))]))])),
+            ("country", map(&[("iso_code", string(country))])),
+        ])
+    }
+
+    #[test]
+    fn lookup_and_reload() {
+        let dir = std::env::temp_dir().join(format!("alphonse-geo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let country = dir.join("country.mmdb");
+        let asn = dir.join("asn.mmdb");
+        std::fs::write(&country, mmdb(1, city("AU", "Sydney"))).unwrap();
+        std::fs::write(
+            &asn,
+            mmdb(
+                1,
+                map(&[
+                    ("autonomous_system_number", uint32(13335)),
+                    ("autonomous_system_organization", string("Cloudflare, Inc.")),
+                ]),
+            ),
+        )
+        .unwrap();
+
+        let mut cfg = Config::default();
+        assert!(GeoDb::new(&cfg).unwrap().is_none());
+        cfg.geoip_country = dir.join("missing.mmdb").to_str().unwrap().to_string();
+        assert!(GeoDb::new(&cfg).is_err());
+
+        cfg.geoip_country = country.to_str().unwrap().to_string();
+        cfg.geoip_asn = asn.to_str().unwrap().to_string();
+        let mut db = GeoDb::new(&cfg).unwrap().unwrap();
+        let geo = db.lookup("1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(geo.country.as_deref(), Some("AU"));
+        assert_eq!(geo.city.as_deref(), Some("Sydney"));
+        assert_eq!(geo.asn, Some(13335));
+        assert_eq!(geo.org.as_deref(), Some("Cloudflare, Inc."));
+        assert_eq!(db.lookup("2.1.1.1".parse().unwrap()), None);
+
+        // a changed database is reloaded, a broken one keeps the previous version
+        std::fs::write(&country, mmdb(2, city("FR", "Paris"))).unwrap();
+        db.reload();
+        assert_eq!(
+            db.lookup("2.1.1.1".parse().unwrap())
+                .unwrap()
+                .country
+                .as_deref(),
+            Some("FR")
+        );
+        assert!(db
+            .lookup("1.1.1.1".parse().unwrap())
+            .unwrap()
+            .country
+            .is_none());
+        std::fs::write(&country, b"broken").unwrap();
+        db.reload();
+        assert_eq!(
+            db.lookup("2.1.1.1".parse().unwrap())
+                .unwrap()
+                .city
+                .as_deref(),
+            Some("Paris")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+
+use alphonse_api as api;
+use api::session::Session;
+
+use crate::config::Config;
+
+mod cidr;
+mod geo;
+
+pub use cidr::CidrLabels;
+pub use geo::GeoDb;
+
+/// Adds configured tags and information looked up from local databases to sessions
+/// before they're saved
+#[derive(Default)]
+pub struct Enricher {
+    /// Tags added to all sessions
+    tags: Vec<String>,
+    labels: Option<CidrLabels>,
+    geo: Option<GeoDb>,
+}
+
+impl Enricher {
+    pub fn new(cfg: &Config) -> Result<Self> {
+        Ok(Enricher {
+            tags: cfg.tags.clone(),
+            labels: CidrLabels::new(cfg),
+            geo: GeoDb::new(cfg)?,
+        })
+    }
+
+    pub fn enrich(&mut self, ses: &mut Session) {
+        for tag in &self.tags {
+            ses.add_tag(tag);
+        }
+        if let Some(labels) = &self.labels {
+            ses.src_labels = ses.src_ip.map(|ip| labels.lookup(ip)).unwrap_or_default();
+            ses.dst_labels = ses.dst_ip.map(|ip| labels.lookup(ip)).unwrap_or_default();
+        }
+        if let Some(geo) = self.geo.as_mut() {
+            geo.reload();
+            ses.src_geo = ses.src_ip.and_then(|ip| geo.lookup(ip)).map(Box::new);
+            ses.dst_geo = ses.dst_ip.and_then(|ip| geo.lookup(ip)).map(Box::new);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrich() {
+        let mut cfg = Config::default();
+        cfg.tags = vec!["sensor-1".to_string()];
+        cfg.cidr_labels = vec![("10.9.0.0/16".to_string(), "scada".to_string())];
+        let mut enricher = Enricher::new(&cfg).unwrap();
+
+        let mut ses = Session::new();
+        ses.src_ip = Some("10.9.0.1".parse().unwrap());
+        ses.dst_ip = Some("10.1.0.1".parse().unwrap());
+        enricher.enrich(&mut ses);
+        assert_eq!(ses.src_labels, vec!["scada"]);
+        assert!(ses.dst_labels.is_empty());
+        let json = serde_json::to_value(&ses).unwrap();
+        assert_eq!(json["tags"], serde_json::json!(["sensor-1"]));
+    }
+}
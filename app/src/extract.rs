@@ -0,0 +1,367 @@
+//! Extract packets of a session from a pcap file
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
+
+use alphonse_api as api;
+use api::packet::{Packet as PacketTrait, PacketHashKey, Protocol, TunnelHashModes};
+use api::session::Session;
+
+use crate::commands::ExtractArg;
+use crate::config::Config;
+use crate::packet::Parser;
+use crate::rx::libpcap::Packet;
+
+/// IP protocol numbers of ICMP, which isn't decoded into the transport layer
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+
+/// Endpoints of a session, matched against session keys regardless of direction
+struct Tuple {
+    /// TCP, UDP, SCTP or ICMP
+    proto: Protocol,
+    /// Larger ip address first, the same as session keys
+    ips: (IpAddr, IpAddr),
+    /// Ports given with the ip addresses, 0 for any port
+    ports: (u16, u16),
+}
+
+impl Tuple {
+    fn matches(&self, key: &PacketHashKey, pkt: &dyn PacketTrait) -> bool {
+        let proto = match self.proto {
+            Protocol::ICMP => {
+                key.trans_proto == Protocol::UNKNOWN
+                    && (pkt.ip_protocol() == ICMP || pkt.ip_protocol() == ICMPV6)
+            }
+            proto => key.trans_proto == proto,
+        };
+        if !proto || (key.src_ip, key.dst_ip) != self.ips {
+            return false;
+        }
+
+        // key ports are sorted, larger one first
+        match self.ports {
+            (0, 0) => true,
+            (port, 0) | (0, port) => key.src_port == port || key.dst_port == port,
+            (a, b) => (a.max(b), a.min(b)) == (key.src_port, key.dst_port),
+        }
+    }
+}
+
+/// Which packets to extract, all given conditions must match
+#[derive(Default)]
+struct Selector {
+    /// Endpoints of the session
+    tuple: Option<Tuple>,
+    community_id: Option<String>,
+    /// Time window in microseconds, start inclusive and end exclusive
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Selector {
+    fn from_args(args: &clap::ArgMatches) -> Result<Self> {
+        let mut selector = Selector::default();
+
+        if let Some(proto) = args.value_of(ExtractArg::Proto.as_str()) {
+            let proto = match proto.to_lowercase().as_str() {
+                "tcp" => Protocol::TCP,
+                "udp" => Protocol::UDP,
+                "sctp" => Protocol::SCTP,
+                "icmp" => Protocol::ICMP,
+                _ => return Err(anyhow!("Unknown protocol: {}", proto)),
+            };
+            let (src_ip, src_port) = parse_endpoint(args.value_of(ExtractArg::Src.as_str()))?;
+            let (dst_ip, dst_port) = parse_endpoint(args.value_of(ExtractArg::Dst.as_str()))?;
+            if src_ip.is_ipv4() != dst_ip.is_ipv4() {
+                return Err(anyhow!("Endpoints of different IP versions"));
+            }
+            if proto == Protocol::ICMP && (src_port != 0 || dst_port != 0) {
+                return Err(anyhow!("ICMP sessions have no ports"));
+            }
+            selector.tuple = Some(Tuple {
+                proto,
+                ips: (src_ip.max(dst_ip), src_ip.min(dst_ip)),
+                ports: (src_port, dst_port),
+            });
+        }
+
+        selector.community_id = args
+            .value_of(ExtractArg::CommunityId.as_str())
+            .map(String::from);
+        if let Some(start) = args.value_of(ExtractArg::Start.as_str()) {
+            selector.start = Some(parse_time(start)?);
+        }
+        if let Some(end) = args.value_of(ExtractArg::End.as_str()) {
+            selector.end = Some(parse_time(end)?);
+        }
+
+        if selector.tuple.is_none()
+            && selector.community_id.is_none()
+            && selector.start.is_none()
+            && selector.end.is_none()
+        {
+            return Err(anyhow!(
+                "No session selector given, expecting a 5-tuple, a Community ID or a time window"
+            ));
+        }
+        Ok(selector)
+    }
+}
+
+/// Parse an endpoint, e.g. 10.0.0.1:80, [2001:db8::1]:80 or a bare ip address of any port
+fn parse_endpoint(endpoint: Option<&str>) -> Result<(IpAddr, u16)> {
+    let endpoint = endpoint.ok_or_else(|| anyhow!("Missing session endpoint"))?;
+    if let Ok(addr) = endpoint.parse::<SocketAddr>() {
+        return Ok((addr.ip(), addr.port()));
+    }
+    match endpoint.parse::<IpAddr>() {
+        Ok(ip) => Ok((ip, 0)),
+        Err(_) => Err(anyhow!("Invalid session endpoint: {}", endpoint)),
+    }
+}
+
+/// Parse a time of unix seconds or RFC 3339 into microseconds
+fn parse_time(time: &str) -> Result<i64> {
+    if let Ok(secs) = time.parse::<f64>() {
+        return Ok((secs * 1000000.0) as i64);
+    }
+    match chrono::DateTime::parse_from_rfc3339(time) {
+        Ok(t) => Ok(t.timestamp() * 1000000 + t.timestamp_subsec_micros() as i64),
+        Err(_) => Err(anyhow!("Invalid time: {}", time)),
+    }
+}
+
+/// Matches packets against a selector
+struct Extractor {
+    selector: Selector,
+    parser: Parser,
+    tunnel_hash_modes: TunnelHashModes,
+    community_id_seed: u16,
+    /// Whether each session's Community ID matches, decided by the session's first packet
+    community_ids: FnvHashMap<PacketHashKey, bool>,
+}
+
+impl Extractor {
+    fn new(cfg: &Config, selector: Selector) -> Self {
+        Extractor {
+            selector,
+            parser: Parser::new(cfg),
+            tunnel_hash_modes: cfg.tunnel_hash_modes,
+            community_id_seed: cfg.community_id_seed,
+            community_ids: FnvHashMap::default(),
+        }
+    }
+
+    fn matches(&mut self, pkt: &mut dyn PacketTrait) -> bool {
+        if self.selector.tuple.is_some() || self.selector.community_id.is_some() {
+            // Decode errors are ignored the same way the packet threads do
+            let _ = self.parser.parse_pkt(pkt);
+            let key = PacketHashKey::new(pkt, &self.tunnel_hash_modes);
+
+            if let Some(tuple) = &self.selector.tuple {
+                if !tuple.matches(&key, pkt) {
+                    return false;
+                }
+            }
+
+            if let Some(id) = &self.selector.community_id {
+                let seed = self.community_id_seed;
+                let matched = *self.community_ids.entry(key).or_insert_with(|| {
+                    let mut ses = Session::new();
+                    ses.update(pkt);
+                    ses.update_community_id(pkt, seed);
+                    ses.community_id.as_ref() == Some(id)
+                });
+                if !matched {
+                    return false;
+                }
+            }
+        }
+
+        let ts = pkt.ts().tv_sec as i64 * 1000000 + pkt.ts().tv_usec as i64;
+        self.selector.start.map_or(true, |start| ts >= start)
+            && self.selector.end.map_or(true, |end| ts < end)
+    }
+}
+
+/// Run the extract subcommand
+pub fn run(cfg: &Config, args: &clap::ArgMatches) -> Result<()> {
+    let selector = Selector::from_args(args)?;
+    let input = args.value_of(ExtractArg::Input.as_str()).unwrap();
+    let output = args.value_of(ExtractArg::Output.as_str()).unwrap();
+
+    let mut cap = pcap::Capture::from_file(input)?;
+    let link_type = cap.get_datalink();
+    let mut savefile = pcap::Capture::dead(link_type)?.savefile(output)?;
+    let mut extractor = Extractor::new(cfg, selector);
+
+    let mut extracted = 0;
+    loop {
+        let raw = match cap.next() {
+            Ok(raw) => raw,
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let mut pkt = Packet::new(&raw, link_type.0 as u16);
+        if extractor.matches(&mut pkt) {
+            savefile.write(&raw);
+            extracted += 1;
+        }
+    }
+
+    println!("{} packets extracted to {}", extracted, output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use api::utils::packet::Packet as TestPacket;
+
+    fn tcp_pkt(src: ([u8; 4], u16), dst: ([u8; 4], u16), sec: i64) -> TestPacket {
+        let mut raw = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08,
+            0x00, // ethernet
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, // ipv4
+        ];
+        raw.extend_from_slice(&src.0);
+        raw.extend_from_slice(&dst.0);
+        raw.extend_from_slice(&src.1.to_be_bytes());
+        raw.extend_from_slice(&dst.1.to_be_bytes());
+        raw.extend_from_slice(&[0; 8]);
+        raw.extend_from_slice(&[0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        let mut pkt = TestPacket::default();
+        pkt.caplen = raw.len() as u32;
+        pkt.raw = Box::new(raw);
+        pkt.ts.tv_sec = sec as libc::time_t;
+        pkt
+    }
+
+    fn icmp_pkt(src: [u8; 4], dst: [u8; 4]) -> TestPacket {
+        let mut raw = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08,
+            0x00, // ethernet
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x01, 0x00, 0x00, // ipv4
+        ];
+        raw.extend_from_slice(&src);
+        raw.extend_from_slice(&dst);
+        raw.extend_from_slice(&[0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01]); // echo request
+        let mut pkt = TestPacket::default();
+        pkt.caplen = raw.len() as u32;
+        pkt.raw = Box::new(raw);
+        pkt
+    }
+
+    fn selector(args: &[&str]) -> Result<Selector> {
+        let mut argv = vec!["alphonse", "extract", "in.pcap", "-w", "out.pcap"];
+        argv.extend_from_slice(args);
+        let matches = crate::commands::new_root_command().get_matches_from(argv);
+        Selector::from_args(
+            matches
+                .subcommand_matches(crate::commands::EXTRACT)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn five_tuple() {
+        let selector = selector(&[
+            "--proto",
+            "tcp",
+            "--src",
+            "10.0.0.1:50000",
+            "--dst",
+            "10.0.0.2:80",
+        ])
+        .unwrap();
+        let mut extractor = Extractor::new(&Config::default(), selector);
+
+        let client = ([10, 0, 0, 1], 50000);
+        let server = ([10, 0, 0, 2], 80);
+        assert!(extractor.matches(&mut tcp_pkt(client, server, 0)));
+        assert!(extractor.matches(&mut tcp_pkt(server, client, 0)));
+        assert!(!extractor.matches(&mut tcp_pkt(([10, 0, 0, 1], 50001), server, 0)));
+    }
+
+    #[test]
+    fn any_port() {
+        let selector = selector(&[
+            "--proto",
+            "tcp",
+            "--src",
+            "10.0.0.2",
+            "--dst",
+            "10.0.0.1:50000",
+        ])
+        .unwrap();
+        let mut extractor = Extractor::new(&Config::default(), selector);
+
+        let client = ([10, 0, 0, 1], 50000);
+        assert!(extractor.matches(&mut tcp_pkt(client, ([10, 0, 0, 2], 80), 0)));
+        assert!(extractor.matches(&mut tcp_pkt(([10, 0, 0, 2], 443), client, 0)));
+        assert!(!extractor.matches(&mut tcp_pkt(([10, 0, 0, 1], 50001), ([10, 0, 0, 2], 80), 0)));
+        assert!(!extractor.matches(&mut tcp_pkt(client, ([10, 0, 0, 3], 80), 0)));
+    }
+
+    #[test]
+    fn icmp() {
+        let selector =
+            selector(&["--proto", "icmp", "--src", "10.0.0.1", "--dst", "10.0.0.2"]).unwrap();
+        let mut extractor = Extractor::new(&Config::default(), selector);
+
+        assert!(extractor.matches(&mut icmp_pkt([10, 0, 0, 1], [10, 0, 0, 2])));
+        assert!(extractor.matches(&mut icmp_pkt([10, 0, 0, 2], [10, 0, 0, 1])));
+        assert!(!extractor.matches(&mut icmp_pkt([10, 0, 0, 1], [10, 0, 0, 3])));
+        assert!(!extractor.matches(&mut tcp_pkt(([10, 0, 0, 1], 50000), ([10, 0, 0, 2], 80), 0)));
+    }
+
+    #[test]
+    fn community_id_and_time() {
+        let client = ([10, 0, 0, 1], 50000);
+        let server = ([10, 0, 0, 2], 80);
+        let id = api::utils::community_id::community_id(
+            0,
+            IpAddr::from(client.0),
+            IpAddr::from(server.0),
+            6,
+            Some((client.1, server.1)),
+        );
+        let selector = selector(&[
+            "--community-id",
+            &id,
+            "--start",
+            "100",
+            "--end",
+            "1970-01-01T00:03:20Z",
+        ])
+        .unwrap();
+        assert_eq!(selector.end, Some(200000000));
+        let mut extractor = Extractor::new(&Config::default(), selector);
+
+        assert!(!extractor.matches(&mut tcp_pkt(client, server, 99)));
+        assert!(extractor.matches(&mut tcp_pkt(server, client, 100)));
+        assert!(!extractor.matches(&mut tcp_pkt(client, server, 200)));
+        assert!(!extractor.matches(&mut tcp_pkt(client, ([10, 0, 0, 3], 80), 150)));
+    }
+
+    #[test]
+    fn bad_selectors() {
+        assert!(selector(&[]).is_err());
+        assert!(selector(&["--proto", "gre", "--src", "10.0.0.1", "--dst", "10.0.0.2"]).is_err());
+        assert!(selector(&["--proto", "udp", "--src", "10.0.0.1", "--dst", "::1"]).is_err());
+        assert!(selector(&[
+            "--proto",
+            "icmp",
+            "--src",
+            "10.0.0.1:8",
+            "--dst",
+            "10.0.0.2"
+        ])
+        .is_err());
+        assert!(selector(&["--start", "yesterday"]).is_err());
+    }
+}
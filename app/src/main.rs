@@ -16,6 +16,8 @@ use api::{classifiers, parsers::ParserID};
 
 mod commands;
 mod config;
+mod enrich;
+mod extract;
 mod packet;
 mod rx;
 mod stats;
@@ -26,7 +28,7 @@ fn start_rx<'a>(
     cfg: Arc<config::Config>,
     sender: Sender<Box<dyn Packet>>,
 ) -> Result<Vec<JoinHandle<Result<()>>>> {
-    if !cfg.pcap_file.is_empty() {
+    if cfg.offline() {
         return (rx::files::UTILITY.start)(exit, cfg, sender);
     }
 
@@ -39,14 +41,14 @@ fn start_rx<'a>(
 }
 
 fn main() -> Result<()> {
-    let root_cmd = commands::new_root_command();
-    let mut cfg = config::parse_args(root_cmd)?;
+    let matches = commands::new_root_command().get_matches();
+    if let Some(args) = matches.subcommand_matches(commands::EXTRACT) {
+        let cfg = config::parse_config(&matches)?;
+        return extract::run(&cfg, args);
+    }
+    let mut cfg = config::parse_args(&matches)?;
     let exit = Arc::new(AtomicBool::new(false));
 
-    let session_table = Arc::new(dashmap::DashMap::with_capacity_and_hasher(
-        1000000,
-        fnv::FnvBuildHasher::default(),
-    ));
     match cfg.rx_backend.as_str() {
         "libpcap" => {
             (rx::libpcap::UTILITY.init)(&mut cfg)?;
@@ -65,6 +67,16 @@ fn main() -> Result<()> {
 
     let mut handles = vec![];
 
+    let writer = if cfg.writer_dir.is_empty() {
+        None
+    } else {
+        let (writer, writer_thread) = threadings::PcapWriter::new(&cfg)?;
+        let builder = std::thread::Builder::new().name(writer_thread.name());
+        handles.push(builder.spawn(move || writer_thread.spawn())?);
+        Some(writer)
+    };
+    let ctx = Arc::new(threadings::SessionContext::new(&cfg, writer));
+
     // keep share library 'alive' so that the vtable of trait object pointer is not pointing to an invalid position
     let mut parser_libraries = HashMap::new();
     let mut protocol_parsers = Vec::new();
@@ -90,8 +102,10 @@ fn main() -> Result<()> {
     }
 
     let mut classifier_manager = classifiers::ClassifierManager::new();
+    let mut field_registry = api::session::field::FieldRegistry::default();
     for parser in &mut protocol_parsers {
         parser.register_classify_rules(&mut classifier_manager)?;
+        parser.register_fields(&mut field_registry)?;
         parser.init()?;
     }
 
@@ -99,7 +113,9 @@ fn main() -> Result<()> {
     let classifier_manager = Arc::new(classifier_manager);
 
     let (ses_sender, ses_receiver) = bounded(cfg.pkt_channel_size as usize);
-    let mut output_thread = threadings::output::Thread::new(ses_receiver.clone());
+    let enricher = enrich::Enricher::new(&cfg)?;
+    let mut output_thread =
+        threadings::output::Thread::new(ses_receiver.clone(), enricher, field_registry);
 
     // initialize pkt threads
     let (pkt_sender, pkt_receiver) = bounded(cfg.pkt_channel_size as usize);
@@ -111,6 +127,7 @@ fn main() -> Result<()> {
             exit.clone(),
             classifier_manager.clone(),
             pkt_receiver.clone(),
+            ses_sender.clone(),
         );
         pkt_threads.push(thread);
     }
@@ -128,19 +145,20 @@ fn main() -> Result<()> {
     // start all pkt threads
     for thread in pkt_threads {
         let cfg = cfg.clone();
-        let session_table = session_table.clone();
+        let ctx = ctx.clone();
         let parsers = Box::new(protocol_parsers.iter().map(|p| p.box_clone()).collect());
         let builder = std::thread::Builder::new().name(thread.name());
-        let handle = builder.spawn(move || thread.spawn(cfg, session_table, parsers))?;
+        let handle = builder.spawn(move || thread.spawn(cfg, ctx, parsers))?;
         handles.push(handle);
     }
 
-    // start session timeout thread
+    // start session timeout thread, the writer thread exits once the pkt threads and
+    // the timeout thread drop the session context
     {
         let cfg = cfg.clone();
         let builder = std::thread::Builder::new().name(timeout_thread.name());
         let handle = builder
-            .spawn(move || timeout_thread.spawn(cfg, session_table.clone()))
+            .spawn(move || timeout_thread.spawn(cfg, ctx))
             .unwrap();
         handles.push(handle);
     }
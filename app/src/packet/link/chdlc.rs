@@ -0,0 +1,87 @@
+use super::{ethernet, Error, Layer, Protocol, SimpleProtocolParser};
+
+/// Unicast address
+const UNICAST: u8 = 0x0f;
+/// Broadcast address
+const BROADCAST: u8 = 0x8f;
+/// Serial Line Address Resolution Protocol
+const SLARP: u16 = 0x8035;
+/// Cisco Discovery Protocol
+const CDP: u16 = 0x2000;
+
+/// Whether the frame starts with a Cisco HDLC address
+#[inline]
+pub fn is_chdlc(buf: &[u8]) -> bool {
+    !buf.is_empty() && (buf[0] == UNICAST || buf[0] == BROADCAST)
+}
+
+/// Cisco HDLC, address, control and an ether type
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 4 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted C-HDLC packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        if !is_chdlc(buf) {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted C-HDLC packet, unknown address: {:#x}",
+                buf[0]
+            )));
+        }
+
+        let etype = (buf[2] as u16) << 8 | buf[3] as u16;
+        let protocol = match etype {
+            ethernet::IPV4 => Protocol::IPV4,
+            ethernet::IPV6 => Protocol::IPV6,
+            ethernet::MPLSUC => Protocol::MPLS,
+            // keepalive & neighbor discovery, nothing to decode further
+            SLARP | CDP => return Ok(None),
+            _ => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport C-HDLC protocol: {:#x}",
+                    etype
+                )))
+            }
+        };
+
+        Ok(Some(Layer {
+            protocol,
+            offset: offset + 4,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [0x0f, 0x00, 0x08, 0x00, 0x45, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+
+        let buf = [0x8f, 0x00, 0x80, 0x35, 0x00, 0x00];
+        assert!(PARSER.parse(&buf, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn err() {
+        let buf = [0xff, 0x03, 0x08, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        let buf = [0x0f, 0x00, 0x81, 0x37];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+    }
+}
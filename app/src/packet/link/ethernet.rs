@@ -37,7 +37,8 @@ pub const IPV4: u16 = 0x0800;
 // const DEC_DIAG: u16 = 0x6005;
 // const DEC_CUST: u16 = 0x6006;
 // const DEC_SCA: u16 = 0x6007;
-// const ETHBRIDGE: u16 = 0x6558;
+/// Transparent Ethernet Bridging, used by GRE
+pub const ETHBRIDGE: u16 = 0x6558;
 // const RAW_FR: u16 = 0x6559;
 // const REVARP: u16 = 0x8035;
 // const DEC_LB: u16 = 0x8038;
@@ -68,7 +69,9 @@ pub const MPLSUC: u16 = 0x8847;
 // const MPLSmc: u16 = 0x8848;
 /// Some Foundry proprietary protocol
 // const FOUNDRY: u16 = 0x885A;
-// const PPPoEd: u16 = 0x8863;
+/// PPPoE discovery stage
+pub const PPPOED: u16 = 0x8863;
+/// PPPoE session stage
 pub const PPPOES: u16 = 0x8864;
 /// Intel Advanced Networking Services
 // const INTEL_ANS: u16 = 0x886D;
@@ -213,7 +216,7 @@ impl SimpleProtocolParser for Parser {
             IPV6 => layer.protocol = Protocol::IPV6,
             PPP => layer.protocol = Protocol::PPP,
             MPLSUC => layer.protocol = Protocol::MPLS,
-            PPPOES => layer.protocol = Protocol::PPPOE,
+            PPPOED | PPPOES => layer.protocol = Protocol::PPPOE,
             VLAN => {
                 layer.protocol = Protocol::VLAN;
                 layer.offset = layer.offset + 6 + 6;
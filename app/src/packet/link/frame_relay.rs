@@ -12,23 +12,127 @@
 //! So what we actually doing here is parsing Cisco's HDLC protocol and its deriving protocols
 //! And in this specific case is Frame Relay protocol
 
-use super::super::network;
-use super::{packet, Error};
+use super::{ethernet, Error, Layer, Protocol, SimpleProtocolParser};
 
-#[inline]
-pub fn parse(pkt: &mut packet::Packet, depth: usize) -> Result<packet::LayerType, Error> {
-    if pkt.len_of_layer(depth) < 4 {
-        return Err(Error::CorruptPacket);
+/// Q.922 unnumbered information control field
+const UI: u8 = 0x03;
+
+// Network layer protocol identifiers, RFC 2427
+const NLPID_PADDING: u8 = 0x00;
+const NLPID_SNAP: u8 = 0x80;
+const NLPID_IPV4: u8 = 0xcc;
+const NLPID_IPV6: u8 = 0x8e;
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 4 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted Frame Relay packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        // 2 bytes Q.922 address, only the last byte has the EA bit set
+        if buf[0] & 0x01 != 0 || buf[1] & 0x01 != 1 {
+            return Err(Error::UnsupportProtocol(
+                "Unsupport Frame Relay address length".to_string(),
+            ));
+        }
+
+        if buf[2] != UI {
+            return Err(Error::UnsupportProtocol(format!(
+                "Unsupport Frame Relay control field: {:#x}",
+                buf[2]
+            )));
+        }
+
+        let mut pos = 3;
+        if buf[pos] == NLPID_PADDING {
+            pos += 1;
+        }
+
+        let protocol = match buf.get(pos) {
+            Some(&NLPID_IPV4) => Protocol::IPV4,
+            Some(&NLPID_IPV6) => Protocol::IPV6,
+            Some(&NLPID_SNAP) => {
+                // 3 bytes OUI and 2 bytes PID, PID is an ether type when OUI is 0
+                let snap = match buf.get(pos + 1..pos + 6) {
+                    Some(snap) => snap,
+                    None => {
+                        return Err(Error::CorruptPacket(
+                            "Corrupted Frame Relay packet, truncated SNAP header".to_string(),
+                        ))
+                    }
+                };
+                pos += 5;
+                match (
+                    snap[0..3] == [0, 0, 0],
+                    (snap[3] as u16) << 8 | snap[4] as u16,
+                ) {
+                    (true, ethernet::IPV4) => Protocol::IPV4,
+                    (true, ethernet::IPV6) => Protocol::IPV6,
+                    _ => {
+                        return Err(Error::UnsupportProtocol(
+                            "Unsupport Frame Relay SNAP protocol".to_string(),
+                        ))
+                    }
+                }
+            }
+            Some(nlpid) => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport Frame Relay NLPID: {:#x}",
+                    nlpid
+                )))
+            }
+            None => {
+                return Err(Error::CorruptPacket(
+                    "Corrupted Frame Relay packet, no NLPID".to_string(),
+                ))
+            }
+        };
+
+        Ok(Some(Layer {
+            protocol,
+            offset: offset + pos as u16 + 1,
+        }))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
 
-    // calculate next layers start byte position
-    pkt.layers[depth + 1].start_pos = pkt.layers[depth].start_pos + 4;
+    #[test]
+    fn ipv4() {
+        let buf = [0x18, 0x41, 0x03, 0xcc, 0x45, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+    }
 
-    let pos = pkt.layers[depth].start_pos;
-    let protocol_type = (pkt.data()[pos + 2] as u16) << 8 | pkt.data()[pos + 3] as u16;
-    if protocol_type == 0x03cc {
-        return Ok(packet::LayerType::Network(network::NetworkType::IPV4));
+    #[test]
+    fn snap() {
+        let buf = [
+            0x18, 0x41, 0x03, 0x00, 0x80, 0x00, 0x00, 0x00, 0x86, 0xdd, 0x60, 0x00,
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 10);
     }
 
-    Ok(())
+    #[test]
+    fn err() {
+        let buf = [0x18, 0x41, 0x03, 0x81, 0x00, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+
+        let buf = [0x18, 0x41, 0x03, 0x80, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
 }
@@ -0,0 +1,66 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// Solaris ipnet header length
+const HDR_LEN: usize = 24;
+const IPH_AF_INET: u8 = 2;
+const IPH_AF_INET6: u8 = 26;
+
+/// Solaris ipnet pseudo header
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < HDR_LEN {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted ipnet packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let protocol = match buf[1] {
+            IPH_AF_INET => Protocol::IPV4,
+            IPH_AF_INET6 => Protocol::IPV6,
+            family => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport ipnet address family: {}",
+                    family
+                )))
+            }
+        };
+
+        Ok(Some(Layer {
+            protocol,
+            offset: offset + HDR_LEN as u16,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let mut buf = [0u8; 26];
+        buf[0] = 2;
+        buf[1] = IPH_AF_INET6;
+        buf[24] = 0x60;
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 24);
+    }
+
+    #[test]
+    fn err() {
+        let result = PARSER.parse(&[2, IPH_AF_INET], 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        let mut buf = [0u8; 24];
+        buf[1] = 7;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+    }
+}
@@ -3,8 +3,14 @@
 use super::parser::{Error, SimpleProtocolParser};
 use super::{Layer, Protocol};
 
+pub mod chdlc;
 pub mod ethernet;
+pub mod frame_relay;
+pub mod ipnet;
 pub mod null;
+pub mod pflog;
+pub mod ppp_hdlc;
+pub mod raw;
 
 /// From https://www.tcpdump.org/linktypes.html
 pub const NULL: u16 = 0;
@@ -13,19 +19,19 @@ pub const ETHERNET: u16 = 1;
 // pub const IEEE802_5: u16 = 6;
 // pub const ARCNET_BSD: u16 = 7;
 // pub const SLIP: u16 = 8;
-// pub const PPP: u16 = 9;
+pub const PPP: u16 = 9;
 // pub const FDDI: u16 = 10;
-// pub const PPP_HDLC: u16 = 50;
+pub const PPP_HDLC: u16 = 50;
 // pub const PPP_ETHER: u16 = 51;
 // pub const ATM_RFC1483: u16 = 100;
 pub const RAW: u16 = 101;
-// pub const C_HDLC: u16 = 104;
+pub const C_HDLC: u16 = 104;
 // pub const IEEE802_11: u16 = 105;
-// pub const FRELAY: u16 = 107;
-// pub const LOOP: u16 = 108;
+pub const FRELAY: u16 = 107;
+pub const LOOP: u16 = 108;
 // pub const LINUX_SLL: u16 = 113;
 // pub const LTALK: u16 = 114;
-// pub const PFLOG: u16 = 117;
+pub const PFLOG: u16 = 117;
 // pub const IEEE802_11_PRISM: u16 = 119;
 // pub const IP_OVER_FC: u16 = 122;
 // pub const SUNATM: u16 = 123;
@@ -80,7 +86,7 @@ pub const RAW: u16 = 101;
 // pub const USB_LINUX_MMAPPED: u16 = 220;
 // pub const FC_2: u16 = 224;
 // pub const FC_2_WITH_FRAME_DELIMS: u16 = 225;
-// pub const IPNET: u16 = 226;
+pub const IPNET: u16 = 226;
 // pub const CAN_SOCKETCAN: u16 = 227;
 pub const IPV4: u16 = 228;
 pub const IPV6: u16 = 229;
@@ -18,7 +18,9 @@ impl SimpleProtocolParser for Parser {
             offset: 0,
         };
         layer.offset = 4;
-        let link_type = buf[0];
+        // NULL header is in the capturing host's byte order while LOOP is in network
+        // byte order, address families are small enough to tell them apart
+        let link_type = if buf[0] == 0 { buf[3] } else { buf[0] };
 
         // from https://www.tcpdump.org/linktypes.html
         match link_type {
@@ -99,6 +101,14 @@ mod tests {
         assert!(matches!(l.unwrap().protocol, Protocol::IPV6));
     }
 
+    #[test]
+    fn test_ok_loop() {
+        let buf = [0x00, 0x00, 0x00, 0x02, 0x45, 0x00];
+        let l = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(l.protocol, Protocol::IPV4);
+        assert_eq!(l.offset, 4);
+    }
+
     #[test]
     fn test_err_pkt_too_short() {
         let buf = [0x01];
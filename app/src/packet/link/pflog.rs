@@ -0,0 +1,78 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// Minimal pflog header length, up to the rule set name
+const MIN_HDR_LEN: usize = 4;
+const AF_INET: u8 = 2;
+
+/// OpenBSD pflog header
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < MIN_HDR_LEN {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted pflog packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        // header length field excludes padding to 4 bytes boundary
+        let hdr_len = (buf[0] as usize + 3) & !3;
+        if hdr_len < MIN_HDR_LEN || hdr_len > buf.len() {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted pflog packet, invalid header length: {}",
+                buf[0]
+            )));
+        }
+
+        let protocol = match buf[1] {
+            AF_INET => Protocol::IPV4,
+            // AF_INET6 of OpenBSD, FreeBSD and macOS
+            24 | 28 | 30 => Protocol::IPV6,
+            family => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport pflog address family: {}",
+                    family
+                )))
+            }
+        };
+
+        Ok(Some(Layer {
+            protocol,
+            offset: offset + hdr_len as u16,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let mut buf = [0u8; 68];
+        buf[0] = 61;
+        buf[1] = AF_INET;
+        buf[64] = 0x45;
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 64);
+    }
+
+    #[test]
+    fn err() {
+        let mut buf = [0u8; 32];
+        buf[0] = 61;
+        buf[1] = AF_INET;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        buf[0] = 28;
+        buf[1] = 17;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+    }
+}
@@ -0,0 +1,38 @@
+use super::super::tunnel::ppp;
+use super::{chdlc, Error, Layer, SimpleProtocolParser};
+
+/// PPP in HDLC-like framing, which is also used for Cisco PPP with HDLC framing, RFC 1547
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if chdlc::is_chdlc(buf) {
+            return chdlc::Parser::default().parse(buf, offset);
+        }
+        ppp::Parser::default().parse(buf, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Protocol;
+
+    #[test]
+    fn cisco_hdlc() {
+        let buf = [0x0f, 0x00, 0x08, 0x00, 0x45, 0x00];
+        let layer = Parser::default().parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+    }
+
+    #[test]
+    fn ppp() {
+        let buf = [0xff, 0x03, 0x00, 0x21, 0x45, 0x00];
+        let layer = Parser::default().parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+    }
+}
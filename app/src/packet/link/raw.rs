@@ -0,0 +1,55 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// Raw IP, packet begins with an IPv4 or IPv6 header
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.is_empty() {
+            return Err(Error::CorruptPacket(format!(
+                "The packet is corrupted, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let protocol = match buf[0] >> 4 {
+            4 => Protocol::IPV4,
+            6 => Protocol::IPV6,
+            version => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport raw ip version: {}",
+                    version
+                )))
+            }
+        };
+
+        Ok(Some(Layer { protocol, offset }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let layer = PARSER.parse(&[0x45, 0x00], 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 0);
+
+        let layer = PARSER.parse(&[0x60, 0x00], 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+    }
+
+    #[test]
+    fn unsupport_version() {
+        let result = PARSER.parse(&[0x25, 0x00], 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+
+        let result = PARSER.parse(&[], 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
@@ -0,0 +1,99 @@
+use super::{ip_proto, Error, Layer, Protocol, SimpleProtocolParser};
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 12 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted AH packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        // payload length is in 4-octet units, minus 2
+        let ah_len = (buf[1] as usize + 2) * 4;
+        if ah_len < 12 || ah_len > buf.len() {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted AH packet, invalid header length ({} bytes)",
+                ah_len
+            )));
+        }
+
+        let mut layer = Layer {
+            protocol: Protocol::default(),
+            offset: offset + ah_len as u16,
+        };
+
+        // AH only authenticates, the next header is in plaintext
+        match buf[0] {
+            ip_proto::ICMP => layer.protocol = Protocol::ICMP,
+            ip_proto::IPV4 => layer.protocol = Protocol::IPV4,
+            ip_proto::TCP => layer.protocol = Protocol::TCP,
+            ip_proto::UDP => layer.protocol = Protocol::UDP,
+            ip_proto::ESP => layer.protocol = Protocol::ESP,
+            ip_proto::IPV6 => layer.protocol = Protocol::IPV6,
+            ip_proto::GRE => layer.protocol = Protocol::GRE,
+            ip_proto::SCTP => layer.protocol = Protocol::SCTP,
+            next => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport protocol, AH next header: {}",
+                    next
+                )))
+            }
+        };
+
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [
+            0x06, 0x04, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01, 0x27, 0xcf,
+            0xc0, 0xa1, 0x41, 0x1a, 0x1e, 0x8a, 0xa3, 0x2a, 0x8e, 0x9c, // ah
+            0x04, 0x3f, 0x08, 0x22, // tcp
+        ];
+        let layer = PARSER.parse(&buf, 20).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::TCP);
+        assert_eq!(layer.offset, 44);
+    }
+
+    #[test]
+    fn pkt_too_short() {
+        let buf = [0x06, 0x04, 0x00, 0x00];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+
+    #[test]
+    fn length_exceeds_packet() {
+        let buf = [
+            0x06, 0x04, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01,
+        ];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+
+    #[test]
+    fn unsupport_protocol() {
+        let buf = [
+            0x3b, 0x01, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01,
+        ];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::UnsupportProtocol(_))
+        ));
+    }
+}
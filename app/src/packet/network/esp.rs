@@ -0,0 +1,60 @@
+use super::{Error, Layer, SimpleProtocolParser};
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], _offset: u16) -> Result<Option<Layer>, Error> {
+        // SPI and sequence number
+        if buf.len() < 8 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted ESP packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let spi =
+            (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32;
+        if spi == 0 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted ESP packet, reserved SPI 0"
+            )));
+        }
+
+        // payload is encrypted, nothing more to decode
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [
+            0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01, 0x8c, 0x2a, 0x61, 0x3e, 0x9f, 0x07,
+        ];
+        assert!(matches!(PARSER.parse(&buf, 34), Ok(None)));
+    }
+
+    #[test]
+    fn pkt_too_short() {
+        let buf = [0x00, 0x00, 0x10, 0x01];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+
+    #[test]
+    fn reserved_spi() {
+        let buf = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+}
@@ -49,7 +49,7 @@ pub const GRE: u8 = 47;
 // pub const DSR: u8 = 48;
 // pub const BNA: u8 = 49;
 pub const ESP: u8 = 50;
-// pub const AH: u8 = 51;
+pub const AH: u8 = 51;
 // pub const I_NLSP: u8 = 52;
 // pub const SWIPE: u8 = 53;
 // pub const NARP: u8 = 54;
@@ -39,11 +39,12 @@ impl SimpleProtocolParser for Parser {
 
         match ip_proto {
             ip_proto::ICMP => layer.protocol = Protocol::ICMP,
-            ip_proto::IPV4 => layer.protocol = Protocol::IPV4,
+            ip_proto::IPV4 => layer.protocol = Protocol::IPIP,
             ip_proto::TCP => layer.protocol = Protocol::TCP,
             ip_proto::UDP => layer.protocol = Protocol::UDP,
             ip_proto::ESP => layer.protocol = Protocol::ESP,
-            ip_proto::IPV6 => layer.protocol = Protocol::IPV6,
+            ip_proto::AH => layer.protocol = Protocol::AH,
+            ip_proto::IPV6 => layer.protocol = Protocol::IPIP,
             ip_proto::GRE => layer.protocol = Protocol::GRE,
             ip_proto::SCTP => layer.protocol = Protocol::SCTP,
             _ => {
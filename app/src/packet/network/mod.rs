@@ -1,6 +1,8 @@
 use super::parser::{Error, SimpleProtocolParser};
 use super::{Layer, Protocol};
 
+pub mod ah;
+pub mod esp;
 pub mod icmp;
 pub mod ip_proto;
 pub mod ipv4;
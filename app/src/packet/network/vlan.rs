@@ -19,7 +19,7 @@ impl SimpleProtocolParser for Parser {
             link::ethernet::IPV6 => layer.protocol = Protocol::IPV6,
             link::ethernet::PPP => layer.protocol = Protocol::PPP,
             link::ethernet::MPLSUC => layer.protocol = Protocol::MPLS,
-            link::ethernet::PPPOES => layer.protocol = Protocol::PPPOE,
+            link::ethernet::PPPOED | link::ethernet::PPPOES => layer.protocol = Protocol::PPPOE,
             link::ethernet::VLAN => layer.protocol = Protocol::VLAN,
             _ => {
                 return Err(Error::UnsupportProtocol(format!(
@@ -1,11 +1,13 @@
 use std::fmt::{Display, Formatter};
 
 use anyhow::Result;
+use fnv::FnvHashMap;
 
 use alphonse_api as api;
-use api::packet::{Layer, Packet, Protocol};
+use api::packet::{Layer, Packet, Protocol, Tunnel};
 
 use super::{link, network, transport, tunnel};
+use crate::config::Config;
 
 /// A parser only validate protocol and returns layer start offset
 pub trait SimpleProtocolParser {
@@ -26,12 +28,15 @@ pub enum Error {
     UnsupportProtocol(String),
     CorruptPacket(String),
     UnknownProtocol,
+    /// No decoder is registered for the data link type
+    UnknownLinkType(u16),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
             Error::UnknownProtocol => write!(f, "Unknown Protocol"),
+            Error::UnknownLinkType(link_type) => write!(f, "Unknown link type {}", link_type),
             Error::UnsupportProtocol(s) | Error::CorruptPacket(s) => write!(f, "{}", s),
         }
     }
@@ -39,27 +44,105 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+/// Error of decoding a whole packet
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Protocol being decoded when the error occurs
+    pub protocol: Protocol,
+    pub error: Error,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.protocol, self.error)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decoder of a data link type
+struct LinkDecoder {
+    /// Protocol of the data link layer
+    protocol: Protocol,
+    parser: Box<dyn SimpleProtocolParser>,
+}
+
 pub struct Parser {
     /// SnapLen, Snap Length, or snapshot length is the amount of data for each frame
     /// that is actually captured by the network capturing tool and stored into the CaptureFile.
     /// https://wiki.wireshark.org/SnapLen
     _snap_len: u32,
-    link_type: u16,
+    /// Data link decoders, keyed by pcap DLT number
+    links: FnvHashMap<u16, LinkDecoder>,
     callbacks: Vec<Option<Box<dyn SimpleProtocolParser>>>,
 }
 
 impl Parser {
     /// create a new protocol parser
-    pub fn new(link_type: u16) -> Parser {
+    pub fn new(cfg: &Config) -> Parser {
         let mut callbacks = vec![];
         for _ in 0..u8::MAX as usize {
             callbacks.push(None);
         }
         let mut parser = Parser {
             _snap_len: 65535,
-            link_type,
+            links: FnvHashMap::default(),
             callbacks,
         };
+        // register data link type decoders
+        parser.register_link(
+            link::NULL,
+            Protocol::NULL,
+            Box::new(link::null::Parser::default()),
+        );
+        parser.register_link(
+            link::LOOP,
+            Protocol::NULL,
+            Box::new(link::null::Parser::default()),
+        );
+        parser.register_link(
+            link::ETHERNET,
+            Protocol::ETHERNET,
+            Box::new(link::ethernet::Parser::default()),
+        );
+        for link_type in &[link::RAW, link::IPV4, link::IPV6] {
+            parser.register_link(
+                *link_type,
+                Protocol::RAW,
+                Box::new(link::raw::Parser::default()),
+            );
+        }
+        parser.register_link(
+            link::PPP,
+            Protocol::PPP,
+            Box::new(tunnel::ppp::Parser::default()),
+        );
+        parser.register_link(
+            link::PPP_HDLC,
+            Protocol::PPP,
+            Box::new(link::ppp_hdlc::Parser::default()),
+        );
+        parser.register_link(
+            link::C_HDLC,
+            Protocol::CHDLC,
+            Box::new(link::chdlc::Parser::default()),
+        );
+        parser.register_link(
+            link::FRELAY,
+            Protocol::FRELAY,
+            Box::new(link::frame_relay::Parser::default()),
+        );
+        parser.register_link(
+            link::IPNET,
+            Protocol::IPNET,
+            Box::new(link::ipnet::Parser::default()),
+        );
+        parser.register_link(
+            link::PFLOG,
+            Protocol::PFLOG,
+            Box::new(link::pflog::Parser::default()),
+        );
+
         // register protocol callbacks
         // link layer protocol parsers
         parser.callbacks[Protocol::ETHERNET as u8 as usize] =
@@ -68,14 +151,29 @@ impl Parser {
             Some(Box::new(link::null::Parser::default()));
 
         // tunnel protocol parsers
-        parser.callbacks[Protocol::MPLS as u8 as usize] =
-            Some(Box::new(tunnel::mpls::Parser::default()));
+        parser.callbacks[Protocol::GRE as u8 as usize] =
+            Some(Box::new(tunnel::gre::Parser::default()));
+        parser.callbacks[Protocol::GTP as u8 as usize] =
+            Some(Box::new(tunnel::gtp::Parser::default()));
+        parser.callbacks[Protocol::VXLAN as u8 as usize] =
+            Some(Box::new(tunnel::vxlan::Parser::default()));
+        parser.callbacks[Protocol::MPLS as u8 as usize] = Some(Box::new(tunnel::mpls::Parser {
+            heuristic: cfg.mpls_heuristic,
+        }));
         parser.callbacks[Protocol::L2TP as u8 as usize] =
             Some(Box::new(tunnel::l2tp::Parser::default()));
         parser.callbacks[Protocol::PPP as u8 as usize] =
             Some(Box::new(tunnel::ppp::Parser::default()));
         parser.callbacks[Protocol::PPPOE as u8 as usize] =
             Some(Box::new(tunnel::pppoe::Parser::default()));
+        parser.callbacks[Protocol::IPIP as u8 as usize] =
+            Some(Box::new(tunnel::ipip::Parser::default()));
+        parser.callbacks[Protocol::IP6IN4 as u8 as usize] =
+            Some(Box::new(tunnel::ip6in4::Parser::default()));
+        parser.callbacks[Protocol::TEREDO as u8 as usize] =
+            Some(Box::new(tunnel::teredo::Parser::default()));
+        parser.callbacks[Protocol::WIREGUARD as u8 as usize] =
+            Some(Box::new(tunnel::wireguard::Parser::default()));
 
         // network layer protocl parsers
         parser.callbacks[Protocol::IPV4 as u8 as usize] =
@@ -86,6 +184,10 @@ impl Parser {
             Some(Box::new(network::vlan::Parser::default()));
         parser.callbacks[Protocol::ICMP as u8 as usize] =
             Some(Box::new(network::icmp::Parser::default()));
+        parser.callbacks[Protocol::ESP as u8 as usize] =
+            Some(Box::new(network::esp::Parser::default()));
+        parser.callbacks[Protocol::AH as u8 as usize] =
+            Some(Box::new(network::ah::Parser::default()));
 
         // transport layer protocl parsers
         parser.callbacks[Protocol::TCP as u8 as usize] =
@@ -98,61 +200,165 @@ impl Parser {
         parser
     }
 
-    /// parse a single packet
+    /// Register a data link type decoder, replacing the existing one of the same link type
+    ///
+    /// # Arguments
+    ///
+    /// * `link_type` - pcap DLT number
+    ///
+    /// * `protocol` - Protocol recorded as the data link layer
+    ///
+    /// * `parser` - Decoder of the data link layer
+    pub fn register_link(
+        &mut self,
+        link_type: u16,
+        protocol: Protocol,
+        parser: Box<dyn SimpleProtocolParser>,
+    ) {
+        self.links
+            .insert(link_type, LinkDecoder { protocol, parser });
+    }
+
+    /// Record a tunnel layer
+    ///
+    /// When entering the outermost tunnel, current network & transport layers are saved as
+    /// outer layers and cleared, so that they are only filled by the encapsulated packet
     #[inline]
-    pub fn parse_pkt(&self, pkt: &mut dyn Packet) -> Result<(), Error> {
-        // 根据 link type 解析数据链路层协议, 获取下一层协议的协议类型和起始位置
-        let mut result = match self.link_type {
-            link::NULL => {
-                pkt.layers_mut().data_link.protocol = Protocol::NULL;
-                let index = pkt.layers_mut().data_link.protocol as u8 as usize;
-                self.callbacks[index].as_ref().unwrap().parse(pkt.raw(), 0)
-            }
-            link::ETHERNET => {
-                pkt.layers_mut().data_link.protocol = Protocol::ETHERNET;
-                let index = pkt.layers_mut().data_link.protocol as u8 as usize;
-                self.callbacks[index].as_ref().unwrap().parse(pkt.raw(), 0)
-            }
-            link::RAW | link::IPV4 => {
-                let layer = Layer {
-                    protocol: Protocol::IPV4,
-                    offset: 0,
-                };
-                Ok(Some(layer))
-            }
-            link::IPV6 => {
-                let layer = Layer {
-                    protocol: Protocol::IPV6,
-                    offset: 0,
-                };
-                Ok(Some(layer))
+    fn enter_tunnel(pkt: &mut dyn Packet, layer: Layer) {
+        let tunnel = match layer.protocol {
+            Protocol::GRE => Tunnel::GRE,
+            Protocol::GTP => Tunnel::GTP,
+            Protocol::L2TP => Tunnel::L2TP,
+            Protocol::MPLS => Tunnel::MPLS,
+            Protocol::PPP => Tunnel::PPP,
+            Protocol::PPPOE => Tunnel::PPPOE,
+            Protocol::VXLAN => Tunnel::VXLAN,
+            Protocol::IPIP => Tunnel::IPIP,
+            Protocol::IP6IN4 => Tunnel::IP6IN4,
+            Protocol::TEREDO => Tunnel::TEREDO,
+            Protocol::WIREGUARD => Tunnel::WIREGUARD,
+            _ => return,
+        };
+
+        if layer.protocol == Protocol::MPLS && pkt.layers().mpls.protocol != Protocol::MPLS {
+            pkt.layers_mut().mpls = layer;
+        }
+
+        if layer.protocol == Protocol::PPPOE && pkt.layers().pppoe.protocol != Protocol::PPPOE {
+            pkt.layers_mut().pppoe = layer;
+        }
+
+        if pkt.tunnel().is_empty() {
+            let layers = pkt.layers_mut();
+            layers.tunnel = layer;
+            layers.outer_network = layers.network;
+            layers.outer_trans = layers.trans;
+            layers.network = Layer::default();
+            layers.trans = Layer::default();
+        }
+        *pkt.tunnel_mut() |= tunnel;
+    }
+
+    /// Clear the layer which failed to decode, so only successfully decoded layers are kept
+    ///
+    /// A tunnel failing to decode is undone, `tunnel` is the packet's tunnels before entering it
+    #[inline]
+    fn reset_layer(pkt: &mut dyn Packet, layer: Layer, tunnel: Tunnel) {
+        let same = |l: &Layer| l.protocol == layer.protocol && l.offset == layer.offset;
+        let layers = pkt.layers_mut();
+        match layer.protocol {
+            Protocol::ETHERNET | Protocol::NULL => layers.data_link = Layer::default(),
+            Protocol::IPV4 | Protocol::IPV6 => layers.network = Layer::default(),
+            Protocol::TCP | Protocol::UDP | Protocol::SCTP | Protocol::ESP => {
+                layers.trans = Layer::default()
             }
             _ => {
-                return Err(Error::UnsupportProtocol(format!(
-                    "Unsupport data link layer protocol, link type: {}",
-                    self.link_type
-                )))
+                if same(&layers.mpls) {
+                    layers.mpls = Layer::default();
+                }
+                if same(&layers.pppoe) {
+                    layers.pppoe = Layer::default();
+                }
+                if same(&layers.tunnel) {
+                    layers.tunnel = Layer::default();
+                    layers.network = layers.outer_network;
+                    layers.trans = layers.outer_trans;
+                    layers.outer_network = Layer::default();
+                    layers.outer_trans = Layer::default();
+                    // The tunnel's header becomes the payload of the outer transport layer
+                    layers.app = Layer {
+                        protocol: Protocol::APPLICATION,
+                        offset: layer.offset,
+                    };
+                }
+                *pkt.tunnel_mut() = tunnel;
+            }
+        };
+    }
+
+    /// parse a single packet
+    ///
+    /// On error, layers decoded before the failing one are kept in the packet
+    #[inline]
+    pub fn parse_pkt(&self, pkt: &mut dyn Packet) -> Result<(), DecodeError> {
+        // 根据 link type 解析数据链路层协议, 获取下一层协议的协议类型和起始位置
+        let link_type = pkt.link_type();
+        let link = match self.links.get(&link_type) {
+            Some(link) => link,
+            None => {
+                return Err(DecodeError {
+                    protocol: Protocol::UNKNOWN,
+                    error: Error::UnknownLinkType(link_type),
+                })
             }
         };
+        pkt.layers_mut().data_link = Layer {
+            protocol: link.protocol,
+            offset: 0,
+        };
 
+        let mut result = link.parser.parse(pkt.raw(), 0);
         let mut layer = match result {
             Ok(l) => match l {
                 Some(l) => l,
                 None => return Ok(()),
             },
-            Err(e) => return Err(e),
+            Err(e) => {
+                let protocol = link.protocol;
+                pkt.layers_mut().data_link = Layer::default();
+                return Err(DecodeError { protocol, error: e });
+            }
         };
 
         loop {
+            if layer.protocol == Protocol::APPLICATION {
+                pkt.layers_mut().app = layer;
+                return Ok(());
+            }
+
             let index = layer.protocol as u8 as usize;
+            let tunnel = pkt.tunnel();
             result = match &self.callbacks[index] {
                 Some(p) => {
                     match &layer.protocol {
                         Protocol::ETHERNET => pkt.layers_mut().data_link = layer,
                         Protocol::IPV4 | Protocol::IPV6 => pkt.layers_mut().network = layer,
-                        Protocol::TCP | Protocol::UDP | Protocol::SCTP => {
+                        // ESP over NAT-T replaces the UDP layer, so that
+                        // it's keyed the same way as plain ESP
+                        Protocol::TCP | Protocol::UDP | Protocol::SCTP | Protocol::ESP => {
                             pkt.layers_mut().trans = layer
                         }
+                        Protocol::GRE
+                        | Protocol::GTP
+                        | Protocol::L2TP
+                        | Protocol::MPLS
+                        | Protocol::PPP
+                        | Protocol::PPPOE
+                        | Protocol::VXLAN
+                        | Protocol::IPIP
+                        | Protocol::IP6IN4
+                        | Protocol::TEREDO
+                        | Protocol::WIREGUARD => Parser::enter_tunnel(pkt, layer),
                         _ => {}
                     };
                     let buf = &pkt.raw()[layer.offset as usize..];
@@ -160,10 +366,13 @@ impl Parser {
                     p.parse(buf, offset)
                 }
                 None => {
-                    return Err(Error::UnsupportProtocol(format!(
-                        "Unsupport protocol {:?}",
-                        layer.protocol
-                    )));
+                    return Err(DecodeError {
+                        protocol: layer.protocol,
+                        error: Error::UnsupportProtocol(format!(
+                            "Unsupport protocol {:?}",
+                            layer.protocol
+                        )),
+                    });
                 }
             };
 
@@ -175,8 +384,210 @@ impl Parser {
                     };
                     // layer = l;
                 }
-                Err(e) => return Err(e),
+                Err(e) => {
+                    Parser::reset_layer(pkt, layer, tunnel);
+                    return Err(DecodeError {
+                        protocol: layer.protocol,
+                        error: e,
+                    });
+                }
             };
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::utils::packet::Packet as TestPacket;
+
+    fn eth_ipv4_pkt(ip_len: u16, trans: &[u8]) -> TestPacket {
+        let mut raw = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08,
+            0x00, // ethernet
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x01, 0x0a, 0x00, 0x00, 0x02, // ipv4
+        ];
+        raw[16] = (ip_len >> 8) as u8;
+        raw[17] = ip_len as u8;
+        raw.extend_from_slice(trans);
+        let mut pkt = TestPacket::default();
+        pkt.raw = Box::new(raw);
+        pkt
+    }
+
+    #[test]
+    fn application_layer() {
+        let tcp = [
+            0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x50, 0x18,
+            0xff, 0xff, 0x00, 0x00, 0x00, 0x00, b'a', b'b', b'c',
+        ];
+        let mut pkt = eth_ipv4_pkt(43, &tcp);
+        let parser = Parser::new(&Config::default());
+        assert!(parser.parse_pkt(&mut pkt).is_ok());
+        assert_eq!(pkt.layers().trans.protocol, Protocol::TCP);
+        assert_eq!(pkt.layers().app.protocol, Protocol::APPLICATION);
+        assert_eq!(pkt.layers().app.offset, 54);
+        assert_eq!(pkt.payload(), b"abc");
+    }
+
+    #[test]
+    fn corrupt_packet() {
+        let tcp = [0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        let mut pkt = eth_ipv4_pkt(30, &tcp);
+        let parser = Parser::new(&Config::default());
+        let err = parser.parse_pkt(&mut pkt).unwrap_err();
+        assert_eq!(err.protocol, Protocol::TCP);
+        assert!(matches!(err.error, Error::CorruptPacket(_)));
+        // successfully decoded layers are kept
+        assert_eq!(pkt.layers().network.protocol, Protocol::IPV4);
+        assert_eq!(pkt.layers().trans.protocol, Protocol::UNKNOWN);
+    }
+
+    #[test]
+    fn unsupport_protocol() {
+        let mut pkt = eth_ipv4_pkt(20, &[]);
+        pkt.raw[23] = 1; // ICMP
+        let parser = Parser::new(&Config::default());
+        let err = parser.parse_pkt(&mut pkt).unwrap_err();
+        assert_eq!(err.protocol, Protocol::ICMP);
+        assert!(matches!(err.error, Error::UnsupportProtocol(_)));
+        assert_eq!(pkt.layers().network.protocol, Protocol::IPV4);
+    }
+
+    #[test]
+    fn esp() {
+        let esp = [0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x01];
+        let mut pkt = eth_ipv4_pkt(28, &esp);
+        pkt.raw[23] = 50; // ESP
+        let parser = Parser::new(&Config::default());
+        assert!(parser.parse_pkt(&mut pkt).is_ok());
+        assert_eq!(pkt.layers().trans.protocol, Protocol::ESP);
+        assert_eq!(pkt.layers().trans.offset, 34);
+        assert_eq!(pkt.esp_spi(), 0x1001);
+    }
+
+    #[test]
+    fn ipip() {
+        let inner = [
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0x02, // ipv4
+            0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, // udp
+        ];
+        let mut pkt = eth_ipv4_pkt(48, &inner);
+        pkt.raw[23] = 4; // IPIP
+        let parser = Parser::new(&Config::default());
+        assert!(parser.parse_pkt(&mut pkt).is_ok());
+        assert_eq!(pkt.tunnel(), Tunnel::IPIP);
+        assert_eq!(pkt.layers().tunnel.protocol, Protocol::IPIP);
+        assert_eq!(pkt.layers().outer_network.offset, 14);
+        assert_eq!(pkt.layers().network.protocol, Protocol::IPV4);
+        assert_eq!(pkt.layers().network.offset, 34);
+        assert_eq!(pkt.layers().trans.protocol, Protocol::UDP);
+    }
+
+    #[test]
+    fn wireguard() {
+        let mut udp = vec![0xca, 0x6c, 0xca, 0x6c, 0x00, 0x28, 0x00, 0x00];
+        udp.extend_from_slice(&[0x04, 0x00, 0x00, 0x00]);
+        udp.extend_from_slice(&[0u8; 28]);
+        let mut pkt = eth_ipv4_pkt(60, &udp);
+        pkt.raw[23] = 17; // UDP
+        let parser = Parser::new(&Config::default());
+        assert!(parser.parse_pkt(&mut pkt).is_ok());
+        assert_eq!(pkt.tunnel(), Tunnel::WIREGUARD);
+        assert_eq!(pkt.layers().outer_trans.protocol, Protocol::UDP);
+        assert_eq!(pkt.layers().network.protocol, Protocol::UNKNOWN);
+        assert_eq!(pkt.layers().app.protocol, Protocol::UNKNOWN);
+    }
+
+    #[test]
+    fn tunnel_decode_error() {
+        // VXLAN header without the VNI flag
+        let udp = [
+            0x30, 0x39, 0x12, 0xb5, 0x00, 0x10, 0x00, 0x00, // udp
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // vxlan
+        ];
+        let mut pkt = eth_ipv4_pkt(36, &udp);
+        pkt.raw[23] = 17; // UDP
+        let parser = Parser::new(&Config::default());
+        let err = parser.parse_pkt(&mut pkt).unwrap_err();
+        assert_eq!(err.protocol, Protocol::VXLAN);
+        assert!(pkt.tunnel().is_empty());
+        assert_eq!(pkt.layers().tunnel.protocol, Protocol::UNKNOWN);
+        assert_eq!(pkt.layers().network.offset, 14);
+        assert_eq!(pkt.layers().trans.protocol, Protocol::UDP);
+        assert_eq!(pkt.layers().outer_network.protocol, Protocol::UNKNOWN);
+        assert_eq!(pkt.layers().outer_trans.protocol, Protocol::UNKNOWN);
+        assert_eq!(pkt.layers().app.protocol, Protocol::APPLICATION);
+        assert_eq!(pkt.layers().app.offset, 42);
+        assert_eq!(pkt.payload(), &udp[8..]);
+
+        // GTP header with an invalid version
+        let udp = [
+            0x30, 0x39, 0x08, 0x68, 0x00, 0x10, 0x00, 0x00, // udp
+            0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // gtp
+        ];
+        let mut pkt = eth_ipv4_pkt(36, &udp);
+        pkt.raw[23] = 17; // UDP
+        let err = parser.parse_pkt(&mut pkt).unwrap_err();
+        assert_eq!(err.protocol, Protocol::GTP);
+        assert!(pkt.tunnel().is_empty());
+        assert_eq!(pkt.layers().tunnel.protocol, Protocol::UNKNOWN);
+        assert_eq!(pkt.layers().trans.protocol, Protocol::UDP);
+        assert_eq!(pkt.layers().app.protocol, Protocol::APPLICATION);
+        assert_eq!(pkt.layers().app.offset, 42);
+        assert_eq!(pkt.payload(), &udp[8..]);
+    }
+
+    #[test]
+    fn link_types() {
+        let ipv4 = [
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x01, 0x0a, 0x00, 0x00, 0x02, // ipv4
+            0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, // udp
+        ];
+        let parser = Parser::new(&Config::default());
+        let cases: &[(u16, &[u8], Protocol)] = &[
+            (link::RAW, &[], Protocol::RAW),
+            (link::LOOP, &[0x00, 0x00, 0x00, 0x02], Protocol::NULL),
+            (link::PPP, &[0xff, 0x03, 0x00, 0x21], Protocol::PPP),
+            (link::PPP_HDLC, &[0x0f, 0x00, 0x08, 0x00], Protocol::PPP),
+            (link::C_HDLC, &[0x0f, 0x00, 0x08, 0x00], Protocol::CHDLC),
+            (link::FRELAY, &[0x18, 0x41, 0x03, 0xcc], Protocol::FRELAY),
+        ];
+        for (link_type, hdr, protocol) in cases {
+            let mut pkt = TestPacket::default();
+            pkt.link_type = *link_type;
+            let mut raw = hdr.to_vec();
+            raw.extend_from_slice(&ipv4);
+            pkt.raw = Box::new(raw);
+            assert!(parser.parse_pkt(&mut pkt).is_ok());
+            assert_eq!(pkt.layers().data_link.protocol, *protocol);
+            assert_eq!(pkt.layers().network.offset, hdr.len() as u16);
+            assert_eq!(pkt.layers().trans.protocol, Protocol::UDP);
+            assert!(pkt.tunnel().is_empty());
+        }
+    }
+
+    #[test]
+    fn unknown_link_type() {
+        let udp = [0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let mut pkt = eth_ipv4_pkt(28, &udp);
+        pkt.raw[23] = 17; // UDP
+        pkt.link_type = 147;
+        let parser = Parser::new(&Config::default());
+        let err = parser.parse_pkt(&mut pkt).unwrap_err();
+        assert_eq!(err.protocol, Protocol::UNKNOWN);
+        assert!(matches!(err.error, Error::UnknownLinkType(147)));
+
+        let mut parser = parser;
+        parser.register_link(
+            147,
+            Protocol::ETHERNET,
+            Box::new(link::ethernet::Parser::default()),
+        );
+        assert!(parser.parse_pkt(&mut pkt).is_ok());
+        assert_eq!(pkt.layers().network.protocol, Protocol::IPV4);
+    }
+}
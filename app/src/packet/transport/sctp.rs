@@ -1,3 +1,6 @@
+use alphonse_api as api;
+use api::packet::sctp;
+
 use super::{Error, Layer, Protocol, SimpleProtocolParser};
 
 #[derive(Default)]
@@ -6,20 +9,119 @@ pub struct Parser {}
 impl SimpleProtocolParser for Parser {
     #[inline]
     fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
-        if buf.len() < 12 {
+        if buf.len() < sctp::COMMON_HDR_LEN {
             return Err(Error::CorruptPacket(format!(
                 "Corrupted SCTP packet, packet too short ({} bytes)",
                 buf.len()
             )));
         }
 
-        // here we asume sctp must has at least one data chunk
-        // need checking rfc document to make sure
-        let layer = Layer {
-            protocol: Protocol::APPLICATION,
-            offset: offset + 16 as u16,
-        };
+        let mut app = None;
+        let mut pos = sctp::COMMON_HDR_LEN;
+        while pos + sctp::CHUNK_HDR_LEN <= buf.len() {
+            let chunk_type = buf[pos];
+            let chunk_len = ((buf[pos + 2] as u16) << 8 | buf[pos + 3] as u16) as usize;
+            if chunk_len < sctp::CHUNK_HDR_LEN {
+                // link layer padding, no more chunks
+                break;
+            }
+
+            if pos + chunk_len > buf.len() {
+                return Err(Error::CorruptPacket(format!(
+                    "Corrupted SCTP packet, chunk length {} exceeds packet length",
+                    chunk_len
+                )));
+            }
+
+            let min_len = match chunk_type {
+                sctp::DATA | sctp::SACK => sctp::DATA_HDR_LEN,
+                sctp::INIT | sctp::INIT_ACK => sctp::INIT_LEN,
+                sctp::SHUTDOWN => sctp::CHUNK_HDR_LEN + 4,
+                _ => sctp::CHUNK_HDR_LEN,
+            };
+            if chunk_len < min_len {
+                return Err(Error::CorruptPacket(format!(
+                    "Corrupted SCTP packet, chunk type {} too short ({} bytes)",
+                    chunk_type, chunk_len
+                )));
+            }
+
+            if chunk_type == sctp::DATA && app.is_none() {
+                // application layer points to the first DATA chunk's payload
+                app = Some(Layer {
+                    protocol: Protocol::APPLICATION,
+                    offset: offset + (pos + sctp::DATA_HDR_LEN) as u16,
+                });
+            }
+
+            // chunks are padded to 4 bytes
+            pos += (chunk_len + 3) & !3;
+        }
+
+        Ok(app)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn data() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x0e, 0x50, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x03, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00,
+            0x00, 0x00, // SACK
+            0x00, 0x03, 0x00, 0x13, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x2e, 0x01, 0x02, 0x03, 0x00, // DATA
+        ];
+        let layer = PARSER.parse(&buf, 34).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::APPLICATION);
+        assert_eq!(layer.offset, 34 + 44);
+    }
+
+    #[test]
+    fn control_only() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x00, 0x00, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x01, 0x00, 0x00, 0x14, 0x11, 0x22, 0x33, 0x44, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0a,
+            0xff, 0xff, 0x00, 0x00, 0x00, 0x01, // INIT
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // link layer padding
+        ];
+        assert!(PARSER.parse(&buf, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn pkt_too_short() {
+        let buf = [0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x0e, 0x50];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+
+    #[test]
+    fn chunk_too_long() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x0e, 0x50, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00,
+        ];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
 
-        Ok(Some(layer))
+    #[test]
+    fn init_too_short() {
+        let buf = [
+            0x0b, 0x59, 0x0b, 0x59, 0x00, 0x00, 0x00, 0x00, 0x53, 0xc3, 0x05, 0x5f, // header
+            0x01, 0x00, 0x00, 0x08, 0x11, 0x22, 0x33, 0x44,
+        ];
+        assert!(matches!(
+            PARSER.parse(&buf, 0),
+            Err(Error::CorruptPacket(_))
+        ));
     }
 }
@@ -16,6 +16,13 @@ impl SimpleProtocolParser for Parser {
         }
 
         let tcp_hdr_len = (buf[12] >> 4) * 4;
+        if tcp_hdr_len < 20 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted TCP packet, header length too short ({} bytes)",
+                tcp_hdr_len
+            )));
+        }
+
         if tcp_hdr_len as usize > buf.len() {
             return Err(Error::CorruptPacket(format!(
                 "Corrupted TCP packet, packet too short ({} bytes)",
@@ -70,4 +77,14 @@ mod tests {
         let err = result.unwrap_err();
         assert!(matches!(err, Error::CorruptPacket(_)));
     }
+
+    #[test]
+    fn test_err_tcp_hdr_len_too_short() {
+        let buf = [
+            0x04, 0x3f, 0x08, 0x22, 0x04, 0x61, 0x1b, 0xea, 0x00, 0x00, 0x00, 0x00, 0x40, 0x02,
+            0xff, 0xff, 0x7c, 0x77, 0x00, 0x00,
+        ];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result, Err(Error::CorruptPacket(_))));
+    }
 }
@@ -1,5 +1,14 @@
+use super::super::tunnel::{teredo, wireguard};
 use super::{Error, Layer, Protocol, SimpleProtocolParser};
 
+const L2TP_PORT: u16 = 1701;
+const GTP_U_PORT: u16 = 2152;
+const VXLAN_PORT: u16 = 4789;
+const TEREDO_PORT: u16 = 3544;
+const WIREGUARD_PORT: u16 = 51820;
+/// IPsec NAT traversal, RFC 3948
+const NAT_T_PORT: u16 = 4500;
+
 #[derive(Default)]
 pub struct Parser;
 
@@ -17,7 +26,7 @@ impl SimpleProtocolParser for Parser {
 
         let src_port = ((buf[0] as u16) << 8) + buf[1] as u16;
         let dst_port = ((buf[2] as u16) << 8) + buf[3] as u16;
-        if src_port == 1701 && dst_port == 1701 {
+        if src_port == L2TP_PORT && dst_port == L2TP_PORT {
             let layer = Layer {
                 protocol: Protocol::L2TP,
                 offset: offset + 8,
@@ -25,6 +34,52 @@ impl SimpleProtocolParser for Parser {
             return Ok(Some(layer));
         }
 
+        if dst_port == VXLAN_PORT {
+            let layer = Layer {
+                protocol: Protocol::VXLAN,
+                offset: offset + 8,
+            };
+            return Ok(Some(layer));
+        }
+
+        if src_port == GTP_U_PORT || dst_port == GTP_U_PORT {
+            let layer = Layer {
+                protocol: Protocol::GTP,
+                offset: offset + 8,
+            };
+            return Ok(Some(layer));
+        }
+
+        // Teredo and WireGuard ports are also picked as ephemeral ports by other applications,
+        // so the payload has to decode as well
+        if (src_port == TEREDO_PORT || dst_port == TEREDO_PORT)
+            && teredo::Parser::default()
+                .parse(&buf[8..], offset + 8)
+                .is_ok()
+        {
+            let layer = Layer {
+                protocol: Protocol::TEREDO,
+                offset: offset + 8,
+            };
+            return Ok(Some(layer));
+        }
+
+        if (src_port == WIREGUARD_PORT || dst_port == WIREGUARD_PORT)
+            && wireguard::Parser::default()
+                .parse(&buf[8..], offset + 8)
+                .is_ok()
+        {
+            let layer = Layer {
+                protocol: Protocol::WIREGUARD,
+                offset: offset + 8,
+            };
+            return Ok(Some(layer));
+        }
+
+        if src_port == NAT_T_PORT || dst_port == NAT_T_PORT {
+            return Ok(Some(Parser::parse_nat_t(&buf[8..], offset + 8)));
+        }
+
         let layer = Layer {
             protocol: Protocol::APPLICATION,
             offset: offset + 8,
@@ -34,6 +89,33 @@ impl SimpleProtocolParser for Parser {
     }
 }
 
+impl Parser {
+    /// Tell ESP from IKE and keepalive in NAT-T encapsulated payload
+    #[inline]
+    fn parse_nat_t(payload: &[u8], offset: u16) -> Layer {
+        if payload.len() >= 4 && payload[..4] == [0, 0, 0, 0] {
+            // Non-ESP marker, followed by an IKE message
+            return Layer {
+                protocol: Protocol::APPLICATION,
+                offset: offset + 4,
+            };
+        }
+
+        if payload.len() >= 8 {
+            return Layer {
+                protocol: Protocol::ESP,
+                offset,
+            };
+        }
+
+        // NAT keepalive
+        Layer {
+            protocol: Protocol::APPLICATION,
+            offset,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -76,4 +158,74 @@ mod test {
         assert!(matches!(layer.unwrap().protocol, Protocol::L2TP));
         assert_eq!(layer.unwrap().offset, 8);
     }
+
+    #[test]
+    fn vxlan() {
+        let buf = [0xc3, 0x50, 0x12, 0xb5, 0x00, 0x54, 0x00, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::VXLAN);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn gtp() {
+        let buf = [0x08, 0x68, 0x08, 0x68, 0x00, 0x54, 0x00, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::GTP);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn nat_t() {
+        // ESP
+        let buf = [
+            0x11, 0x94, 0x11, 0x94, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x00,
+            0x00, 0x01, 0x8c, 0x2a, 0x61, 0x3e,
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::ESP);
+        assert_eq!(layer.offset, 8);
+
+        // IKE after non-ESP marker
+        let buf = [
+            0x11, 0x94, 0x11, 0x94, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x8c, 0x2a,
+            0x61, 0x3e, 0x9f, 0x07, 0x11, 0x22,
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::APPLICATION);
+        assert_eq!(layer.offset, 12);
+
+        // keepalive
+        let buf = [0x11, 0x94, 0x11, 0x94, 0x00, 0x09, 0x00, 0x00, 0xff];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::APPLICATION);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn tunnels() {
+        let mut buf = vec![0xc0, 0x01, 0x0d, 0xd8, 0x00, 0x30, 0x00, 0x00];
+        buf.push(0x60);
+        buf.extend_from_slice(&[0; 39]);
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::TEREDO);
+        assert_eq!(layer.offset, 8);
+
+        let mut buf = vec![0xca, 0x6c, 0xc0, 0x01, 0x00, 0x28, 0x00, 0x00];
+        buf.extend_from_slice(&[0x04, 0x00, 0x00, 0x00]);
+        buf.extend_from_slice(&[0; 28]);
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::WIREGUARD);
+        assert_eq!(layer.offset, 8);
+
+        // other applications on the tunnel ports, e.g. DNS from ephemeral ports
+        for src_port in [TEREDO_PORT, WIREGUARD_PORT].iter() {
+            let mut buf = src_port.to_be_bytes().to_vec();
+            buf.extend_from_slice(&[0x00, 0x35, 0x00, 0x14, 0x00, 0x00]);
+            buf.extend_from_slice(&[0x12, 0x34, 0x01, 0x00, 0x00, 0x01]);
+            buf.extend_from_slice(&[0; 6]);
+            let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+            assert_eq!(layer.protocol, Protocol::APPLICATION);
+        }
+    }
 }
@@ -0,0 +1,141 @@
+use super::link::ethernet;
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+const CHECKSUM: u8 = 0b10000000;
+const KEY: u8 = 0b00100000;
+const SEQUENCE: u8 = 0b00010000;
+/// Enhanced GRE (PPTP) acknowledgment number present
+const ACK: u8 = 0b10000000;
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 4 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted GRE packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let flags = buf[0];
+        let version = buf[1] & 0x07;
+        let mut length = 4;
+        match version {
+            0 => {
+                if flags & CHECKSUM == CHECKSUM {
+                    length += 4;
+                }
+                if flags & KEY == KEY {
+                    length += 4;
+                }
+                if flags & SEQUENCE == SEQUENCE {
+                    length += 4;
+                }
+            }
+            1 => {
+                // Enhanced GRE always has key field (payload length & call id)
+                length += 4;
+                if flags & SEQUENCE == SEQUENCE {
+                    length += 4;
+                }
+                if buf[1] & ACK == ACK {
+                    length += 4;
+                }
+            }
+            _ => {
+                return Err(Error::CorruptPacket(format!(
+                    "Unsupported or invalid GRE version: {}",
+                    version
+                )))
+            }
+        };
+
+        if buf.len() < length as usize {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted GRE packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let mut layer = Layer {
+            protocol: Protocol::default(),
+            offset: offset + length,
+        };
+
+        let etype = (buf[2] as u16) << 8 | buf[3] as u16;
+        match etype {
+            ethernet::IPV4 => layer.protocol = Protocol::IPV4,
+            ethernet::IPV6 => layer.protocol = Protocol::IPV6,
+            ethernet::PPP => layer.protocol = Protocol::PPP,
+            ethernet::MPLSUC => layer.protocol = Protocol::MPLS,
+            ethernet::ETHBRIDGE => layer.protocol = Protocol::ETHERNET,
+            _ => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport GRE protocol, ether type: {:x}",
+                    etype
+                )))
+            }
+        };
+
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [
+            0x00, 0x00, 0x08, 0x00, // gre
+            0x45, 0x00, 0x00, 0x14, 0x00, 0x0a, 0x00, 0x00, 0xff, 0x01, 0xa5, 0x6a, 0x0a, 0x01,
+            0x02, 0x01, 0x0a, 0x22, 0x00, 0x01, // ipv4
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+    }
+
+    #[test]
+    fn key_present() {
+        let buf = [
+            0x20, 0x00, 0x65, 0x58, 0x00, 0x00, 0x00, 0x2a, // gre
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0xf0, 0x00, 0x08,
+            0x00, // ethernet
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::ETHERNET);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn enhanced_gre() {
+        let buf = [
+            0x30, 0x81, 0x88, 0x0b, 0x00, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x00, 0x00, // gre
+            0xff, 0x03, 0x00, 0x21, // ppp
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::PPP);
+        assert_eq!(layer.offset, 16);
+    }
+
+    #[test]
+    fn pkt_too_short() {
+        let buf = [0xb0, 0x00, 0x08, 0x00, 0x00, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+
+    #[test]
+    fn unsupport_protocol() {
+        let buf = [0x00, 0x00, 0x88, 0xbe];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::UnsupportProtocol(_)));
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+const EXTENSION: u8 = 0b00000100;
+const SEQUENCE: u8 = 0b00000010;
+const NPDU: u8 = 0b00000001;
+
+/// GTP-U message type carrying a user plane packet
+const G_PDU: u8 = 0xff;
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 8 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted GTP packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        let flags = buf[0];
+        let version = flags >> 5;
+        if version != 1 {
+            return Err(Error::CorruptPacket(format!(
+                "Unsupported or invalid GTP-U version: {}",
+                version
+            )));
+        }
+
+        // Echo request/response, error indication etc. carry no user plane packet
+        if buf[1] != G_PDU {
+            return Ok(None);
+        }
+
+        let mut length = 8;
+        if flags & (EXTENSION | SEQUENCE | NPDU) != 0 {
+            length += 4;
+            if buf.len() < length {
+                return Err(Error::CorruptPacket(format!(
+                    "Corrupted GTP packet, packet too short ({} bytes)",
+                    buf.len()
+                )));
+            }
+
+            let mut next_ext = if flags & EXTENSION == EXTENSION {
+                buf[length - 1]
+            } else {
+                0
+            };
+            while next_ext != 0 {
+                let ext_len = match buf.get(length) {
+                    Some(l) if *l > 0 => *l as usize * 4,
+                    _ => {
+                        return Err(Error::CorruptPacket(format!(
+                            "Corrupted GTP packet, invalid extension header"
+                        )))
+                    }
+                };
+                length += ext_len;
+                next_ext = match buf.get(length - 1) {
+                    Some(n) => *n,
+                    None => {
+                        return Err(Error::CorruptPacket(format!(
+                            "Corrupted GTP packet, packet too short ({} bytes)",
+                            buf.len()
+                        )))
+                    }
+                };
+            }
+        }
+
+        let protocol = match buf.get(length) {
+            Some(b) if b >> 4 == 4 => Protocol::IPV4,
+            Some(b) if b >> 4 == 6 => Protocol::IPV6,
+            _ => {
+                return Err(Error::UnsupportProtocol(format!(
+                    "Unsupport GTP-U payload protocol"
+                )))
+            }
+        };
+
+        let layer = Layer {
+            protocol,
+            offset: offset + length as u16,
+        };
+
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [
+            0x30, 0xff, 0x00, 0x14, 0x00, 0x00, 0x00, 0x01, // gtp
+            0x45, 0x00, 0x00, 0x14, 0x00, 0x0a, 0x00, 0x00, 0xff, 0x01, 0xa5, 0x6a, 0x0a, 0x01,
+            0x02, 0x01, 0x0a, 0x22, 0x00, 0x01, // ipv4
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn extension_header() {
+        let buf = [
+            0x34, 0xff, 0x00, 0x30, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x85, // gtp
+            0x01, 0x10, 0x01, 0x00, // pdu session container
+            0x60, 0x00, 0x00, 0x00, // ipv6
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 16);
+    }
+
+    #[test]
+    fn echo_request() {
+        let buf = [
+            0x32, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        ];
+        assert!(matches!(PARSER.parse(&buf, 0), Ok(None)));
+    }
+
+    #[test]
+    fn invalid_version() {
+        let buf = [0x50, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
@@ -0,0 +1,43 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// IPv6 encapsulated in IPv4, a.k.a. 6in4 or SIT, RFC 4213
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.is_empty() || buf[0] >> 4 != 6 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted 6in4 packet, encapsulated packet is not IPv6"
+            )));
+        }
+
+        let layer = Layer {
+            protocol: Protocol::IPV6,
+            offset,
+        };
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [0x60, 0x00, 0x00, 0x00];
+        let layer = PARSER.parse(&buf, 20).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 20);
+    }
+
+    #[test]
+    fn not_ipv6() {
+        let buf = [0x45, 0x00, 0x00, 0x14];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// IPv4/IPv6 encapsulated in IPv4/IPv6, RFC 2003 & RFC 2473
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.is_empty() {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted IPIP packet, no encapsulated packet"
+            )));
+        }
+
+        let protocol = match buf[0] >> 4 {
+            4 => Protocol::IPV4,
+            6 => Protocol::IPV6,
+            version => {
+                return Err(Error::CorruptPacket(format!(
+                    "Corrupted IPIP packet, unknown encapsulated ip version: {}",
+                    version
+                )))
+            }
+        };
+
+        Ok(Some(Layer { protocol, offset }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [0x45, 0x00, 0x00, 0x14];
+        let layer = PARSER.parse(&buf, 20).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 20);
+
+        let buf = [0x60, 0x00, 0x00, 0x00];
+        let layer = PARSER.parse(&buf, 40).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 40);
+    }
+
+    #[test]
+    fn unknown_version() {
+        let buf = [0x25, 0x00, 0x00, 0x14];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        let result = PARSER.parse(&[], 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
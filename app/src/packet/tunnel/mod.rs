@@ -2,7 +2,14 @@ use super::parser::{Error, SimpleProtocolParser};
 use super::{link, network};
 use super::{Layer, Protocol};
 
+pub mod gre;
+pub mod gtp;
+pub mod ip6in4;
+pub mod ipip;
 pub mod l2tp;
 pub mod mpls;
 pub mod ppp;
 pub mod pppoe;
+pub mod teredo;
+pub mod vxlan;
+pub mod wireguard;
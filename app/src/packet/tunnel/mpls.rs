@@ -1,19 +1,36 @@
-#[cfg(feature = "heuristic-mpls")]
 use super::{link, network, ppp, pppoe};
 use super::{Error, Layer, Protocol, SimpleProtocolParser};
 
 #[derive(Default)]
 pub struct Parser {
-    #[cfg(feature = "heuristic-mpls")]
-    ethernet: link::ethernet::Parser,
-    #[cfg(feature = "heuristic-mpls")]
-    ipv4: network::ipv4::Parser,
-    #[cfg(feature = "heuristic-mpls")]
-    ipv6: network::ipv6::Parser,
-    #[cfg(feature = "heuristic-mpls")]
-    ppp: ppp::Parser,
-    #[cfg(feature = "heuristic-mpls")]
-    pppoe: pppoe::Parser,
+    /// Try decoding the payload as Ethernet before IP or a PW control word,
+    /// e.g. Ethernet over MPLS without control word
+    pub heuristic: bool,
+}
+
+impl Parser {
+    /// Whether the payload looks like an Ethernet frame carrying a valid next layer
+    fn is_ethernet(&self, buf: &[u8], offset: u16) -> bool {
+        let layer = match link::ethernet::Parser::default().parse(buf, offset) {
+            Ok(Some(l)) => l,
+            _ => return false,
+        };
+
+        let payload = match buf.get((layer.offset - offset) as usize..) {
+            Some(payload) => payload,
+            None => return false,
+        };
+        let result = match layer.protocol {
+            Protocol::IPV4 => network::ipv4::Parser::default().parse(payload, layer.offset),
+            Protocol::IPV6 => network::ipv6::Parser::default().parse(payload, layer.offset),
+            Protocol::PPP => ppp::Parser::default().parse(payload, layer.offset),
+            Protocol::PPPOE => pppoe::Parser::default().parse(payload, layer.offset),
+            Protocol::MPLS | Protocol::VLAN => return true,
+            _ => return false,
+        };
+
+        result.is_ok()
+    }
 }
 
 impl SimpleProtocolParser for Parser {
@@ -28,138 +45,71 @@ impl SimpleProtocolParser for Parser {
             )));
         }
 
+        // walk through the label stack until the bottom of stack bit is set
         let mut pos: usize = 0;
-
-        let mut mpls_stack_bottom = buf[2] & 0x1;
-        while mpls_stack_bottom == 1 {
+        loop {
+            if pos + 4 > buf.len() {
+                return Err(Error::CorruptPacket(
+                    "Corrupted MPLS packet, label stack exceeds packet length".to_string(),
+                ));
+            }
+            let stack_bottom = buf[pos + 2] & 0x1;
             pos += 4;
-            if pos > buf.len() {
+            if stack_bottom == 1 {
                 break;
             }
+        }
+
+        if pos >= buf.len() {
+            return Err(Error::CorruptPacket(
+                "Corrupted MPLS packet, no payload after label stack".to_string(),
+            ));
+        }
 
-            mpls_stack_bottom = buf[pos + 2] & 0x1;
+        if self.heuristic && self.is_ethernet(&buf[pos..], offset + pos as u16) {
+            let layer = Layer {
+                protocol: Protocol::ETHERNET,
+                offset: offset + pos as u16,
+            };
+            return Ok(Some(layer));
+        }
 
-            #[cfg(feature = "heuristic-mpls")]
-            {
-                // Try to decode it as an Ethernet protocol first
-                // If failed, then try to decode it other ways
-                let layer = match self.ethernet.parse(&buf[pos..], offset + pos as u16) {
-                    Err(_) => None, // If can't detect next layer
-                    Ok(l) => l,
+        match buf[pos] >> 4 {
+            0b0000 => {
+                // PW Ethernet Control Word
+                let layer = Layer {
+                    protocol: Protocol::ETHERNET,
+                    offset: offset + 4 + pos as u16,
                 };
-
-                match layer {
-                    None => {}
-                    Some(l) => {
-                        match l.protocol {
-                            Protocol::IPV4 => {
-                                match self.ipv4.parse(&buf[l.offset as usize..], l.offset) {
-                                    Ok(_) => {
-                                        let layer = Layer {
-                                            protocol: Protocol::ETHERNET,
-                                            offset: offset + pos as u16,
-                                        };
-                                        return Ok(Some(layer));
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            Protocol::IPV6 => {
-                                match self.ipv6.parse(&buf[l.offset as usize..], l.offset) {
-                                    Ok(_) => {
-                                        let layer = Layer {
-                                            protocol: Protocol::ETHERNET,
-                                            offset: offset + pos as u16,
-                                        };
-                                        return Ok(Some(layer));
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            Protocol::PPP => {
-                                match self.ppp.parse(&buf[l.offset as usize..], l.offset) {
-                                    Ok(_) => {
-                                        let layer = Layer {
-                                            protocol: Protocol::ETHERNET,
-                                            offset: offset + pos as u16,
-                                        };
-                                        return Ok(Some(layer));
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            Protocol::MPLS => {
-                                todo! {"too much tunnel layers to handle"}
-                            }
-                            Protocol::PPPOE => {
-                                match self.pppoe.parse(&buf[l.offset as usize..], l.offset) {
-                                    Ok(_) => {
-                                        let layer = Layer {
-                                            protocol: Protocol::ETHERNET,
-                                            offset: offset + pos as u16,
-                                        };
-                                        return Ok(Some(layer));
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            Protocol::VLAN => {
-                                todo! {"too much tunnel layers to handle"}
-                            }
-                            _ => {} // do nothing, unsupported protocol
-                        };
-                    }
+                Ok(Some(layer))
+            }
+            0b0100 => {
+                let layer = Layer {
+                    protocol: Protocol::IPV4,
+                    offset: offset + pos as u16,
                 };
+                Ok(Some(layer))
             }
-
-            match buf[offset as usize + pos] >> 4 {
-                0b0000 => {
-                    // PW Ethernet Control Word
-                    let layer = Layer {
-                        protocol: Protocol::ETHERNET,
-                        offset: offset + 4 + pos as u16,
-                    };
-                    return Ok(Some(layer));
-                }
-                0b0100 => {
-                    let layer = Layer {
-                        protocol: Protocol::IPV4,
-                        offset: offset + pos as u16,
-                    };
-                    return Ok(Some(layer));
-                }
-                0b0110 => {
-                    let layer = Layer {
-                        protocol: Protocol::IPV6,
-                        offset: offset + pos as u16,
-                    };
-                    return Ok(Some(layer));
-                }
-                _ => {}
-            };
+            0b0110 => {
+                let layer = Layer {
+                    protocol: Protocol::IPV6,
+                    offset: offset + pos as u16,
+                };
+                Ok(Some(layer))
+            }
+            _ => Err(Error::CorruptPacket(
+                "Corrupted MPLS packet, at mpls stack bottom but no valid network layer found"
+                    .to_string(),
+            )),
         }
-
-        return Err(Error::CorruptPacket(format!(
-            "Corrupted MPLS packet, at mpls stack bottom but no valid network layer found",
-        )));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    const PARSER: Parser = Parser {
-        #[cfg(feature = "heuristic-mpls")]
-        ethernet: link::ethernet::Parser {},
-        #[cfg(feature = "heuristic-mpls")]
-        ipv4: network::ipv4::Parser {},
-        #[cfg(feature = "heuristic-mpls")]
-        ipv6: network::ipv6::Parser {},
-        #[cfg(feature = "heuristic-mpls")]
-        ppp: ppp::Parser {},
-        #[cfg(feature = "heuristic-mpls")]
-        pppoe: pppoe::Parser {},
-    };
+    const PARSER: Parser = Parser { heuristic: false };
+    const HEURISTIC_PARSER: Parser = Parser { heuristic: true };
 
     #[test]
     fn single_layer_mpls_with_ipv4() {
@@ -175,12 +125,10 @@ mod tests {
             0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, // icmp
         ];
 
-        match PARSER.parse(&buffer, 0) {
-            Ok(l) => {
-                assert_eq!(l.unwrap().protocol, Protocol::IPV4);
-                assert_eq!(l.unwrap().offset, 4);
-            }
-            Err(_) => {}
+        for parser in &[PARSER, HEURISTIC_PARSER] {
+            let l = parser.parse(&buffer, 0).unwrap().unwrap();
+            assert_eq!(l.protocol, Protocol::IPV4);
+            assert_eq!(l.offset, 4);
         }
     }
 
@@ -197,37 +145,9 @@ mod tests {
             0x02, 0x61, 0x74, 0x00, 0x00, 0x01, 0x00, 0x01, // dns,
         ];
 
-        match PARSER.parse(&buffer, 0) {
-            Ok(l) => {
-                assert_eq!(l.unwrap().protocol, Protocol::IPV6);
-                assert_eq!(l.unwrap().offset, 4);
-            }
-            Err(_) => {}
-        }
-    }
-
-    #[test]
-    fn double_layer_mpls_with_ethernet() {
-        let buffer = [
-            0x00, 0x01, 0x20, 0xfe, // mpls
-            0x00, 0x01, 0x01, 0xff, // mpls
-            0x00, 0x00, 0x00, 0x00, // PW Ethernet Control Word
-            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0xf0, 0x00, 0x00,
-            0x26, // Ethernet
-            0x42, 0x42, 0x03, // Logical-Link Control
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x80, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0x00, 0x00, 0x80, 0x01, 0x00,
-            0x00, 0x14, 0x00, 0x02, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, // Spanning Tree Protocol
-        ];
-
-        match PARSER.parse(&buffer, 0) {
-            Ok(l) => {
-                assert_eq!(l.unwrap().protocol, Protocol::ETHERNET);
-                assert_eq!(l.unwrap().offset, 8);
-            }
-            Err(_) => {}
-        }
+        let l = PARSER.parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(l.protocol, Protocol::IPV6);
+        assert_eq!(l.offset, 4);
     }
 
     #[test]
@@ -245,17 +165,14 @@ mod tests {
             0x00, // Spanning Tree Protocol
         ];
 
-        match PARSER.parse(&buffer, 0) {
-            Ok(l) => {
-                assert_eq!(l.unwrap().protocol, Protocol::ETHERNET);
-                assert_eq!(l.unwrap().offset, 8);
-            }
-            Err(_) => {}
+        for parser in &[PARSER, HEURISTIC_PARSER] {
+            let l = parser.parse(&buffer, 0).unwrap().unwrap();
+            assert_eq!(l.protocol, Protocol::ETHERNET);
+            assert_eq!(l.offset, 12);
         }
     }
 
     #[test]
-    #[cfg(feature = "heuristic-mpls")]
     fn ethernet_over_mpls_without_pw_contrtol_word() {
         let buffer = [
             0x00, 0x01, 0x01, 0xff, // mpls
@@ -271,12 +188,37 @@ mod tests {
             0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xab, 0xcd, // icmp
         ];
 
-        match PARSER.parse(&buffer, 0) {
-            Ok(l) => {
-                assert_eq!(l.unwrap().protocol, Protocol::ETHERNET);
-                assert_eq!(l.unwrap().offset, 4);
-            }
-            Err(_) => {}
-        }
+        let l = HEURISTIC_PARSER.parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(l.protocol, Protocol::ETHERNET);
+        assert_eq!(l.offset, 4);
+
+        // without heuristic, the first nibble of destination mac is taken as control word
+        let l = PARSER.parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(l.protocol, Protocol::ETHERNET);
+        assert_eq!(l.offset, 8);
+    }
+
+    #[test]
+    fn unknown_payload() {
+        let buffer = [
+            0x00, 0x01, 0x01, 0xff, // mpls
+            0x81, 0x80, 0xc2, 0x00, 0x00, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0xf0, 0x00, 0x08, 0x00,
+        ];
+        assert!(matches!(
+            PARSER.parse(&buffer, 0),
+            Err(Error::CorruptPacket(_))
+        ));
+    }
+
+    #[test]
+    fn label_stack_too_long() {
+        let buffer = [
+            0x00, 0x01, 0x20, 0xfe, // mpls
+            0x00, 0x01, 0x20, 0xfe, // mpls
+        ];
+        assert!(matches!(
+            PARSER.parse(&buffer, 0),
+            Err(Error::CorruptPacket(_))
+        ));
     }
 }
@@ -1,3 +1,6 @@
+use alphonse_api as api;
+use api::packet::ppp;
+
 use super::{Error, Layer, Protocol, SimpleProtocolParser};
 
 #[derive(Default)]
@@ -6,25 +9,92 @@ pub struct Parser;
 impl SimpleProtocolParser for Parser {
     #[inline]
     fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
-        if buf.len() < 4 || buf[2] != 0x00 {
-            return Err(Error::CorruptPacket(format!("Corrupted PPPOE packet")));
+        if buf.len() < 2 {
+            return Err(Error::CorruptPacket("Corrupted PPP packet".to_string()));
         }
 
-        let layer = match buf[3] {
-            0x21 => Layer {
+        // address and control field may be omitted
+        let mut pos = if buf[0] == 0xff && buf[1] == 0x03 {
+            2
+        } else {
+            0
+        };
+        // protocol field may be compressed to 1 byte, whose lowest bit is always set
+        let protocol = match buf.get(pos..) {
+            Some(b) if !b.is_empty() && b[0] & 0x01 == 0x01 => {
+                pos += 1;
+                b[0] as u16
+            }
+            Some(b) if b.len() >= 2 => {
+                pos += 2;
+                ((b[0] as u16) << 8) | b[1] as u16
+            }
+            _ => return Err(Error::CorruptPacket("Corrupted PPP packet".to_string())),
+        };
+
+        let layer = match protocol {
+            ppp::IPV4 => Layer {
                 protocol: Protocol::IPV4,
-                offset: offset + 4,
+                offset: offset + pos as u16,
             },
-            0x57 => Layer {
+            ppp::IPV6 => Layer {
                 protocol: Protocol::IPV6,
-                offset: offset + 4,
+                offset: offset + pos as u16,
             },
+            _ if ppp::is_control(protocol) => {
+                if ppp::ControlPacket::parse(&buf[pos..]).is_none() {
+                    return Err(Error::CorruptPacket(format!(
+                        "Corrupted PPP packet, truncated control packet {:#x}",
+                        protocol
+                    )));
+                }
+                return Ok(None);
+            }
             _ => Layer {
                 protocol: Protocol::UNKNOWN,
-                offset: offset + 4,
+                offset: offset + pos as u16,
             },
         };
 
         Ok(Some(layer))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ipv4() {
+        let buf = [0xff, 0x03, 0x00, 0x21, 0x45, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 4);
+    }
+
+    #[test]
+    fn compressed() {
+        // no address & control field, compressed protocol field
+        let buf = [0x21, 0x45, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 1);
+
+        let buf = [0x00, 0x57, 0x60, 0x00];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 2);
+    }
+
+    #[test]
+    fn lcp() {
+        let buf = [
+            0xff, 0x03, 0xc0, 0x21, 0x05, 0x02, 0x00, 0x04, // Terminate-Request
+        ];
+        assert!(PARSER.parse(&buf, 0).unwrap().is_none());
+
+        let result = PARSER.parse(&buf[..6], 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
@@ -1,3 +1,6 @@
+use alphonse_api as api;
+use api::packet::ppp;
+
 use super::{Error, Layer, Protocol, SimpleProtocolParser};
 
 #[derive(Default)]
@@ -6,31 +9,125 @@ pub struct Parser;
 impl SimpleProtocolParser for Parser {
     #[inline]
     fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
-        if buf.len() < 8 || buf[0] != 0x11 || buf[1] != 0 {
+        if buf.len() < ppp::PPPOE_HDR_LEN || buf[0] != 0x11 {
             return Err(Error::CorruptPacket(format!("Corrupted PPPOE packet")));
         }
 
+        // Ethernet frames may be padded, so payload could be shorter than the frame
         let plen = (((buf[4] as u16) << 8) | buf[5] as u16) as usize;
-        if plen != buf.len() - 6 {
-            return Err(Error::CorruptPacket(format!("Corrupted PPPOE packet")));
+        if plen > buf.len() - ppp::PPPOE_HDR_LEN {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted PPPOE packet, payload length {} exceeds packet length",
+                plen
+            )));
+        }
+        let payload = &buf[ppp::PPPOE_HDR_LEN..ppp::PPPOE_HDR_LEN + plen];
+
+        match buf[1] {
+            ppp::PPPOE_SESSION => {
+                Parser::parse_session(payload, offset + ppp::PPPOE_HDR_LEN as u16)
+            }
+            // discovery packets carry only tags, nothing to decode further
+            ppp::PADI | ppp::PADO | ppp::PADR | ppp::PADS | ppp::PADT => Ok(None),
+            code => Err(Error::CorruptPacket(format!(
+                "Corrupted PPPOE packet, unknown code: {:#x}",
+                code
+            ))),
         }
+    }
+}
 
-        let protocol = ((buf[6] as u16) << 8) | buf[7] as u16;
+impl Parser {
+    /// Parse PPP frame of PPPoE session stage
+    #[inline]
+    fn parse_session(payload: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if payload.len() < 2 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted PPPOE packet, no PPP protocol field"
+            )));
+        }
+
+        let protocol = ((payload[0] as u16) << 8) | payload[1] as u16;
         let layer = match protocol {
-            0x21 => Layer {
+            ppp::IPV4 => Layer {
                 protocol: Protocol::IPV4,
-                offset: offset + 8,
+                offset: offset + 2,
             },
-            0x57 => Layer {
+            ppp::IPV6 => Layer {
                 protocol: Protocol::IPV6,
-                offset: offset + 8,
+                offset: offset + 2,
             },
+            _ if ppp::is_control(protocol) => {
+                if ppp::ControlPacket::parse(&payload[2..]).is_none() {
+                    return Err(Error::CorruptPacket(format!(
+                        "Corrupted PPPOE packet, truncated PPP control packet {:#x}",
+                        protocol
+                    )));
+                }
+                return Ok(None);
+            }
             _ => Layer {
                 protocol: Protocol::UNKNOWN,
-                offset: offset + 8,
+                offset: offset + 2,
             },
         };
 
         Ok(Some(layer))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn session() {
+        let buf = [
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x16, 0x00, 0x21, // pppoe & ppp
+            0x45, 0x00, 0x00, 0x14, 0x00, 0x00, 0x40, 0x00, 0x40, 0xff, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x01, 0x0a, 0x00, 0x00, 0x02, // ipv4
+        ];
+        let layer = PARSER.parse(&buf, 14).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV4);
+        assert_eq!(layer.offset, 22);
+    }
+
+    #[test]
+    fn control() {
+        let buf = [
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x10, 0xc0, 0x23, // pppoe & ppp
+            0x01, 0x01, 0x00, 0x0e, 0x04, b'u', b's', b'e', b'r', 0x04, b'p', b'a', b's',
+            b's', // pap
+            0x00, 0x00, 0x00, 0x00, // ethernet padding
+        ];
+        assert!(PARSER.parse(&buf, 14).unwrap().is_none());
+
+        // truncated LCP
+        let buf = [
+            0x11, 0x00, 0x00, 0x2a, 0x00, 0x06, 0xc0, 0x21, 0x01, 0x01, 0x00, 0x0e,
+        ];
+        let result = PARSER.parse(&buf, 14);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+
+    #[test]
+    fn discovery() {
+        let buf = [
+            0x11, 0x09, 0x00, 0x00, 0x00, 0x04, 0x01, 0x01, 0x00, 0x00, // PADI
+        ];
+        assert!(PARSER.parse(&buf, 14).unwrap().is_none());
+
+        let mut buf = buf;
+        buf[1] = 0x42;
+        let result = PARSER.parse(&buf, 14);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+
+    #[test]
+    fn payload_too_long() {
+        let buf = [0x11, 0x00, 0x00, 0x2a, 0x00, 0x20, 0x00, 0x21];
+        let result = PARSER.parse(&buf, 14);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
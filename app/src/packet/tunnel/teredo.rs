@@ -0,0 +1,96 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// Authentication indicator type
+const AUTH_INDICATOR: u16 = 0x0001;
+/// Origin indicator type
+const ORIGIN_INDICATOR: u16 = 0x0000;
+/// Origin indicator length, type, obfuscated port and obfuscated ipv4 address
+const ORIGIN_INDICATOR_LEN: usize = 8;
+/// Nonce and confirmation byte following authentication value
+const AUTH_TRAILER_LEN: usize = 9;
+const IPV6_HDR_LEN: usize = 40;
+
+/// Teredo, IPv6 over UDP over IPv4, RFC 4380
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        let mut pos = 0;
+
+        // authentication indicator always precedes origin indicator
+        if buf.len() >= 4 && (buf[0] as u16) << 8 | buf[1] as u16 == AUTH_INDICATOR {
+            let id_len = buf[2] as usize;
+            let auth_len = buf[3] as usize;
+            pos += 4 + id_len + auth_len + AUTH_TRAILER_LEN;
+        }
+
+        if buf.len() >= pos + 2 && (buf[pos] as u16) << 8 | buf[pos + 1] as u16 == ORIGIN_INDICATOR
+        {
+            pos += ORIGIN_INDICATOR_LEN;
+        }
+
+        if buf.len() < pos + IPV6_HDR_LEN {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted Teredo packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        if buf[pos] >> 4 != 6 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted Teredo packet, encapsulated packet is not IPv6"
+            )));
+        }
+
+        let layer = Layer {
+            protocol: Protocol::IPV6,
+            offset: offset + pos as u16,
+        };
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    const IPV6: [u8; 40] = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3b, 0x15, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x80, 0x00, 0xf2, 0x27, 0xbe, 0xc4, 0x66, 0x0d, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x80, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    ];
+
+    #[test]
+    fn ipv6() {
+        let layer = PARSER.parse(&IPV6, 28).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 28);
+    }
+
+    #[test]
+    fn indicators() {
+        let mut buf = vec![
+            0x00, 0x01, 0x00, 0x00, // authentication indicator without id & auth value
+            0x8a, 0xde, 0xb0, 0xd0, 0x2e, 0xea, 0x0b, 0xfc, 0x00, // nonce & confirmation
+            0x00, 0x00, 0xf2, 0x27, 0xbe, 0xc4, 0x66, 0x0d, // origin indicator
+        ];
+        buf.extend_from_slice(&IPV6);
+        let layer = PARSER.parse(&buf, 28).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::IPV6);
+        assert_eq!(layer.offset, 28 + 21);
+    }
+
+    #[test]
+    fn not_ipv6() {
+        let mut buf = IPV6;
+        buf[0] = 0x45;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        let result = PARSER.parse(&IPV6[..20], 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
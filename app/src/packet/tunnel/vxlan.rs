@@ -0,0 +1,64 @@
+use super::{Error, Layer, Protocol, SimpleProtocolParser};
+
+/// VNI present flag
+const VNI: u8 = 0b00001000;
+
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 8 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted VXLAN packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        if buf[0] & VNI != VNI {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted VXLAN packet, VNI flag is not set"
+            )));
+        }
+
+        let layer = Layer {
+            protocol: Protocol::ETHERNET,
+            offset: offset + 8,
+        };
+
+        Ok(Some(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let buf = [
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7b, 0x00, // vxlan
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0xcc, 0x04, 0x0d, 0x5c, 0xf0, 0x00, 0x08,
+            0x00, // ethernet
+        ];
+        let layer = PARSER.parse(&buf, 0).unwrap().unwrap();
+        assert_eq!(layer.protocol, Protocol::ETHERNET);
+        assert_eq!(layer.offset, 8);
+    }
+
+    #[test]
+    fn vni_flag_not_set() {
+        let buf = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7b, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+
+    #[test]
+    fn pkt_too_short() {
+        let buf = [0x08, 0x00, 0x00];
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
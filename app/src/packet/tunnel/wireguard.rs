@@ -0,0 +1,108 @@
+use super::{Error, Layer, SimpleProtocolParser};
+
+const HANDSHAKE_INITIATION: u8 = 1;
+const HANDSHAKE_RESPONSE: u8 = 2;
+const COOKIE_REPLY: u8 = 3;
+const TRANSPORT_DATA: u8 = 4;
+
+const HANDSHAKE_INITIATION_LEN: usize = 148;
+const HANDSHAKE_RESPONSE_LEN: usize = 92;
+const COOKIE_REPLY_LEN: usize = 64;
+/// Transport data header length, type, reserved, receiver index and counter
+const TRANSPORT_HDR_LEN: usize = 16;
+/// Poly1305 authentication tag length
+const AUTH_TAG_LEN: usize = 16;
+
+/// WireGuard message framing, payload is encrypted so there is no next layer
+#[derive(Default)]
+pub struct Parser {}
+
+impl SimpleProtocolParser for Parser {
+    #[inline]
+    fn parse(&self, buf: &[u8], _offset: u16) -> Result<Option<Layer>, Error> {
+        if buf.len() < 4 {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted WireGuard packet, packet too short ({} bytes)",
+                buf.len()
+            )));
+        }
+
+        if buf[1..4] != [0, 0, 0] {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted WireGuard packet, reserved bytes are not zero"
+            )));
+        }
+
+        let valid = match buf[0] {
+            HANDSHAKE_INITIATION => buf.len() == HANDSHAKE_INITIATION_LEN,
+            HANDSHAKE_RESPONSE => buf.len() == HANDSHAKE_RESPONSE_LEN,
+            COOKIE_REPLY => buf.len() == COOKIE_REPLY_LEN,
+            // encrypted packets are padded to 16 bytes, keepalive has an empty one
+            TRANSPORT_DATA => {
+                buf.len() >= TRANSPORT_HDR_LEN + AUTH_TAG_LEN
+                    && (buf.len() - TRANSPORT_HDR_LEN) % 16 == 0
+            }
+            msg_type => {
+                return Err(Error::CorruptPacket(format!(
+                    "Corrupted WireGuard packet, unknown message type: {}",
+                    msg_type
+                )))
+            }
+        };
+
+        if !valid {
+            return Err(Error::CorruptPacket(format!(
+                "Corrupted WireGuard packet, invalid length {} of message type {}",
+                buf.len(),
+                buf[0]
+            )));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PARSER: Parser = Parser {};
+
+    #[test]
+    fn ok() {
+        let mut buf = vec![0u8; HANDSHAKE_INITIATION_LEN];
+        buf[0] = HANDSHAKE_INITIATION;
+        assert!(PARSER.parse(&buf, 0).unwrap().is_none());
+
+        // keepalive
+        let mut buf = vec![0u8; 32];
+        buf[0] = TRANSPORT_DATA;
+        assert!(PARSER.parse(&buf, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_length() {
+        let mut buf = vec![0u8; HANDSHAKE_RESPONSE_LEN + 1];
+        buf[0] = HANDSHAKE_RESPONSE;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        let mut buf = vec![0u8; 40];
+        buf[0] = TRANSPORT_DATA;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+
+    #[test]
+    fn not_wireguard() {
+        let mut buf = vec![0u8; COOKIE_REPLY_LEN];
+        buf[0] = COOKIE_REPLY;
+        buf[2] = 1;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+
+        buf[0] = 5;
+        buf[2] = 0;
+        let result = PARSER.parse(&buf, 0);
+        assert!(matches!(result.unwrap_err(), Error::CorruptPacket(_)));
+    }
+}
@@ -8,7 +8,7 @@ use yaml_rust::Yaml;
 
 use alphonse_api as api;
 use api::classifiers::matched::Rule;
-use api::packet::{Layers, Packet as PacketTrait, Rules, Tunnel};
+use api::packet::{Direction, Layers, Packet as PacketTrait, Rules, Tunnel};
 use api::utils::timeval::{precision, TimeVal};
 
 use crate::config::Config;
@@ -205,6 +205,7 @@ pub struct Packet {
     layers: Layers,
     rules: Rules,
     tunnel: Tunnel,
+    direction: Direction,
     drop: bool,
 }
 
@@ -229,6 +230,7 @@ impl Clone for Packet {
             layers: self.layers.clone(),
             rules: self.rules.clone(),
             tunnel: self.tunnel,
+            direction: self.direction,
             drop: self.drop,
         }
     }
@@ -274,4 +276,12 @@ impl PacketTrait for Packet {
     fn clone_box(&self) -> Box<dyn PacketTrait + '_> {
         Box::new(self.clone())
     }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn direction_mut(&mut self) -> &mut Direction {
+        &mut self.direction
+    }
 }
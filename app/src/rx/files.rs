@@ -146,6 +146,7 @@ impl RxThread {
 
 struct Offline {
     cap: Box<pcap::Capture<pcap::Offline>>,
+    link_type: u16,
 }
 
 impl Offline {
@@ -164,8 +165,10 @@ impl Offline {
             Ok(v) => pcap_file = v,
         }
 
+        let link_type = pcap_file.get_datalink().0 as u16;
         Ok(Offline {
             cap: Box::new(pcap_file),
+            link_type,
         })
     }
 }
@@ -174,7 +177,7 @@ impl Offline {
     #[inline]
     fn next(&mut self) -> Result<Box<dyn PacketTrait>> {
         let raw = self.cap.as_mut().next()?;
-        let pkt = Box::new(Packet::from(&raw));
+        let pkt = Box::new(Packet::new(&raw, self.link_type));
         Ok(pkt)
     }
 
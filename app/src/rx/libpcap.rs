@@ -8,7 +8,7 @@ use crossbeam_channel::Sender;
 use alphonse_api as api;
 use api::classifiers::matched::Rule;
 use api::packet::Packet as PacketTrait;
-use api::packet::{Layers, Rules, Tunnel};
+use api::packet::{Direction, Layers, Rules, Tunnel};
 
 use crate::config::Config;
 use crate::rx::RxUtility;
@@ -117,13 +117,14 @@ impl RxThread {
 
 struct NetworkInterface {
     cap: Box<pcap::Capture<pcap::Active>>,
+    link_type: u16,
 }
 
 impl NetworkInterface {
     #[inline]
     fn next(&mut self) -> Result<Box<dyn PacketTrait>, pcap::Error> {
         let raw = self.cap.as_mut().next()?;
-        let pkt: Box<Packet> = Box::new(Packet::from(&raw));
+        let pkt: Box<Packet> = Box::new(Packet::new(&raw, self.link_type));
         Ok(pkt)
     }
 
@@ -155,7 +156,11 @@ impl NetworkInterface {
                     .buffer_size(i32::MAX)
                     .open()
                     .unwrap();
-                Ok(NetworkInterface { cap: Box::new(cap) })
+                let link_type = cap.get_datalink().0 as u16;
+                Ok(NetworkInterface {
+                    cap: Box::new(cap),
+                    link_type,
+                })
             }
             Err(_) => todo!(),
         }
@@ -170,6 +175,8 @@ pub struct Packet {
     layers: Layers,
     rules: Rules,
     tunnel: Tunnel,
+    link_type: u16,
+    direction: Direction,
 }
 
 impl PacketTrait for Packet {
@@ -212,10 +219,29 @@ impl PacketTrait for Packet {
     fn clone_box(&self) -> Box<dyn PacketTrait + '_> {
         Box::new(self.clone())
     }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn direction_mut(&mut self) -> &mut Direction {
+        &mut self.direction
+    }
+
+    fn link_type(&self) -> u16 {
+        self.link_type
+    }
 }
 
-impl From<&pcap::Packet<'_>> for Packet {
-    fn from(pkt: &pcap::Packet) -> Self {
+impl Packet {
+    /// Create a packet from a libpcap packet
+    ///
+    /// # Arguments
+    ///
+    /// * `pkt` - Packet read from libpcap
+    ///
+    /// * `link_type` - Data link type of the capture
+    pub fn new(pkt: &pcap::Packet, link_type: u16) -> Self {
         Packet {
             raw: Vec::from(pkt.data),
             ts: pkt.header.ts,
@@ -223,6 +249,8 @@ impl From<&pcap::Packet<'_>> for Packet {
             layers: Layers::default(),
             rules: Rules::default(),
             tunnel: Tunnel::default(),
+            link_type,
+            direction: Direction::default(),
         }
     }
 }
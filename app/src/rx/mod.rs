@@ -1,4 +1,8 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread::JoinHandle;
 
 use anyhow::Result;
@@ -7,8 +11,8 @@ use dashmap::DashMap;
 use fnv::{FnvBuildHasher, FnvHashMap};
 
 use alphonse_api as api;
-use api::packet::{Packet, PacketHashKey};
-use api::parsers::{ParserID, ProtocolParserTrait};
+use api::packet::{ppp, Packet, PacketHashKey, Protocol};
+use api::parsers::{ParserID, ProtocolParserTrait, StreamData};
 use api::session::Session;
 
 use crate::config::Config;
@@ -17,6 +21,7 @@ use crate::config::Config;
 pub mod dpdk;
 pub mod files;
 pub mod libpcap;
+pub mod reassembly;
 
 pub struct RxUtility {
     pub init: fn(cfg: &mut Config) -> Result<()>,
@@ -31,6 +36,12 @@ pub struct RxUtility {
 pub struct SessionData {
     pub info: Box<Session>,
     pub parsers: Box<FnvHashMap<ParserID, Box<dyn ProtocolParserTrait>>>,
+    /// TCP stream reassembler, only allocated once a stream parser joins the session
+    pub tcp: Option<Box<reassembly::TcpReassembler>>,
+    /// Fire time of the session's pending timer
+    pub expire_at: u64,
+    /// Bytes of this session accounted by the session limits
+    pub memory: usize,
 }
 
 impl Default for SessionData {
@@ -39,8 +50,482 @@ impl Default for SessionData {
             // Here we use Session::new(), since default() doesn't generate the struct we need
             info: Box::new(Session::new()),
             parsers: Box::new(FnvHashMap::default()),
+            tcp: None,
+            expire_at: 0,
+            memory: 0,
+        }
+    }
+}
+
+impl SessionData {
+    /// Approximate bytes held by this session, its parsers' states and reassembly buffers
+    pub fn memory_usage(&self) -> usize {
+        let parsers: usize = self.parsers.values().map(|parser| parser.memory()).sum();
+        let tcp = self.tcp.as_ref().map_or(0, |tcp| {
+            std::mem::size_of::<reassembly::TcpReassembler>() + tcp.buffered()
+        });
+        std::mem::size_of::<SessionData>()
+            + std::mem::size_of::<Session>()
+            + self.info.fields.memory()
+            + parsers
+            + tcp
+    }
+
+    /// Reassemble a TCP packet, and hand over in-order stream data to stream parsers
+    ///
+    /// # Arguments
+    ///
+    /// * `pkt` - TCP packet of this session
+    /// * `budget` - Max bytes buffered for out of order segments of this session
+    pub fn reassemble(&mut self, pkt: &dyn Packet, budget: usize) -> Result<()> {
+        if self.tcp.is_none() {
+            if !self.parsers.values().any(|parser| parser.stream_parser()) {
+                return Ok(());
+            }
+            self.tcp = Some(Box::new(reassembly::TcpReassembler::new(budget)));
+        }
+
+        let direction = pkt.direction();
+        let parsers = &mut self.parsers;
+        let info = &mut self.info;
+        self.tcp.as_mut().unwrap().insert(
+            direction,
+            pkt.tcp_flags(),
+            pkt.tcp_seq(),
+            pkt.payload(),
+            |data| {
+                for parser in parsers.values_mut().filter(|p| p.stream_parser()) {
+                    parser.parse_stream(data, direction, info.as_mut())?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Deliver buffered TCP stream data, then finish all protocol parsers
+    pub fn finish(&mut self) {
+        let parsers = &mut self.parsers;
+        let info = &mut self.info;
+        if let Some(tcp) = self.tcp.as_mut() {
+            let result = tcp.flush(|direction, data: StreamData| {
+                for parser in parsers.values_mut().filter(|p| p.stream_parser()) {
+                    parser.parse_stream(data, direction, info.as_mut())?;
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                eprintln!("Failed to parse buffered TCP stream data: {}", e);
+            }
+        }
+
+        for (_, parser) in parsers.iter_mut() {
+            parser.finish(info.as_mut());
         }
     }
 }
 
 pub type SessionTable = DashMap<PacketHashKey, Box<SessionData>, FnvBuildHasher>;
+
+/// Limits of the session table, and the accounting of its sessions
+///
+/// Once a limit is hit, sessions are evicted until the table is 1% below its limits,
+/// so a flood of new sessions doesn't evict on every packet.
+#[derive(Default)]
+pub struct SessionLimits {
+    /// Max sessions, 0 for unlimited
+    max_sessions: usize,
+    /// Max bytes of all sessions, 0 for unlimited
+    max_memory: usize,
+    sessions: AtomicUsize,
+    memory: AtomicUsize,
+    /// Total evicted sessions
+    evicted: AtomicU64,
+}
+
+impl SessionLimits {
+    pub fn new(cfg: &Config) -> Self {
+        SessionLimits {
+            max_sessions: cfg.ses_max_sessions,
+            max_memory: cfg.ses_max_memory,
+            ..Default::default()
+        }
+    }
+
+    /// Account a session inserted into the session table
+    pub fn add(&self, ses: &mut SessionData) {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        self.update(ses);
+    }
+
+    /// Account the memory change of a session since it's last accounted
+    pub fn update(&self, ses: &mut SessionData) {
+        let memory = ses.memory_usage();
+        if memory > ses.memory {
+            self.memory
+                .fetch_add(memory - ses.memory, Ordering::Relaxed);
+        } else {
+            self.memory
+                .fetch_sub(ses.memory - memory, Ordering::Relaxed);
+        }
+        ses.memory = memory;
+    }
+
+    /// Account a session removed from the session table
+    pub fn remove(&self, ses: &SessionData) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+        self.memory.fetch_sub(ses.memory, Ordering::Relaxed);
+    }
+
+    /// Total evicted sessions
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Whether a new session exceeds the limits
+    pub fn exceeded(&self) -> bool {
+        self.exceeds(|max| max)
+    }
+
+    #[inline]
+    fn exceeds<F: Fn(usize) -> usize>(&self, limit: F) -> bool {
+        (self.max_sessions > 0 && self.sessions.load(Ordering::Relaxed) >= limit(self.max_sessions))
+            || (self.max_memory > 0
+                && self.memory.load(Ordering::Relaxed) >= limit(self.max_memory))
+    }
+
+    /// Evict sessions until the table is back under its low watermarks
+    ///
+    /// Sessions of all shards are ranked together, incomplete sessions, single direction,
+    /// half-open or closed ones, are evicted first, then the most idle ones. Evicted
+    /// sessions are no longer accounted, the caller is responsible for finishing and
+    /// saving them.
+    pub fn evict(&self, table: &SessionTable) -> Vec<(PacketHashKey, Box<SessionData>)> {
+        let low_watermark = |max: usize| max - max / 100;
+        let mut evicted = Vec::new();
+
+        // rank with one shard locked at a time, sessions are only removed afterwards
+        let mut candidates = Vec::with_capacity(table.len());
+        for shard in table.shards() {
+            let shard = shard.read();
+            candidates.extend(
+                shard
+                    .iter()
+                    .map(|(key, ses)| (eviction_priority(&ses.get().info), key.clone())),
+            );
+        }
+        candidates.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+        for (_, key) in candidates {
+            if !self.exceeds(low_watermark) {
+                break;
+            }
+            // the session could have been removed by another thread in between
+            if let Some((key, ses)) = table.remove(&key) {
+                self.remove(&ses);
+                evicted.push((key, ses));
+            }
+        }
+
+        self.evicted
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        evicted
+    }
+}
+
+/// Eviction priority of a session, the greater the earlier it's evicted
+#[inline]
+fn eviction_priority(ses: &Session) -> (bool, Reverse<i64>) {
+    let incomplete = ses.pkt_count.contains(&0)
+        || ses.tcp_closed()
+        || ses
+            .tcp
+            .as_ref()
+            .map_or(false, |tcp| !tcp.handshake_complete);
+    (incomplete, Reverse(ses.end_time.tv_sec as i64))
+}
+
+/// (src ip, dst ip, spi) of an ESP security association
+type SaKey = (IpAddr, IpAddr, u32);
+
+/// Pairs the two unidirectional ESP security associations between two endpoints,
+/// so both directions of an IPsec tunnel belong to the same session.
+///
+/// The first SPI seen between two endpoints waits for an unknown SPI of the reverse
+/// direction, both SPIs are then mapped to the first one, the canonical SPI.
+#[derive(Default)]
+pub struct SpiTable {
+    /// (src ip, dst ip, spi) => canonical spi
+    spis: DashMap<SaKey, u32, FnvBuildHasher>,
+    /// (src ip, dst ip) => spi waiting for its reverse direction
+    pending: DashMap<(IpAddr, IpAddr), u32, FnvBuildHasher>,
+    /// (smaller ip, larger ip, canonical spi) => keys of the SA pair in `spis`
+    pairs: DashMap<SaKey, Vec<SaKey>, FnvBuildHasher>,
+}
+
+impl SpiTable {
+    #[inline]
+    fn pair(a: IpAddr, b: IpAddr, spi: u32) -> SaKey {
+        (a.min(b), a.max(b), spi)
+    }
+
+    /// Get canonical spi of an ESP packet's SA pair
+    pub fn canonical(&self, src: IpAddr, dst: IpAddr, spi: u32) -> u32 {
+        let pending = &self.pending;
+        let pairs = &self.pairs;
+        *self.spis.entry((src, dst, spi)).or_insert_with(|| {
+            let canonical = match pending.remove(&(dst, src)) {
+                Some((_, peer)) => peer,
+                None => {
+                    pending.insert((src, dst), spi);
+                    spi
+                }
+            };
+            pairs
+                .entry(Self::pair(src, dst, canonical))
+                .or_default()
+                .push((src, dst, spi));
+            canonical
+        })
+    }
+
+    /// Forget SAs of a timed out ESP session
+    pub fn remove(&self, key: &PacketHashKey) {
+        let (src, dst) = (key.src_ip, key.dst_ip);
+        if let Some((_, sas)) = self.pairs.remove(&Self::pair(src, dst, key.spi)) {
+            for sa in sas {
+                self.spis.remove(&sa);
+            }
+        }
+        self.pending.remove_if(&(src, dst), |_, spi| *spi == key.spi);
+        self.pending.remove_if(&(dst, src), |_, spi| *spi == key.spi);
+    }
+}
+
+/// Subscriber user names of PPPoE sessions.
+///
+/// User names are learnt from PAP/CHAP packets of a PPPoE session, and are shared
+/// with the sessions of IP packets carried by the same PPPoE session.
+#[derive(Default)]
+pub struct PppoeTable {
+    /// (larger mac, smaller mac, pppoe session id) => user name
+    usernames: DashMap<([u8; 6], [u8; 6], u16), String, FnvBuildHasher>,
+}
+
+impl PppoeTable {
+    #[inline]
+    fn key(pkt: &dyn Packet, session_id: u16) -> Option<([u8; 6], [u8; 6], u16)> {
+        let data_link = &pkt.layers().data_link;
+        if data_link.protocol != Protocol::ETHERNET {
+            return None;
+        }
+        let pos = data_link.offset as usize;
+        let macs = pkt.raw().get(pos..pos + 12)?;
+        let dst = <[u8; 6]>::try_from(&macs[..6]).unwrap();
+        let src = <[u8; 6]>::try_from(&macs[6..]).unwrap();
+        if src > dst {
+            Some((src, dst, session_id))
+        } else {
+            Some((dst, src, session_id))
+        }
+    }
+
+    /// Learn the user name of a session's PPPoE session, or fill it if already learnt
+    pub fn update(&self, pkt: &dyn Packet, ses: &mut Session) {
+        let pppoe = match ses.pppoe.as_mut() {
+            Some(pppoe) if pppoe.session_id != 0 => pppoe,
+            _ => return,
+        };
+        let key = match PppoeTable::key(pkt, pppoe.session_id) {
+            Some(key) => key,
+            None => return,
+        };
+
+        if let Some((ppp::PADT, _)) = pkt.pppoe_discovery() {
+            self.usernames.remove(&key);
+            return;
+        }
+
+        match &pppoe.username {
+            Some(name) => {
+                if self.usernames.get(&key).map_or(true, |n| *n != *name) {
+                    self.usernames.insert(key, name.clone());
+                }
+            }
+            None => {
+                if let Some(name) = self.usernames.get(&key) {
+                    pppoe.username = Some(name.clone());
+                }
+            }
+        };
+    }
+
+    /// Forget the user name learnt by a session leaving the session table
+    ///
+    /// User names are learnt by the sessions of PPPoE control packets, which are keyed
+    /// by the mac addresses and the PPPoE session id.
+    pub fn remove(&self, key: &PacketHashKey) {
+        if key.tunnel_proto == Protocol::PPPOE && key.network_proto == Protocol::UNKNOWN {
+            self.usernames
+                .remove(&(key.src_mac, key.dst_mac, key.tunnel_id as u16));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use api::packet::Layer;
+    use api::utils::packet::Packet as TestPacket;
+
+    #[test]
+    fn spi_table() {
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let table = SpiTable::default();
+
+        assert_eq!(table.canonical(a, b, 0x10), 0x10);
+        assert_eq!(table.canonical(a, b, 0x10), 0x10);
+        // reverse direction SA is paired with the pending one
+        assert_eq!(table.canonical(b, a, 0x20), 0x10);
+        assert_eq!(table.canonical(b, a, 0x20), 0x10);
+        // a rekeyed SA pair
+        assert_eq!(table.canonical(b, a, 0x30), 0x30);
+        assert_eq!(table.canonical(a, b, 0x40), 0x30);
+
+        let mut key = PacketHashKey::default();
+        key.src_ip = b;
+        key.dst_ip = a;
+        key.spi = 0x10;
+        table.remove(&key);
+        assert_eq!(table.spis.len(), 2);
+        assert_eq!(table.pairs.len(), 1);
+        assert_eq!(table.canonical(a, b, 0x40), 0x30);
+        assert_eq!(table.canonical(a, b, 0x10), 0x10);
+    }
+
+    #[test]
+    fn session_limits() {
+        let table = SessionTable::default();
+        let limits = SessionLimits {
+            max_sessions: 3,
+            ..Default::default()
+        };
+        let sessions = [([1, 1], 100), ([1, 0], 200), ([2, 3], 50)];
+        for (i, (pkt_count, end)) in sessions.iter().enumerate() {
+            let mut ses = Box::new(SessionData::default());
+            ses.info.pkt_count = *pkt_count;
+            ses.info.end_time.tv_sec = *end;
+            limits.add(ses.as_mut());
+            let mut key = PacketHashKey::default();
+            key.spi = i as u32;
+            table.insert(key, ses);
+        }
+        assert!(limits.exceeded());
+        assert_eq!(
+            limits.memory.load(Ordering::Relaxed),
+            3 * SessionData::default().memory_usage()
+        );
+
+        let evicted = limits.evict(&table);
+        assert_eq!(evicted.len(), 1);
+        // the single direction session
+        assert_eq!(evicted[0].0.spi, 1);
+        assert_eq!(table.len(), 2);
+        assert_eq!(limits.evicted(), 1);
+        assert!(!limits.exceeded());
+        assert_eq!(
+            limits.memory.load(Ordering::Relaxed),
+            2 * SessionData::default().memory_usage()
+        );
+
+        // single direction sessions go first, then the most idle ones
+        let mut priorities: Vec<_> = sessions
+            .iter()
+            .enumerate()
+            .map(|(i, (pkt_count, end))| {
+                let mut ses = Session::new();
+                ses.pkt_count = *pkt_count;
+                ses.end_time.tv_sec = *end;
+                (eviction_priority(&ses), i)
+            })
+            .collect();
+        priorities.sort_by(|a, b| b.0.cmp(&a.0));
+        let order: Vec<_> = priorities.iter().map(|(_, i)| *i).collect();
+        assert_eq!(order, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn pppoe_table() {
+        let pppoe_pkt = |src_mac: u8, ppp: &[u8]| {
+            let mut pkt = TestPacket::default();
+            let len = ppp.len() as u8;
+            let mut raw = vec![
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // dst mac
+                0x00, 0x00, 0x00, 0x00, 0x00, src_mac, // src mac
+                0x88, 0x64, // ethernet type
+                0x11, 0x00, 0x00, 0x2a, 0x00, len, // pppoe
+            ];
+            raw.extend_from_slice(ppp);
+            pkt.raw = Box::new(raw);
+            pkt.layers.data_link.protocol = Protocol::ETHERNET;
+            pkt.layers.pppoe = Layer {
+                protocol: Protocol::PPPOE,
+                offset: 14,
+            };
+            pkt
+        };
+        let pap = [
+            0xc0, 0x23, 0x01, 0x01, 0x00, 0x0e, 0x04, b'u', b's', b'e', b'r', 0x04, b'p', b'a',
+            b's', b's',
+        ];
+        let ipv4 = [0x00, 0x21, 0x45, 0x00];
+        let table = PppoeTable::default();
+
+        let mut ip_ses = Session::new();
+        let pkt = pppoe_pkt(2, &ipv4);
+        ip_ses.update(&pkt);
+        table.update(&pkt, &mut ip_ses);
+        assert_eq!(ip_ses.pppoe.as_ref().unwrap().username, None);
+
+        let mut ctrl_ses = Session::new();
+        let pkt = pppoe_pkt(2, &pap);
+        ctrl_ses.update(&pkt);
+        table.update(&pkt, &mut ctrl_ses);
+
+        // same pppoe session id of another subscriber
+        let mut other_ses = Session::new();
+        let pkt = pppoe_pkt(3, &ipv4);
+        other_ses.update(&pkt);
+        table.update(&pkt, &mut other_ses);
+        assert_eq!(other_ses.pppoe.as_ref().unwrap().username, None);
+
+        let pkt = pppoe_pkt(2, &ipv4);
+        ip_ses.update(&pkt);
+        table.update(&pkt, &mut ip_ses);
+        assert_eq!(
+            ip_ses.pppoe.as_ref().unwrap().username.as_deref(),
+            Some("user")
+        );
+
+        // the control session leaves the session table
+        let mut key = PacketHashKey::default();
+        key.tunnel_proto = Protocol::PPPOE;
+        key.tunnel_id = 0x2a;
+        key.src_mac = [0, 0, 0, 0, 0, 2];
+        key.dst_mac = [0, 0, 0, 0, 0, 1];
+        table.remove(&key);
+        assert!(table.usernames.is_empty());
+
+        // PADT
+        let pkt = pppoe_pkt(2, &pap);
+        table.update(&pkt, &mut ctrl_ses);
+        assert_eq!(table.usernames.len(), 1);
+        let mut pkt = pppoe_pkt(2, &[]);
+        pkt.raw[15] = ppp::PADT;
+        ctrl_ses.update(&pkt);
+        table.update(&pkt, &mut ctrl_ses);
+        assert!(table.usernames.is_empty());
+    }
+}
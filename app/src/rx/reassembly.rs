@@ -0,0 +1,305 @@
+//! TCP stream reassembly
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use alphonse_api as api;
+use api::packet::{tcp, Direction};
+use api::parsers::StreamData;
+
+#[derive(Default)]
+/// One direction of a TCP stream
+struct Stream {
+    /// Sequence number of the stream's first byte
+    isn: Option<u32>,
+    /// Stream offset of the next expected byte
+    next: u64,
+    /// Out of order segments, keyed by stream offset
+    segments: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Stream {
+    /// Stream offset of a sequence number, negative if it's before the stream's first byte
+    #[inline]
+    fn offset(&self, isn: u32, seq: u32) -> i64 {
+        let expected = isn.wrapping_add(self.next as u32);
+        self.next as i64 + seq.wrapping_sub(expected) as i32 as i64
+    }
+
+    /// Deliver buffered segments that became contiguous
+    fn drain<F>(&mut self, buffered: &mut usize, deliver: &mut F) -> Result<()>
+    where
+        F: FnMut(StreamData) -> Result<()>,
+    {
+        while let Some(&start) = self.segments.keys().next() {
+            if start > self.next {
+                break;
+            }
+            let seg = self.segments.remove(&start).unwrap();
+            *buffered -= seg.len();
+            let end = start + seg.len() as u64;
+            if end > self.next {
+                deliver(StreamData::Data(&seg[(self.next - start) as usize..]))?;
+                self.next = end;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give up the missing bytes before the first buffered segment
+    fn skip<F>(&mut self, buffered: &mut usize, deliver: &mut F) -> Result<()>
+    where
+        F: FnMut(StreamData) -> Result<()>,
+    {
+        if let Some(&start) = self.segments.keys().next() {
+            if start > self.next {
+                deliver(StreamData::Gap((start - self.next) as u32))?;
+                self.next = start;
+            }
+            self.drain(buffered, deliver)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reassembles both directions of a TCP session into in-order stream data
+///
+/// Retransmitted and overlapping bytes are trimmed, out of order segments are buffered
+/// until the missing bytes arrive. Once the buffered bytes of the session exceed the
+/// memory budget, the missing bytes are reported as a gap and the stream moves on.
+pub struct TcpReassembler {
+    streams: [Stream; 2],
+    /// Bytes buffered by out of order segments of both directions
+    buffered: usize,
+    /// Max buffered bytes
+    budget: usize,
+}
+
+impl TcpReassembler {
+    pub fn new(budget: usize) -> Self {
+        TcpReassembler {
+            streams: [Stream::default(), Stream::default()],
+            buffered: 0,
+            budget,
+        }
+    }
+
+    /// Bytes buffered by out of order segments
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// Add a TCP segment, stream data that became in-order is handed to deliver
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Direction of the segment
+    /// * `flags` - Segment's TCP flags
+    /// * `seq` - Segment's sequence number
+    /// * `payload` - Segment's payload
+    /// * `deliver` - Callback receiving in-order stream data of this direction
+    pub fn insert<F>(
+        &mut self,
+        direction: Direction,
+        flags: tcp::Flags,
+        seq: u32,
+        payload: &[u8],
+        mut deliver: F,
+    ) -> Result<()>
+    where
+        F: FnMut(StreamData) -> Result<()>,
+    {
+        let budget = self.budget;
+        let buffered = &mut self.buffered;
+        let stream = &mut self.streams[direction as usize];
+
+        // SYN occupies one sequence number
+        let seq = if flags.contains(tcp::Flags::SYN) {
+            seq.wrapping_add(1)
+        } else {
+            seq
+        };
+        let isn = *stream.isn.get_or_insert(seq);
+        if payload.is_empty() {
+            return Ok(());
+        }
+
+        let start = stream.offset(isn, seq);
+        if start + payload.len() as i64 <= stream.next as i64 {
+            // retransmission
+            return Ok(());
+        }
+        let (start, payload) = if start < stream.next as i64 {
+            // overlaps with delivered bytes
+            let overlap = (stream.next as i64 - start) as usize;
+            (stream.next, &payload[overlap..])
+        } else {
+            (start as u64, payload)
+        };
+
+        if start > stream.next {
+            while *buffered + payload.len() > budget && !stream.segments.is_empty() {
+                stream.skip(buffered, &mut deliver)?;
+            }
+
+            if start > stream.next {
+                if *buffered + payload.len() <= budget {
+                    match stream.segments.get(&start) {
+                        Some(seg) if seg.len() >= payload.len() => {}
+                        _ => {
+                            if let Some(seg) = stream.segments.insert(start, payload.to_vec()) {
+                                *buffered -= seg.len();
+                            }
+                            *buffered += payload.len();
+                        }
+                    };
+                    return Ok(());
+                }
+                deliver(StreamData::Gap((start - stream.next) as u32))?;
+                stream.next = start;
+            }
+        }
+
+        let end = start + payload.len() as u64;
+        if end > stream.next {
+            deliver(StreamData::Data(&payload[(stream.next - start) as usize..]))?;
+            stream.next = end;
+        }
+        stream.drain(buffered, &mut deliver)
+    }
+
+    /// Deliver all buffered segments, missing bytes between them are reported as gaps
+    pub fn flush<F>(&mut self, mut deliver: F) -> Result<()>
+    where
+        F: FnMut(Direction, StreamData) -> Result<()>,
+    {
+        for (direction, stream) in [Direction::LEFT, Direction::RIGHT]
+            .iter()
+            .zip(self.streams.iter_mut())
+        {
+            let mut deliver = |data: StreamData| deliver(*direction, data);
+            while !stream.segments.is_empty() {
+                stream.skip(&mut self.buffered, &mut deliver)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Chunk {
+        Data(Vec<u8>),
+        Gap(u32),
+    }
+
+    fn chunk(data: StreamData) -> Chunk {
+        match data {
+            StreamData::Data(d) => Chunk::Data(d.to_vec()),
+            StreamData::Gap(len) => Chunk::Gap(len),
+        }
+    }
+
+    fn insert(
+        reassembler: &mut TcpReassembler,
+        flags: tcp::Flags,
+        seq: u32,
+        payload: &[u8],
+    ) -> Vec<Chunk> {
+        let mut chunks = vec![];
+        reassembler
+            .insert(Direction::LEFT, flags, seq, payload, |data| {
+                chunks.push(chunk(data));
+                Ok(())
+            })
+            .unwrap();
+        chunks
+    }
+
+    #[test]
+    fn in_order() {
+        let mut r = TcpReassembler::new(1024);
+        assert!(insert(&mut r, tcp::Flags::SYN, 100, b"").is_empty());
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 101, b"GET "),
+            vec![Chunk::Data(b"GET ".to_vec())]
+        );
+        // retransmission
+        assert!(insert(&mut r, tcp::Flags::ACK, 101, b"GET ").is_empty());
+        // overlapping retransmission
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 103, b"T / HTTP"),
+            vec![Chunk::Data(b"/ HTTP".to_vec())]
+        );
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut r = TcpReassembler::new(1024);
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 0xfffffffe, b"ab"),
+            vec![Chunk::Data(b"ab".to_vec())]
+        );
+        // sequence number wraps around
+        assert!(insert(&mut r, tcp::Flags::ACK, 2, b"ef").is_empty());
+        assert!(insert(&mut r, tcp::Flags::ACK, 1, b"def").is_empty());
+        assert_eq!(r.buffered, 5);
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 0, b"c"),
+            vec![Chunk::Data(b"c".to_vec()), Chunk::Data(b"def".to_vec())]
+        );
+        assert_eq!(r.buffered, 0);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut r = TcpReassembler::new(4);
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 0, b"a"),
+            vec![Chunk::Data(b"a".to_vec())]
+        );
+        assert!(insert(&mut r, tcp::Flags::ACK, 2, b"cde").is_empty());
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 10, b"kl"),
+            vec![Chunk::Gap(1), Chunk::Data(b"cde".to_vec())]
+        );
+        assert_eq!(r.buffered, 2);
+        // larger than the budget
+        assert_eq!(
+            insert(&mut r, tcp::Flags::ACK, 20, b"uvwxyz"),
+            vec![
+                Chunk::Gap(5),
+                Chunk::Data(b"kl".to_vec()),
+                Chunk::Gap(8),
+                Chunk::Data(b"uvwxyz".to_vec())
+            ]
+        );
+        assert_eq!(r.buffered, 0);
+    }
+
+    #[test]
+    fn flush() {
+        let mut r = TcpReassembler::new(1024);
+        insert(&mut r, tcp::Flags::ACK, 0, b"a");
+        insert(&mut r, tcp::Flags::ACK, 2, b"c");
+        let mut chunks = vec![];
+        r.flush(|direction, data| {
+            chunks.push((direction, chunk(data)));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                (Direction::LEFT, Chunk::Gap(1)),
+                (Direction::LEFT, Chunk::Data(b"c".to_vec()))
+            ]
+        );
+        assert_eq!(r.buffered, 0);
+    }
+}
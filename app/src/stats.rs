@@ -1,3 +1,12 @@
+use std::fmt::{Display, Formatter};
+
+use fnv::FnvHashMap;
+
+use alphonse_api as api;
+use api::packet::Protocol;
+
+use crate::packet::parser::{DecodeError, Error};
+
 /// Capture statistic information
 #[derive(Debug, Default)]
 pub struct CaptureStat {
@@ -10,3 +19,86 @@ pub struct CaptureStat {
     /// Total dropped packets by network interface
     pub if_dropped: u64,
 }
+
+/// Reason of a packet decode error
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum DecodeErrorReason {
+    UnsupportProtocol,
+    CorruptPacket,
+    UnknownProtocol,
+    UnknownLinkType,
+}
+
+impl From<&Error> for DecodeErrorReason {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::UnsupportProtocol(_) => DecodeErrorReason::UnsupportProtocol,
+            Error::CorruptPacket(_) => DecodeErrorReason::CorruptPacket,
+            Error::UnknownProtocol => DecodeErrorReason::UnknownProtocol,
+            Error::UnknownLinkType(_) => DecodeErrorReason::UnknownLinkType,
+        }
+    }
+}
+
+/// Packet decode error statistic information, keyed by protocol and reason
+#[derive(Debug, Default)]
+pub struct DecodeStat {
+    errors: FnvHashMap<(Protocol, DecodeErrorReason), u64>,
+}
+
+impl DecodeStat {
+    /// Account a decode error
+    #[inline]
+    pub fn add(&mut self, err: &DecodeError) {
+        let reason = DecodeErrorReason::from(&err.error);
+        *self.errors.entry((err.protocol, reason)).or_insert(0) += 1;
+    }
+
+    /// Total decode errors
+    pub fn total(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+impl Display for DecodeStat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut errors: Vec<_> = self.errors.iter().collect();
+        errors.sort_by_key(|((protocol, reason), _)| (*protocol as u8, *reason as u8));
+        for (i, ((protocol, reason), cnt)) in errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:?}/{:?}:{}", protocol, reason, cnt)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_stat() {
+        let mut stat = DecodeStat::default();
+        stat.add(&DecodeError {
+            protocol: Protocol::TCP,
+            error: Error::CorruptPacket(String::from("")),
+        });
+        stat.add(&DecodeError {
+            protocol: Protocol::TCP,
+            error: Error::CorruptPacket(String::from("")),
+        });
+        stat.add(&DecodeError {
+            protocol: Protocol::IPV4,
+            error: Error::UnknownProtocol,
+        });
+
+        assert_eq!(stat.total(), 3);
+        assert_eq!(
+            stat.to_string(),
+            "IPV4/UnknownProtocol:1 TCP/CorruptPacket:2"
+        );
+    }
+}
@@ -1,6 +1,143 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use alphonse_api as api;
+use api::packet::PacketHashKey;
+
+use crate::config::Config;
+use crate::rx::{PppoeTable, SessionLimits, SessionTable, SpiTable};
+
 pub mod output;
 mod pkt;
 mod timeout;
+mod wheel;
+pub mod writer;
 
 pub use pkt::PktThread;
 pub use timeout::TimeoutThread;
+pub use wheel::TimerWheel;
+pub use writer::PcapWriter;
+
+/// Session timers, each fires at the time a session needs to be checked for timeout
+pub type SessionTimers = Mutex<TimerWheel<PacketHashKey>>;
+
+/// Session tables shared by the packet threads and the timeout thread
+pub struct SessionContext {
+    pub session_table: SessionTable,
+    pub limits: SessionLimits,
+    pub spi_table: SpiTable,
+    pub pppoe_table: PppoeTable,
+    pub timers: SessionTimers,
+    pub clock: Clock,
+    /// Dropped along with the context, so the writer thread exits after the packet threads
+    pub writer: Option<Arc<PcapWriter>>,
+}
+
+impl SessionContext {
+    pub fn new(cfg: &Config, writer: Option<Arc<PcapWriter>>) -> Self {
+        let capacity = match cfg.ses_max_sessions {
+            0 => 1000000,
+            max => max.min(1000000),
+        };
+        let clock = Clock::new(cfg.offline());
+        SessionContext {
+            session_table: SessionTable::with_capacity_and_hasher(
+                capacity,
+                fnv::FnvBuildHasher::default(),
+            ),
+            limits: SessionLimits::new(cfg),
+            spi_table: SpiTable::default(),
+            pppoe_table: PppoeTable::default(),
+            timers: Mutex::new(TimerWheel::new(clock.now())),
+            clock,
+            writer,
+        }
+    }
+}
+
+impl Drop for SessionContext {
+    fn drop(&mut self) {
+        if self.limits.evicted() > 0 {
+            println!(
+                "{} sessions evicted by session table limits",
+                self.limits.evicted()
+            );
+        }
+    }
+}
+
+/// Clock driving session timeouts
+///
+/// Live capture uses wall clock time, while offline processing uses the newest packet
+/// timestamp, so sessions of pcap files time out by capture time however old they are
+/// and however fast they're read.
+#[derive(Default)]
+pub struct Clock {
+    offline: bool,
+    /// Newest packet timestamp in seconds, only used for offline processing
+    newest: AtomicU64,
+}
+
+impl Clock {
+    pub fn new(offline: bool) -> Self {
+        Clock {
+            offline,
+            newest: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the clock is driven by packet timestamps
+    #[inline]
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Advance the clock with a packet's timestamp, no-op for live capture
+    #[inline]
+    pub fn update(&self, ts: &libc::timeval) {
+        if self.offline {
+            self.newest.fetch_max(ts.tv_sec as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Current time in seconds
+    #[inline]
+    pub fn now(&self) -> u64 {
+        if self.offline {
+            return self.newest.load(Ordering::Relaxed);
+        }
+        SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_clock() {
+        let clock = Clock::new(true);
+        assert_eq!(clock.now(), 0);
+        clock.update(&libc::timeval {
+            tv_sec: 1000,
+            tv_usec: 0,
+        });
+        // packets from another pkt thread could be older
+        clock.update(&libc::timeval {
+            tv_sec: 900,
+            tv_usec: 0,
+        });
+        assert_eq!(clock.now(), 1000);
+
+        let clock = Clock::new(false);
+        clock.update(&libc::timeval {
+            tv_sec: 1000,
+            tv_usec: 0,
+        });
+        assert!(clock.now() > 1000);
+    }
+}
@@ -4,13 +4,16 @@ use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use chrono::{Datelike, Timelike};
 use crossbeam_channel::Receiver;
-use elasticsearch::{http::transport::Transport, Elasticsearch};
+use elasticsearch::{http::transport::Transport, indices::IndicesPutTemplateParts, Elasticsearch};
+use serde_json::json;
 
 use alphonse_api as api;
+use api::session::field::{FieldDef, FieldRegistry, FieldType};
 use api::session::Session;
 use api::utils::timeval::{precision::Millisecond, TimeVal};
 
 use crate::config::Config;
+use crate::enrich::Enricher;
 
 enum Rotate {
     Hourly(u32),
@@ -67,13 +70,64 @@ fn to_index_suffix(rotate: Rotate, ts: &TimeVal<Millisecond>) -> String {
     }
 }
 
+/// Index template of registered fields, merged on top of other session index templates
+const FIELDS_TEMPLATE: &str = "alphonse_fields_template";
+/// Arkime's index of field documents
+const FIELDS_INDEX: &str = "fields";
+
+/// Index template mapping registered fields in session indices
+fn fields_template(registry: &FieldRegistry) -> serde_json::Value {
+    let properties: serde_json::Map<String, serde_json::Value> = registry
+        .iter()
+        .map(|def| {
+            let es_type = match def.field_type {
+                FieldType::Str => "keyword",
+                FieldType::Int => "long",
+                FieldType::Ip => "ip",
+            };
+            (def.name.clone(), json!({ "type": es_type }))
+        })
+        .collect();
+    json!({
+        "index_patterns": ["sessions2-*"],
+        "order": 1,
+        "mappings": { "properties": properties },
+    })
+}
+
+/// Arkime field document of a registered field
+fn field_doc(def: &FieldDef) -> serde_json::Value {
+    let arkime_type = match def.field_type {
+        FieldType::Str => "termfield",
+        FieldType::Int => "integer",
+        FieldType::Ip => "ip",
+    };
+    json!({
+        "friendlyName": def.friendly_name,
+        "group": def.category,
+        "help": def.help,
+        "dbField2": def.name,
+        "type": arkime_type,
+    })
+}
+
 pub struct Thread {
     receiver: Receiver<Box<Session>>,
+    enricher: Enricher,
+    fields: FieldRegistry,
 }
 
 impl Thread {
-    pub fn new(receiver: Receiver<Box<Session>>) -> Self {
-        Thread { receiver }
+    pub fn new(
+        receiver: Receiver<Box<Session>>,
+        enricher: Enricher,
+        fields: FieldRegistry,
+    ) -> Self {
+        Thread {
+            receiver,
+            enricher,
+            fields,
+        }
     }
 
     pub fn name(&self) -> String {
@@ -92,11 +146,17 @@ impl Thread {
 
         println!("{} started", self.name());
 
+        if !cfg.dry_run {
+            if let Err(e) = rt.block_on(Thread::push_fields(&es, &self.fields)) {
+                eprintln!("{} failed to push field definitions: {}", self.name(), e);
+            }
+        }
+
         let mut sessions = vec![];
 
         rt.block_on(async {
             loop {
-                let ses = match self.receiver.try_recv() {
+                let mut ses = match self.receiver.try_recv() {
                     Ok(ses) => ses,
                     Err(err) => match err {
                         crossbeam_channel::TryRecvError::Disconnected => break,
@@ -107,6 +167,7 @@ impl Thread {
                 if cfg.dry_run {
                     continue;
                 }
+                self.enricher.enrich(&mut ses);
                 sessions.push(Arc::from(ses));
                 if sessions.len() == 5 {
                     let sessions_cloned = Box::new(sessions.clone());
@@ -129,6 +190,50 @@ impl Thread {
         Ok(())
     }
 
+    /// Push the index template and field documents of registered fields
+    async fn push_fields(es: &Elasticsearch, registry: &FieldRegistry) -> Result<()> {
+        if registry.is_empty() {
+            return Ok(());
+        }
+
+        let resp = es
+            .indices()
+            .put_template(IndicesPutTemplateParts::Name(FIELDS_TEMPLATE))
+            .body(fields_template(registry))
+            .send()
+            .await?;
+        if resp.status_code().as_u16() != 200 {
+            return Err(anyhow!(
+                "failed to put index template: {}",
+                resp.text().await?
+            ));
+        }
+
+        let body = registry
+            .iter()
+            .map(|def| {
+                elasticsearch::BulkOperation::from(
+                    elasticsearch::BulkOperation::index(field_doc(def))
+                        .id(def.name.as_str())
+                        .index(FIELDS_INDEX.to_string()),
+                )
+            })
+            .collect();
+        let resp = es
+            .bulk(elasticsearch::BulkParts::None)
+            .body(body)
+            .send()
+            .await?;
+        if resp.status_code().as_u16() != 200 {
+            return Err(anyhow!(
+                "failed to index field documents: {}",
+                resp.text().await?
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn save_sessions(
         _cfg: Arc<Config>,
         es: Arc<Elasticsearch>,
@@ -151,7 +256,9 @@ impl Thread {
                     to_index_suffix(Rotate::Daily, &ses.start_time)
                 );
                 elasticsearch::BulkOperation::from(
-                    elasticsearch::BulkOperation::index(ses.as_ref()).index(index),
+                    elasticsearch::BulkOperation::index(ses.as_ref())
+                        .id(ses.id.as_str())
+                        .index(index),
                 )
             })
             .collect();
@@ -172,3 +279,46 @@ impl Thread {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let mut registry = FieldRegistry::default();
+        let uri = FieldDef::new(
+            "http.uri",
+            FieldType::Str,
+            "http",
+            "URI",
+            "URIs of HTTP requests",
+        );
+        registry.register(uri.clone()).unwrap();
+        registry
+            .register(FieldDef::new(
+                "dns.ip",
+                FieldType::Ip,
+                "dns",
+                "IP",
+                "Resolved ips",
+            ))
+            .unwrap();
+
+        let template = fields_template(&registry);
+        assert_eq!(template["index_patterns"], json!(["sessions2-*"]));
+        assert_eq!(
+            template["mappings"]["properties"],
+            json!({
+                "dns.ip": { "type": "ip" },
+                "http.uri": { "type": "keyword" },
+            })
+        );
+
+        let doc = field_doc(&uri);
+        assert_eq!(doc["friendlyName"], "URI");
+        assert_eq!(doc["group"], "http");
+        assert_eq!(doc["dbField2"], "http.uri");
+        assert_eq!(doc["type"], "termfield");
+    }
+}
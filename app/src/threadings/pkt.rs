@@ -1,23 +1,29 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 
 use alphonse_api as api;
 use api::classifiers::ClassifierManager;
-use api::packet::{Packet, PacketHashKey};
+use api::packet::{tcp, Packet, PacketHashKey, Protocol};
 use api::parsers::ProtocolParserTrait;
+use api::session::{CloseReason, FlowStats, Session};
 use api::utils::timeval::TimeVal;
 
+use super::timeout::check_time;
+use super::SessionContext;
 use crate::config::Config;
-use crate::rx::{SessionData, SessionTable};
+use crate::rx::SessionData;
+use crate::stats::DecodeStat;
 
 pub struct PktThread {
     id: u8,
     exit: Arc<AtomicBool>,
     classifier: Arc<ClassifierManager>,
     receiver: Receiver<Box<dyn Packet>>,
+    sender: Sender<Box<Session>>,
 }
 
 impl PktThread {
@@ -26,12 +32,14 @@ impl PktThread {
         exit: Arc<AtomicBool>,
         classifier: Arc<ClassifierManager>,
         receiver: Receiver<Box<dyn Packet>>,
+        sender: Sender<Box<Session>>,
     ) -> Self {
         Self {
             id,
             exit,
             classifier,
             receiver,
+            sender,
         }
     }
 
@@ -39,8 +47,57 @@ impl PktThread {
         format!("alphonse-pkt{}", self.id)
     }
 
+    /// Hand over a session to the output threads, waits while the channel is full
+    #[inline]
+    fn save(&self, ctx: &SessionContext, mut ses: Box<Session>) {
+        if let Some(writer) = ctx.writer.as_ref() {
+            writer.discard_lost(&mut ses);
+        }
+        if self.sender.send(ses).is_err() {
+            eprintln!(
+                "{} failed to save a session, output threads exited",
+                self.name()
+            );
+        }
+    }
+
     #[inline]
     fn parse_pkt(
+        &self,
+        cfg: &Config,
+        scratch: &mut api::classifiers::ClassifyScratch,
+        protocol_parsers: &mut Box<Vec<Box<dyn ProtocolParserTrait>>>,
+        pkt: &mut dyn Packet,
+        ses_data: &mut SessionData,
+    ) -> Result<()> {
+        if pkt.layers().trans.protocol != Protocol::SCTP
+            || pkt.layers().app.protocol != Protocol::APPLICATION
+        {
+            self.parse_app(scratch, protocol_parsers, pkt, ses_data)?;
+            if pkt.layers().trans.protocol == Protocol::TCP {
+                ses_data.reassemble(pkt, cfg.tcp_reassembly_memory)?;
+            }
+            return Ok(());
+        }
+
+        // Deliver each SCTP DATA chunk's payload separately, protocol parsers
+        // could get the chunk's stream by pkt.sctp_stream_id()
+        let offsets: Vec<u16> = pkt
+            .sctp_chunks()
+            .filter_map(|chunk| chunk.data())
+            .map(|(offset, _)| offset)
+            .collect();
+        for offset in offsets {
+            pkt.layers_mut().app.offset = offset;
+            pkt.rules_mut().clear();
+            self.parse_app(scratch, protocol_parsers, pkt, ses_data)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn parse_app(
         &self,
         scratch: &mut api::classifiers::ClassifyScratch,
         protocol_parsers: &mut Box<Vec<Box<dyn ProtocolParserTrait>>>,
@@ -85,51 +142,148 @@ impl PktThread {
         Ok(())
     }
 
+    /// Evict sessions to make room for a new one, and save the evicted ones
+    fn evict(&self, ctx: &SessionContext) {
+        for (key, mut ses) in ctx.limits.evict(&ctx.session_table) {
+            if let Some(tcp) = ses.info.tcp.as_mut() {
+                tcp.close_reason.get_or_insert(CloseReason::Evicted);
+            }
+            ses.finish();
+            self.save(ctx, ses.info);
+            if key.trans_proto == Protocol::ESP {
+                ctx.spi_table.remove(&key);
+            }
+            ctx.pppoe_table.remove(&key);
+        }
+    }
+
     pub fn spawn(
         &self,
         cfg: Arc<Config>,
-        session_table: Arc<SessionTable>,
+        ctx: Arc<SessionContext>,
         mut protocol_parsers: Box<Vec<Box<dyn ProtocolParserTrait>>>,
     ) -> Result<()> {
-        let parser = crate::packet::Parser::new(crate::packet::link::ETHERNET);
+        let SessionContext {
+            session_table,
+            limits,
+            spi_table,
+            pppoe_table,
+            timers,
+            clock,
+            writer,
+        } = ctx.as_ref();
+        let parser = crate::packet::Parser::new(&cfg);
         let mut classify_scratch = match self.classifier.alloc_scratch() {
             Ok(scratch) => scratch,
             Err(_) => todo!(),
         };
+        let mut pcap = writer.as_ref().map(|writer| writer.buffer());
+        let mut decode_stat = DecodeStat::default();
+        let mut pkt_cnt: u64 = 0;
         println!("{} started", self.name());
 
         while !self.exit.load(Ordering::Relaxed) {
-            let mut pkt = match self.receiver.recv() {
-                Err(_) => break,
+            let mut pkt = match self.receiver.recv_timeout(Duration::from_secs(1)) {
+                Err(RecvTimeoutError::Timeout) => {
+                    // don't keep packets of a quiet link in memory
+                    if let Some(pcap) = pcap.as_mut() {
+                        pcap.flush();
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
                 Ok(s) => s,
             };
 
+            // Decode errors are only accounted, session is still built from the decoded layers
             match parser.parse_pkt(pkt.as_mut()) {
                 Ok(_) => {}
-                Err(e) => match e {
-                    crate::packet::parser::Error::UnsupportProtocol(_) => {}
-                    _ => todo!(),
-                },
+                Err(e) => decode_stat.add(&e),
             };
 
-            let key = PacketHashKey::from(pkt.as_ref());
+            clock.update(pkt.ts());
+
+            pkt_cnt += 1;
+            if pkt_cnt % cfg.rx_stat_log_interval == 0 && decode_stat.total() > 0 {
+                println!("{} decode errors {}", self.name(), decode_stat);
+            }
+
+            let mut key = PacketHashKey::new(pkt.as_ref(), &cfg.tunnel_hash_modes);
+            if key.trans_proto == Protocol::ESP {
+                let (src_ip, dst_ip) = pkt.ips();
+                key.spi = spi_table.canonical(src_ip, dst_ip, key.spi);
+            }
+            // A new connection reusing the ports of a closed one starts a new session
+            if key.trans_proto == Protocol::TCP
+                && pkt.tcp_flags() & (tcp::Flags::SYN | tcp::Flags::ACK) == tcp::Flags::SYN
+            {
+                if let Some((_, mut ses)) =
+                    session_table.remove_if(&key, |_, ses| ses.info.tcp_closed())
+                {
+                    limits.remove(&ses);
+                    ses.finish();
+                    self.save(&ctx, ses.info);
+                }
+            }
+
+            let packet_pos = pcap.as_mut().map(|pcap| pcap.write(pkt.as_ref()));
+
             match session_table.get_mut(&key) {
                 Some(mut ses) => {
                     ses.info.update(pkt.as_ref());
+                    if let Some((file_id, pos)) = packet_pos {
+                        ses.info.add_packet_pos(file_id, pos);
+                    }
+                    if ses.info.community_id.is_none() {
+                        ses.info
+                            .update_community_id(pkt.as_ref(), cfg.community_id_seed);
+                    }
+                    *pkt.direction_mut() = ses.info.direction(pkt.as_ref());
+                    pppoe_table.update(pkt.as_ref(), ses.info.as_mut());
                     self.parse_pkt(
+                        &cfg,
                         &mut classify_scratch,
                         &mut protocol_parsers,
                         pkt.as_mut(),
                         ses.as_mut(),
                     )
                     .unwrap();
+
+                    if ses.info.truncate(cfg.ses_max_packets as u32) {
+                        let save_time = pkt.ts().tv_sec as u64 + cfg.ses_save_timeout as u64;
+                        self.save(&ctx, ses.info.mid_save(save_time));
+                    }
+                    limits.update(ses.as_mut());
+
+                    // closed TCP sessions time out earlier
+                    let expire_at = check_time(&cfg, &key, &ses.info);
+                    if expire_at < ses.expire_at {
+                        ses.expire_at = expire_at;
+                        timers.lock().unwrap().insert(expire_at, key.clone());
+                    }
                 }
                 None => {
+                    if limits.exceeded() {
+                        self.evict(&ctx);
+                    }
+
                     let mut ses = Box::new(SessionData::default());
                     ses.info.start_time = TimeVal::new(*pkt.ts());
+                    ses.info.id = Box::new(Session::new_id(pkt.ts()));
                     ses.info.save_time = pkt.ts().tv_sec as u64 + cfg.ses_save_timeout as u64;
+                    if cfg.ses_stats {
+                        ses.info.stats = Some(Box::new(FlowStats::new(cfg.ses_stats_first_pkts)));
+                    }
                     ses.info.update(pkt.as_ref());
+                    if let Some((file_id, pos)) = packet_pos {
+                        ses.info.add_packet_pos(file_id, pos);
+                    }
+                    ses.info
+                        .update_community_id(pkt.as_ref(), cfg.community_id_seed);
+                    *pkt.direction_mut() = ses.info.direction(pkt.as_ref());
+                    pppoe_table.update(pkt.as_ref(), ses.info.as_mut());
                     self.parse_pkt(
+                        &cfg,
                         &mut classify_scratch,
                         &mut protocol_parsers,
                         pkt.as_mut(),
@@ -137,11 +291,17 @@ impl PktThread {
                     )
                     .unwrap();
 
-                    &mut session_table.insert(key, ses);
+                    ses.expire_at = check_time(&cfg, &key, &ses.info);
+                    timers.lock().unwrap().insert(ses.expire_at, key.clone());
+                    limits.add(ses.as_mut());
+                    session_table.insert(key, ses);
                 }
             };
         }
 
+        if decode_stat.total() > 0 {
+            println!("{} decode errors {}", self.name(), decode_stat);
+        }
         println!("{} exit", self.name());
 
         Ok(())
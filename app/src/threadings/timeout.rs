@@ -1,18 +1,19 @@
 use std::os::raw::c_long;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::Duration;
 
 use anyhow::Result;
 use crossbeam_channel::Sender;
 use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use alphonse_api as api;
-use api::packet::Protocol;
-use api::session::Session;
+use api::packet::{PacketHashKey, Protocol};
+use api::session::{CloseReason, Session, TcpState};
 
+use super::SessionContext;
 use crate::config::Config;
-use crate::rx::SessionTable;
+use crate::rx::SessionData;
 
 /// Session table timeout thread
 pub struct TimeoutThread {
@@ -29,71 +30,48 @@ impl TimeoutThread {
         "alphonse-timeout".to_string()
     }
 
-    pub fn spawn(&self, cfg: Arc<Config>, session_table: Arc<SessionTable>) -> Result<()> {
-        let now: u64 = SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let mut next_timeout_check_time: u64 = now + cfg.timeout_interval;
+    /// Hand over a session to the output threads, waits while the channel is full
+    #[inline]
+    fn save(&self, ctx: &SessionContext, mut ses: Box<Session>) {
+        if let Some(writer) = ctx.writer.as_ref() {
+            writer.discard_lost(&mut ses);
+        }
+        if self.sender.send(ses).is_err() {
+            eprintln!(
+                "{} failed to save a session, output threads exited",
+                self.name()
+            );
+        }
+    }
+
+    pub fn spawn(&self, cfg: Arc<Config>, ctx: Arc<SessionContext>) -> Result<()> {
+        let clock = &ctx.clock;
+        // pcap files are read much faster than capture time goes
+        let interval = if clock.offline() {
+            Duration::from_millis(100)
+        } else {
+            Duration::from_secs(cfg.timeout_interval)
+        };
         println!("{} started", self.name());
 
         while !self.exit.load(Ordering::Relaxed) {
-            let now: u64 = SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            if now <= next_timeout_check_time {
-                continue;
+            let now = clock.now();
+            let fired = ctx.timers.lock().unwrap().advance(now);
+            for (time, key) in fired {
+                self.check(&cfg, &ctx, time, key, now);
             }
-
-            next_timeout_check_time = now + cfg.timeout_interval;
-            session_table
-                .shards()
-                .iter()
-                .par_bridge()
-                .for_each(|shard| {
-                    shard.write().retain(|key, ses| {
-                        let ses = ses.get_mut();
-                        let timeout = match key.trans_proto {
-                            Protocol::TCP => {
-                                ses.info.timeout(cfg.tcp_timeout as c_long, now as c_long)
-                            }
-                            Protocol::UDP => {
-                                ses.info.timeout(cfg.udp_timeout as c_long, now as c_long)
-                            }
-                            Protocol::SCTP => {
-                                ses.info.timeout(cfg.sctp_timeout as c_long, now as c_long)
-                            }
-                            _ => ses
-                                .info
-                                .timeout(cfg.default_timeout as c_long, now as c_long),
-                        };
-
-                        if ses.info.need_mid_save(cfg.ses_max_packets as u32, now) {
-                            self.sender.try_send(ses.info.clone()).unwrap();
-                            ses.info.mid_save_reset(now + cfg.ses_save_timeout as u64);
-                        } else if timeout {
-                            for (_, parser) in ses.parsers.iter_mut() {
-                                parser.finish(ses.info.as_mut());
-                            }
-                            self.sender.try_send(ses.info.clone()).unwrap();
-                        }
-                        !timeout
-                    })
-                });
+            std::thread::sleep(interval);
         }
 
-        session_table
+        ctx.session_table
             .shards()
             .iter()
             .par_bridge()
             .for_each(|shard| {
                 shard.write().retain(|_, ses| {
                     let ses = ses.get_mut();
-                    for (_, parser) in ses.parsers.iter_mut() {
-                        parser.finish(ses.info.as_mut());
-                    }
-                    self.sender.try_send(ses.info.clone()).unwrap();
+                    ses.finish();
+                    self.save(&ctx, ses.info.clone());
                     false
                 })
             });
@@ -102,4 +80,163 @@ impl TimeoutThread {
 
         Ok(())
     }
+
+    /// Check a session whose timer fires, save it if it times out, otherwise
+    /// do a middle save if needed and schedule its next check
+    fn check(&self, cfg: &Config, ctx: &SessionContext, time: u64, key: PacketHashKey, now: u64) {
+        let SessionContext {
+            session_table,
+            limits,
+            spi_table,
+            pppoe_table,
+            timers,
+            ..
+        } = ctx;
+        let timed_out = |key: &PacketHashKey, ses: &SessionData| {
+            ses.expire_at == time
+                && ses
+                    .info
+                    .timeout(idle_timeout(cfg, key, &ses.info) as c_long, now as c_long)
+        };
+
+        match session_table.get_mut(&key) {
+            // a stale timer, the session has been rescheduled or replaced
+            Some(ses) if ses.expire_at != time => return,
+            Some(mut ses) => {
+                if ses.info.need_mid_save(cfg.ses_max_packets as u32, now) {
+                    let segment = ses.info.mid_save(now + cfg.ses_save_timeout as u64);
+                    self.save(ctx, segment);
+                }
+                if !timed_out(&key, &ses) {
+                    ses.expire_at = check_time(cfg, &key, &ses.info);
+                    timers.lock().unwrap().insert(ses.expire_at, key.clone());
+                    return;
+                }
+            }
+            None => return,
+        };
+
+        match session_table.remove_if(&key, |key, ses| timed_out(key, ses)) {
+            Some((key, mut ses)) => {
+                limits.remove(&ses);
+                if let Some(tcp) = ses.info.tcp.as_mut() {
+                    tcp.close_reason.get_or_insert(CloseReason::Timeout);
+                }
+                ses.finish();
+                self.save(ctx, ses.info);
+                if key.trans_proto == Protocol::ESP {
+                    spi_table.remove(&key);
+                }
+                pppoe_table.remove(&key);
+            }
+            None => {
+                // a packet arrived in between
+                if let Some(mut ses) = session_table.get_mut(&key) {
+                    if ses.expire_at == time {
+                        ses.expire_at = check_time(cfg, &key, &ses.info);
+                        timers.lock().unwrap().insert(ses.expire_at, key.clone());
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Idle timeout of a session in seconds
+///
+/// Protocol overrides go first, then port overrides, then the transport's timeout.
+/// TCP state overrides take precedence over all of them for connections not established,
+/// a half-open or closed connection won't last long whatever protocol it is.
+pub(super) fn idle_timeout(cfg: &Config, key: &PacketHashKey, ses: &Session) -> u32 {
+    let state = match (key.trans_proto, ses.tcp.as_ref()) {
+        (Protocol::TCP, Some(tcp)) => Some(tcp.state),
+        _ => None,
+    };
+    let state_timeout = state
+        .and_then(|state| cfg.tcp_state_timeouts.get(&state).copied())
+        .or_else(|| {
+            if ses.tcp_closed() {
+                Some(cfg.tcp_close_timeout)
+            } else {
+                None
+            }
+        });
+    if state != Some(TcpState::Established) {
+        if let Some(timeout) = state_timeout {
+            return timeout;
+        }
+    }
+
+    if let Some((_, timeout)) = cfg
+        .protocol_timeouts
+        .iter()
+        .find(|(protocol, _)| ses.has_protocol(protocol))
+    {
+        return *timeout;
+    }
+
+    // responder's port is more likely the service's
+    for port in [ses.dst_port, ses.src_port]
+        .iter()
+        .filter(|port| **port != 0)
+    {
+        if let Some(timeout) = cfg.port_timeouts.get(port) {
+            return *timeout;
+        }
+    }
+
+    match (key.trans_proto, state_timeout) {
+        (_, Some(timeout)) => timeout,
+        (Protocol::TCP, None) => cfg.tcp_timeout,
+        (Protocol::UDP, None) => cfg.udp_timeout,
+        (Protocol::SCTP, None) => cfg.sctp_timeout,
+        _ => cfg.default_timeout,
+    }
+}
+
+/// Time a session needs to be checked next, either it times out or needs a middle save
+pub(super) fn check_time(cfg: &Config, key: &PacketHashKey, ses: &Session) -> u64 {
+    let idle = ses.end_time.tv_sec as u64 + idle_timeout(cfg, key, ses) as u64 + 1;
+    idle.min(ses.save_time + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use api::session::Tcp;
+
+    #[test]
+    fn idle_timeouts() {
+        let mut cfg = Config::default();
+        cfg.tcp_timeout = 60;
+        cfg.tcp_close_timeout = 5;
+        cfg.udp_timeout = 60;
+        cfg.protocol_timeouts = vec![("dns".to_string(), 5), ("bgp".to_string(), 86400)];
+        cfg.port_timeouts.insert(179, 3600);
+        cfg.port_timeouts.insert(22, 7200);
+        cfg.tcp_state_timeouts.insert(TcpState::SynSent, 10);
+
+        let mut key = PacketHashKey::default();
+        key.trans_proto = Protocol::UDP;
+        let mut ses = Session::new();
+        ses.dst_port = 53;
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 60);
+        ses.add_protocol(&"dns");
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 5);
+
+        key.trans_proto = Protocol::TCP;
+        let mut ses = Session::new();
+        ses.src_port = 179;
+        let mut tcp = Tcp::default();
+        tcp.state = TcpState::SynSent;
+        ses.tcp = Some(Box::new(tcp));
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 10);
+        ses.tcp.as_mut().unwrap().state = TcpState::Established;
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 3600);
+        ses.add_protocol(&"bgp");
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 86400);
+        ses.tcp.as_mut().unwrap().state = TcpState::Reset;
+        assert_eq!(idle_timeout(&cfg, &key, &ses), 5);
+    }
 }
@@ -0,0 +1,164 @@
+//! Hierarchical timer wheel with one second resolution
+
+/// Slots of each level
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+/// Level n covers 64^(n+1) seconds, 4 levels cover about 194 days
+const LEVELS: usize = 4;
+
+/// Hierarchical timer wheel
+///
+/// Level 0 slots are seconds, each slot of level n covers a whole level n-1 wheel.
+/// Timers of higher levels cascade down to lower levels as time passes, so advancing
+/// the wheel costs the number of expired timers, not the number of timers.
+pub struct TimerWheel<T> {
+    levels: Vec<Vec<Vec<(u64, T)>>>,
+    /// Timers of each level
+    counts: [usize; LEVELS],
+    /// Timers beyond the range of the wheel
+    far: Vec<(u64, T)>,
+    /// Timers already expired when inserted
+    expired: Vec<(u64, T)>,
+    /// Current time, all timers until it have been fired
+    now: u64,
+}
+
+impl<T> TimerWheel<T> {
+    pub fn new(now: u64) -> Self {
+        TimerWheel {
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            counts: [0; LEVELS],
+            far: Vec::new(),
+            expired: Vec::new(),
+            now,
+        }
+    }
+
+    /// Amount of pending timers
+    pub fn len(&self) -> usize {
+        self.counts.iter().sum::<usize>() + self.far.len() + self.expired.len()
+    }
+
+    /// Add a timer firing at `time`
+    pub fn insert(&mut self, time: u64, item: T) {
+        if time <= self.now {
+            self.expired.push((time, item));
+            return;
+        }
+
+        // the highest bit differing from current time decides the level
+        let bit = 63 - (time ^ self.now).leading_zeros();
+        let level = (bit / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            self.far.push((time, item));
+            return;
+        }
+        let slot = ((time >> (level as u32 * SLOT_BITS)) & SLOT_MASK) as usize;
+        self.levels[level][slot].push((time, item));
+        self.counts[level] += 1;
+    }
+
+    /// Advance the wheel to `now`, returns fired timers and their time
+    pub fn advance(&mut self, now: u64) -> Vec<(u64, T)> {
+        let mut fired = std::mem::take(&mut self.expired);
+
+        while self.now < now {
+            if self.counts.iter().all(|c| *c == 0) {
+                self.now = now;
+                break;
+            }
+
+            if self.counts[0] == 0 {
+                // nothing to fire until the next level 0 round
+                let next = (self.now | SLOT_MASK) + 1;
+                if next > now {
+                    self.now = now;
+                    break;
+                }
+                self.now = next;
+            } else {
+                self.now += 1;
+            }
+
+            self.cascade();
+            let slot = (self.now & SLOT_MASK) as usize;
+            self.counts[0] -= self.levels[0][slot].len();
+            fired.append(&mut self.levels[0][slot]);
+        }
+
+        if !self.far.is_empty() && self.counts.iter().all(|c| *c == 0) {
+            for (time, item) in std::mem::take(&mut self.far) {
+                self.insert(time, item);
+            }
+            fired.append(&mut self.expired);
+        }
+
+        fired
+    }
+
+    /// Move timers of the higher level slots starting at current time down
+    fn cascade(&mut self) {
+        for level in (1..LEVELS).rev() {
+            let shift = level as u32 * SLOT_BITS;
+            if self.now & ((1 << shift) - 1) != 0 {
+                continue;
+            }
+            let slot = ((self.now >> shift) & SLOT_MASK) as usize;
+            let timers = std::mem::take(&mut self.levels[level][slot]);
+            self.counts[level] -= timers.len();
+            for (time, item) in timers {
+                self.insert(time, item);
+            }
+        }
+
+        if self.now & ((1 << (LEVELS as u32 * SLOT_BITS)) - 1) == 0 {
+            for (time, item) in std::mem::take(&mut self.far) {
+                self.insert(time, item);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fire_in_order() {
+        let mut wheel = TimerWheel::new(1000);
+        wheel.insert(1005, 'a');
+        wheel.insert(1100, 'b');
+        wheel.insert(5000, 'c');
+        wheel.insert(1000, 'd');
+        assert_eq!(wheel.len(), 4);
+
+        assert_eq!(wheel.advance(1004), vec![(1000, 'd')]);
+        assert_eq!(wheel.advance(1005), vec![(1005, 'a')]);
+        assert!(wheel.advance(1099).is_empty());
+        // inserted into a level 0 slot after cascading
+        wheel.insert(1101, 'e');
+        assert_eq!(wheel.advance(1101), vec![(1100, 'b'), (1101, 'e')]);
+        assert_eq!(wheel.advance(10000), vec![(5000, 'c')]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn time_jump() {
+        // offline processing starts from zero, then jumps to the first packet time
+        let mut wheel = TimerWheel::new(0);
+        wheel.insert(1615766460, 'a');
+        assert!(wheel.advance(1615766400).is_empty());
+        wheel.insert(1615766405, 'b');
+        assert_eq!(wheel.advance(1615766410), vec![(1615766405, 'b')]);
+        assert_eq!(wheel.advance(1615766460), vec![(1615766460, 'a')]);
+
+        let mut wheel = TimerWheel::new(100);
+        wheel.insert(200, 'a');
+        wheel.insert(100 + (1 << 30), 'b');
+        assert_eq!(wheel.advance(300), vec![(200, 'a')]);
+        assert_eq!(wheel.advance(1 << 31), vec![(100 + (1 << 30), 'b')]);
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::hash_map::Entry;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use fnv::FnvHashMap;
+
+use alphonse_api as api;
+use api::packet::Packet;
+use api::session::Session;
+
+use crate::config::Config;
+
+/// pcap global header magic number, microsecond resolution
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+const SNAPLEN: u32 = 262144;
+/// Buffered bytes handed over to the writer thread at once
+const CHUNK_SIZE: usize = 1 << 20;
+/// Max chunks waiting for the writer thread, chunks beyond are dropped
+const MAX_PENDING_CHUNKS: usize = 64;
+
+/// Path of a pcap file written by the pcap writer
+pub fn file_path<P: AsRef<Path>>(dir: P, id: u32) -> PathBuf {
+    dir.as_ref().join(format!("alphonse-{:08}.pcap", id))
+}
+
+/// Parse the id of a pcap file written by the pcap writer
+fn file_id(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix("alphonse-")?
+        .strip_suffix(".pcap")?
+        .parse()
+        .ok()
+}
+
+/// Data of a pcap file handed over to the writer thread
+struct Chunk {
+    id: u32,
+    data: Vec<u8>,
+    /// Whether it's the last chunk of the file
+    last: bool,
+}
+
+/// The pcap file being written
+struct Current {
+    id: u32,
+    /// Size of the file, including buffered data
+    size: u64,
+    /// First packet's timestamp in seconds
+    start: i64,
+    link_type: u16,
+    buf: Vec<u8>,
+    /// Whether a chunk of the file is dropped, later packets go to a new file
+    /// so they don't land at wrong offsets
+    broken: bool,
+}
+
+/// Appends packets to size and time rotated pcap files
+///
+/// Each packet thread buffers packets into its own pcap file through a [`PcapBuffer`],
+/// the disk I/O is left to the writer thread. Dropping the last reference stops the
+/// writer thread.
+pub struct PcapWriter {
+    /// Max bytes of a pcap file
+    max_file_size: u64,
+    /// Max seconds of packets in a pcap file, 0 for unlimited
+    max_file_time: u64,
+    next_id: AtomicU32,
+    sender: Sender<Chunk>,
+    /// Chunks dropped because the writer thread fell behind or exited
+    dropped: AtomicU64,
+    /// Byte ranges of the dropped chunks, by file id
+    lost: Mutex<FnvHashMap<u32, Vec<Range<u64>>>>,
+}
+
+impl PcapWriter {
+    /// Create the pcap writer and its writer thread
+    ///
+    /// New files are numbered after the existing ones in the output directory
+    pub fn new(cfg: &Config) -> Result<(Arc<Self>, WriterThread)> {
+        let dir = PathBuf::from(&cfg.writer_dir);
+        std::fs::create_dir_all(&dir)?;
+        let last_id = std::fs::read_dir(&dir)?
+            .filter_map(|entry| file_id(&entry.ok()?.path()))
+            .max()
+            .unwrap_or(0);
+
+        let (sender, receiver) = crossbeam_channel::bounded(MAX_PENDING_CHUNKS);
+        let writer = Arc::new(PcapWriter {
+            max_file_size: cfg.writer_file_size,
+            max_file_time: cfg.writer_file_time,
+            next_id: AtomicU32::new(last_id + 1),
+            sender,
+            dropped: AtomicU64::new(0),
+            lost: Mutex::new(FnvHashMap::default()),
+        });
+        let thread = WriterThread {
+            dir,
+            max_disk: cfg.writer_disk,
+            receiver,
+        };
+        Ok((writer, thread))
+    }
+
+    /// Create a packet thread's buffer
+    pub fn buffer(self: &Arc<Self>) -> PcapBuffer {
+        PcapBuffer {
+            writer: self.clone(),
+            current: None,
+        }
+    }
+
+    #[inline]
+    fn hand_over(&self, cur: &mut Current, last: bool) {
+        let chunk = Chunk {
+            id: cur.id,
+            data: std::mem::take(&mut cur.buf),
+            last,
+        };
+        if let Err(e) = self.sender.try_send(chunk) {
+            cur.broken = true;
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            let reason = match e {
+                TrySendError::Full(_) => "the writer thread falls behind",
+                TrySendError::Disconnected(_) => "the writer thread exited",
+            };
+            let len = e.into_inner().data.len() as u64;
+            self.lost
+                .lock()
+                .unwrap()
+                .entry(cur.id)
+                .or_default()
+                .push(cur.size - len..cur.size);
+            eprintln!("Dropped {} bytes of pcap file {}, {}", len, cur.id, reason);
+        }
+    }
+
+    /// Remove the session's packet positions in dropped chunks, they point at unwritten data
+    pub fn discard_lost(&self, ses: &mut Session) {
+        if self.dropped() == 0 {
+            return;
+        }
+        let lost = self.lost.lock().unwrap();
+        let positions = std::mem::take(&mut ses.packet_pos);
+        ses.file_id.clear();
+        let mut id = 0;
+        for pos in positions {
+            if pos < 0 {
+                id = -pos as u32;
+                continue;
+            }
+            let dropped = match lost.get(&id) {
+                Some(ranges) => ranges.iter().any(|r| r.contains(&(pos as u64))),
+                None => false,
+            };
+            if !dropped {
+                ses.add_packet_pos(id, pos as u64);
+            }
+        }
+    }
+
+    /// Number of chunks dropped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for PcapWriter {
+    fn drop(&mut self) {
+        if self.dropped() > 0 {
+            println!("{} pcap chunks dropped", self.dropped());
+        }
+    }
+}
+
+/// A packet thread's pcap file, packets are buffered without locking
+///
+/// Dropping it finishes the file.
+pub struct PcapBuffer {
+    writer: Arc<PcapWriter>,
+    current: Option<Current>,
+}
+
+impl PcapBuffer {
+    /// Append a packet, returns the id of its file and its record's offset in the file
+    pub fn write(&mut self, pkt: &dyn Packet) -> (u32, u64) {
+        let ts = pkt.ts();
+        let record_len = PCAP_RECORD_HEADER_LEN + pkt.raw().len() as u64;
+        let rotate = match &self.current {
+            None => true,
+            Some(cur) => {
+                (cur.size + record_len > self.writer.max_file_size && cur.size > PCAP_HEADER_LEN)
+                    || (self.writer.max_file_time > 0
+                        && ts.tv_sec as i64 - cur.start >= self.writer.max_file_time as i64)
+                    || cur.link_type != pkt.link_type()
+                    || cur.broken
+            }
+        };
+        if rotate {
+            self.rotate(pkt);
+        }
+
+        let cur = self.current.as_mut().unwrap();
+        let pos = cur.size;
+        cur.buf.extend_from_slice(&(ts.tv_sec as u32).to_le_bytes());
+        cur.buf
+            .extend_from_slice(&(ts.tv_usec as u32).to_le_bytes());
+        cur.buf
+            .extend_from_slice(&(pkt.raw().len() as u32).to_le_bytes());
+        cur.buf
+            .extend_from_slice(&pkt.caplen().max(pkt.raw().len() as u32).to_le_bytes());
+        cur.buf.extend_from_slice(pkt.raw());
+        cur.size += record_len;
+        let id = cur.id;
+
+        if cur.buf.len() >= CHUNK_SIZE {
+            self.writer.hand_over(cur, false);
+        }
+        (id, pos)
+    }
+
+    /// Hand over buffered packets to the writer thread
+    pub fn flush(&mut self) {
+        if let Some(cur) = self.current.as_mut() {
+            if !cur.buf.is_empty() {
+                self.writer.hand_over(cur, false);
+            }
+        }
+    }
+
+    /// Finish the current file and start a new one
+    fn rotate(&mut self, pkt: &dyn Packet) {
+        if let Some(mut cur) = self.current.take() {
+            self.writer.hand_over(&mut cur, true);
+        }
+
+        let mut buf = Vec::with_capacity(CHUNK_SIZE);
+        buf.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.extend_from_slice(&4u16.to_le_bytes());
+        buf.extend_from_slice(&0i32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&SNAPLEN.to_le_bytes());
+        buf.extend_from_slice(&(pkt.link_type() as u32).to_le_bytes());
+        self.current = Some(Current {
+            id: self.writer.next_id.fetch_add(1, Ordering::Relaxed),
+            size: PCAP_HEADER_LEN,
+            start: pkt.ts().tv_sec as i64,
+            link_type: pkt.link_type(),
+            buf,
+            broken: false,
+        });
+    }
+}
+
+impl Drop for PcapBuffer {
+    fn drop(&mut self) {
+        if let Some(mut cur) = self.current.take() {
+            self.writer.hand_over(&mut cur, true);
+        }
+    }
+}
+
+/// Writes pcap files to disk, and deletes the oldest ones once disk usage exceeds the limit
+pub struct WriterThread {
+    dir: PathBuf,
+    /// Max bytes of all pcap files, 0 for unlimited
+    max_disk: u64,
+    receiver: Receiver<Chunk>,
+}
+
+impl WriterThread {
+    pub fn name(&self) -> String {
+        "alphonse-writer".to_string()
+    }
+
+    pub fn spawn(&self) -> Result<()> {
+        // files being written by the packet threads
+        let mut files: FnvHashMap<u32, File> = FnvHashMap::default();
+        println!("{} started", self.name());
+
+        for chunk in self.receiver.iter() {
+            let file = match files.entry(chunk.id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let path = file_path(&self.dir, chunk.id);
+                    entry.insert(OpenOptions::new().create(true).append(true).open(&path)?)
+                }
+            };
+            file.write_all(&chunk.data)?;
+
+            if chunk.last {
+                files.remove(&chunk.id);
+                if self.max_disk > 0 {
+                    if let Err(e) = self.enforce_disk_limit(&files) {
+                        eprintln!("{} failed to delete old pcap files: {}", self.name(), e);
+                    }
+                }
+            }
+        }
+
+        println!("{} exit", self.name());
+        Ok(())
+    }
+
+    /// Delete the oldest pcap files until they fit in the disk limit
+    fn enforce_disk_limit(&self, open: &FnvHashMap<u32, File>) -> Result<()> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if let Some(id) = file_id(&entry.path()) {
+                files.push((id, entry.metadata()?.len()));
+            }
+        }
+        files.sort_unstable();
+
+        let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+        // keep the files still being written
+        for (id, size) in files.iter().filter(|(id, _)| !open.contains_key(id)) {
+            if total <= self.max_disk {
+                break;
+            }
+            std::fs::remove_file(file_path(&self.dir, *id))?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use api::utils::packet::Packet as TestPacket;
+
+    fn pkt(sec: i64, len: usize) -> TestPacket {
+        let mut pkt = TestPacket::default();
+        pkt.ts.tv_sec = sec as libc::time_t;
+        pkt.raw = Box::new(vec![0xab; len]);
+        pkt.caplen = len as u32;
+        pkt
+    }
+
+    #[test]
+    fn rotate() {
+        let dir = std::env::temp_dir().join(format!("alphonse-writer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut cfg = Config::default();
+        cfg.writer_dir = dir.to_str().unwrap().to_string();
+        cfg.writer_file_size = 200;
+        cfg.writer_file_time = 60;
+        cfg.writer_disk = 300;
+        // an existing file
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(file_path(&dir, 3), vec![0; 100]).unwrap();
+
+        let (writer, thread) = PcapWriter::new(&cfg).unwrap();
+        let handle = std::thread::spawn(move || thread.spawn());
+        let mut buffer = writer.buffer();
+        assert_eq!(buffer.write(&pkt(0, 100)), (4, 24));
+        // size rotation
+        assert_eq!(buffer.write(&pkt(1, 100)), (5, 24));
+        assert_eq!(buffer.write(&pkt(2, 10)), (5, 140));
+        // time rotation
+        assert_eq!(buffer.write(&pkt(61, 10)), (6, 24));
+        // another thread's buffer writes its own file
+        assert_eq!(writer.buffer().write(&pkt(61, 10)), (7, 24));
+        drop(buffer);
+        drop(writer);
+        handle.join().unwrap().unwrap();
+
+        let data = std::fs::read(file_path(&dir, 5)).unwrap();
+        assert_eq!(data.len(), 166);
+        assert_eq!(&data[..4], &PCAP_MAGIC.to_le_bytes());
+        assert_eq!(&data[140..144], &2u32.to_le_bytes());
+        assert_eq!(&data[148..152], &10u32.to_le_bytes());
+        // oldest files are deleted
+        assert!(!file_path(&dir, 3).exists());
+        assert!(!file_path(&dir, 4).exists());
+        assert_eq!(std::fs::read(file_path(&dir, 6)).unwrap().len(), 50);
+        assert_eq!(std::fs::read(file_path(&dir, 7)).unwrap().len(), 50);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_chunks() {
+        let dir = std::env::temp_dir().join(format!("alphonse-writer-drop-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut cfg = Config::default();
+        cfg.writer_dir = dir.to_str().unwrap().to_string();
+        cfg.writer_file_size = 1000;
+
+        let (writer, thread) = PcapWriter::new(&cfg).unwrap();
+        let mut buffer = writer.buffer();
+        let mut ses = Session::new();
+        let (id, pos) = buffer.write(&pkt(0, 10));
+        assert_eq!((id, pos), (1, 24));
+        ses.add_packet_pos(id, pos);
+        buffer.flush();
+        assert_eq!(writer.dropped(), 0);
+
+        drop(thread);
+        let (id, pos) = buffer.write(&pkt(1, 10));
+        assert_eq!((id, pos), (1, 50));
+        ses.add_packet_pos(id, pos);
+        buffer.flush();
+        assert_eq!(writer.dropped(), 1);
+        // packets after a dropped chunk start a new file
+        let (id, pos) = buffer.write(&pkt(2, 10));
+        assert_eq!((id, pos), (2, 24));
+        ses.add_packet_pos(id, pos);
+        assert_eq!(buffer.write(&pkt(3, 10)), (2, 50));
+
+        // positions in the dropped chunk are discarded
+        writer.discard_lost(&mut ses);
+        assert_eq!(ses.file_id, vec![1, 2]);
+        assert_eq!(ses.packet_pos, vec![-1, 24, -2, 24]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
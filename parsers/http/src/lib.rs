@@ -5,7 +5,10 @@ use once_cell::sync::OnceCell;
 use alphonse_api as api;
 use api::classifiers;
 use api::classifiers::{dpi, Rule, RuleID, RuleType};
+use api::packet::{Direction, Protocol};
 use api::parsers::ParserID;
+use api::parsers::StreamData;
+use api::session::field::{FieldDef, FieldRegistry, FieldType};
 use api::session::Session;
 use api::{add_simple_dpi_rule, add_simple_dpi_tcp_rule};
 
@@ -17,6 +20,8 @@ struct ProtocolParser<'a> {
     name: String,
     classified: bool,
     parsers: [llhttp::Parser<'a>; 2],
+    /// HTTP data shared by both llhttp parsers, null until classified
+    http: *mut HTTP,
 }
 
 fn on_url(parser: &mut llhttp::Parser, at: *const libc::c_char, length: usize) -> libc::c_int {
@@ -41,6 +46,27 @@ struct HTTP {
     checksum: String,
 }
 
+impl HTTP {
+    /// Approximate bytes held by the parsed data
+    fn memory(&self) -> usize {
+        let url: usize = self.url.iter().map(|url| url.capacity()).sum();
+        let headers: usize = self
+            .value
+            .iter()
+            .chain(self.header.iter())
+            .map(|s| s.capacity())
+            .sum();
+        std::mem::size_of::<HTTP>()
+            + self.url.capacity() * std::mem::size_of::<String>()
+            + url
+            + headers
+            + self.host.capacity()
+            + self.cookie.capacity()
+            + self.auth.capacity()
+            + self.checksum.capacity()
+    }
+}
+
 unsafe impl Send for ProtocolParser<'_> {}
 unsafe impl Sync for ProtocolParser<'_> {}
 
@@ -51,8 +77,34 @@ impl<'a> ProtocolParser<'a> {
             name: String::from("http"),
             classified: false,
             parsers: [llhttp::Parser::default(), llhttp::Parser::default()],
+            http: std::ptr::null_mut(),
         }
     }
+
+    /// Feed data of one direction to its llhttp parser
+    fn parse(&mut self, direction: usize, data: &[u8]) -> Result<()> {
+        match self.parsers[direction].parse(data) {
+            llhttp::Error::Ok => Ok(()),
+            llhttp::Error::Paused | llhttp::Error::PausedUpgrade => Ok(()),
+            _ => self.reset(direction),
+        }
+    }
+
+    /// Reinitialize llhttp parser of one direction, keeping the parsed HTTP data
+    fn reset(&mut self, direction: usize) -> Result<()> {
+        let data = self.parsers[direction].set_data::<HTTP>(std::ptr::null_mut());
+        let settings = match SETTINGS.get() {
+            Some(s) => s,
+            None => {
+                return Err(anyhow!(
+                    "Global llhttp sttings is empty or being initialized"
+                ))
+            }
+        };
+        self.parsers[direction].init(settings, llhttp::Type::BOTH);
+        self.parsers[direction].set_data(data);
+        Ok(())
+    }
 }
 
 impl<'a> api::parsers::ProtocolParserTrait for ProtocolParser<'static> {
@@ -75,6 +127,21 @@ impl<'a> api::parsers::ProtocolParserTrait for ProtocolParser<'static> {
         &self.name.as_str()
     }
 
+    fn memory(&self) -> usize {
+        let http = unsafe { self.http.as_ref() }.map_or(0, |http| http.memory());
+        std::mem::size_of_val(self) + http
+    }
+
+    fn register_fields(&self, registry: &mut FieldRegistry) -> Result<()> {
+        registry.register(FieldDef::new(
+            "http.uri",
+            FieldType::Str,
+            "http",
+            "URI",
+            "URIs of HTTP requests",
+        ))
+    }
+
     fn register_classify_rules(
         &mut self,
         manager: &mut classifiers::ClassifierManager,
@@ -174,46 +241,51 @@ impl<'a> api::parsers::ProtocolParserTrait for ProtocolParser<'static> {
                 Some(s) => s,
                 None => return Err(anyhow!("Global llhttp sttings is empty or initializing")),
             };
-            let http = Box::into_raw(Box::new(HTTP::default()));
+            self.http = Box::into_raw(Box::new(HTTP::default()));
             for parser in &mut self.parsers {
                 parser.init(settings, llhttp::Type::BOTH);
-                parser.set_data(http);
+                parser.set_data(self.http);
             }
         }
 
-        let direction = pkt.direction() as u8 as usize;
-
-        match self.parsers[direction].parse(pkt.payload()) {
-            llhttp::Error::Ok => {}
-            llhttp::Error::Paused | llhttp::Error::PausedUpgrade => {}
-            _ => {
-                let data = self.parsers[direction].set_data::<HTTP>(std::ptr::null_mut());
-                let settings = match SETTINGS.get() {
-                    Some(s) => s,
-                    None => {
-                        return Err(anyhow!(
-                            "Global llhttp sttings is empty or being initialized"
-                        ))
-                    }
-                };
-                self.parsers[direction].init(settings, llhttp::Type::BOTH);
-                self.parsers[direction].set_data(data);
-            }
-        };
+        if pkt.layers().trans.protocol == Protocol::TCP {
+            // TCP payloads are parsed in order by parse_stream
+            return Ok(());
+        }
 
-        Ok(())
+        self.parse(pkt.direction() as u8 as usize, pkt.payload())
+    }
+
+    fn stream_parser(&self) -> bool {
+        true
+    }
+
+    fn parse_stream(
+        &mut self,
+        data: StreamData,
+        direction: Direction,
+        _ses: &mut Session,
+    ) -> Result<()> {
+        match data {
+            StreamData::Data(data) => self.parse(direction as u8 as usize, data),
+            // llhttp can't resume after missing bytes, start over from the next message
+            StreamData::Gap(_) => self.reset(direction as u8 as usize),
+        }
     }
 
     fn finish(&mut self, ses: &mut Session) {
         self.parsers[0].set_data::<HTTP>(std::ptr::null_mut());
-        let http = self.parsers[1].set_data::<HTTP>(std::ptr::null_mut());
+        self.parsers[1].set_data::<HTTP>(std::ptr::null_mut());
+        let http = std::mem::replace(&mut self.http, std::ptr::null_mut());
         let http = if http.is_null() {
             return;
         } else {
             unsafe { Box::from_raw(http) }
         };
 
-        ses.add_field(&"http.uri", &serde_json::json!(http.url));
+        for url in &http.url {
+            ses.fields.add_str("http.uri", url);
+        }
     }
 }
 
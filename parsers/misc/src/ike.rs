@@ -0,0 +1,155 @@
+use anyhow::Result;
+use hyperscan::pattern;
+
+use alphonse_api as api;
+use api::classifiers::{dpi, ClassifierManager, Rule, RuleType};
+use api::packet::Packet;
+use api::session::field::{FieldDef, FieldRegistry, FieldType};
+use api::session::Session;
+
+use super::{add_dpi_rule_with_func, add_dpi_udp_rule_with_func, MatchCallBack, ProtocolParser};
+
+const IKE_PORT: u16 = 500;
+const NAT_T_PORT: u16 = 4500;
+/// Length of IKE header
+const HDR_LEN: usize = 28;
+
+pub fn register_classify_rules(
+    parser: &mut ProtocolParser,
+    manager: &mut ClassifierManager,
+) -> Result<()> {
+    // version byte of IKEv1 or IKEv2 header
+    add_dpi_udp_rule_with_func!(r"^[\x00-\xff]{17}[\x10\x20]", classify, parser, manager);
+
+    Ok(())
+}
+
+pub fn register_fields(registry: &mut FieldRegistry) -> Result<()> {
+    registry.register(FieldDef::new(
+        "ike.version",
+        FieldType::Str,
+        "ike",
+        "Version",
+        "IKE protocol version",
+    ))?;
+    registry.register(FieldDef::new(
+        "ike.initiatorSpi",
+        FieldType::Str,
+        "ike",
+        "Initiator SPI",
+        "IKE initiator security parameter index in hex",
+    ))?;
+    registry.register(FieldDef::new(
+        "ike.responderSpi",
+        FieldType::Str,
+        "ike",
+        "Responder SPI",
+        "IKE responder security parameter index in hex",
+    ))?;
+
+    Ok(())
+}
+
+fn classify(ses: &mut Session, pkt: &dyn Packet) {
+    unsafe {
+        if pkt.src_port() != IKE_PORT
+            && pkt.dst_port() != IKE_PORT
+            && pkt.src_port() != NAT_T_PORT
+            && pkt.dst_port() != NAT_T_PORT
+        {
+            return;
+        }
+    }
+
+    let payload = pkt.payload();
+    if payload.len() < HDR_LEN {
+        return;
+    }
+
+    let len = u32::from_be_bytes([payload[24], payload[25], payload[26], payload[27]]);
+    if (len as usize) < HDR_LEN {
+        return;
+    }
+
+    if !ses.has_protocol(&"ike") {
+        ses.add_protocol(&"ike");
+        let version = format!("{}.{}", payload[17] >> 4, payload[17] & 0x0f);
+        ses.fields.set_first("ike.version", version);
+        ses.fields
+            .set_first("ike.initiatorSpi", hex(&payload[0..8]));
+    }
+
+    // responder SPI is zero until the responder's first message
+    if payload[8..16] != [0; 8] {
+        ses.fields
+            .set_first("ike.responderSpi", hex(&payload[8..16]));
+    }
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use api::packet::Protocol;
+    use api::session::Session;
+    use api::{parsers::ProtocolParserTrait, utils::packet::Packet as TestPacket};
+
+    use crate::ProtocolParser;
+
+    #[test]
+    fn ike() {
+        let mut manager = ClassifierManager::new();
+        let mut parser = ProtocolParser::default();
+        parser.register_classify_rules(&mut manager).unwrap();
+        manager.prepare().unwrap();
+        let mut scratch = manager.alloc_scratch().unwrap();
+
+        // IKE_SA_INIT request
+        let mut pkt: Box<TestPacket> = Box::new(TestPacket::default());
+        pkt.raw = Box::new(vec![
+            0x01, 0xf4, 0x01, 0xf4, 0x00, 0x24, 0x00, 0x00, // udp
+            0x86, 0x43, 0x30, 0xac, 0x30, 0xe6, 0x56, 0x4d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x21, 0x20, 0x22, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1c,
+        ]);
+        pkt.layers.trans.protocol = Protocol::UDP;
+        pkt.layers.app.offset = 8;
+        let mut pkt: Box<dyn api::packet::Packet> = pkt;
+        manager.classify(pkt.as_mut(), &mut scratch).unwrap();
+        assert_eq!(pkt.rules().len(), 1);
+
+        let mut ses = Session::new();
+        for rule in pkt.rules() {
+            parser
+                .parse_pkt(pkt.as_ref(), Some(rule), &mut ses)
+                .unwrap();
+        }
+        assert!(ses.has_protocol(&"ike"));
+        let fields = serde_json::to_value(&ses.fields).unwrap();
+        assert_eq!(fields["ike.version"], "2.0");
+        assert_eq!(fields["ike.initiatorSpi"], "864330ac30e6564d");
+        assert!(fields.get("ike.responderSpi").is_none());
+
+        // IKE_SA_INIT response
+        let mut pkt: Box<TestPacket> = Box::new(TestPacket::default());
+        pkt.raw = Box::new(vec![
+            0x01, 0xf4, 0x01, 0xf4, 0x00, 0x24, 0x00, 0x00, // udp
+            0x86, 0x43, 0x30, 0xac, 0x30, 0xe6, 0x56, 0x4d, 0x4a, 0x1b, 0x2c, 0x3d, 0x5e, 0x6f,
+            0x70, 0x81, 0x21, 0x20, 0x22, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1c,
+        ]);
+        pkt.layers.trans.protocol = Protocol::UDP;
+        pkt.layers.app.offset = 8;
+        let mut pkt: Box<dyn api::packet::Packet> = pkt;
+        manager.classify(pkt.as_mut(), &mut scratch).unwrap();
+        for rule in pkt.rules() {
+            parser
+                .parse_pkt(pkt.as_ref(), Some(rule), &mut ses)
+                .unwrap();
+        }
+        let fields = serde_json::to_value(&ses.fields).unwrap();
+        assert_eq!(fields["ike.initiatorSpi"], "864330ac30e6564d");
+        assert_eq!(fields["ike.responderSpi"], "4a1b2c3d5e6f7081");
+    }
+}
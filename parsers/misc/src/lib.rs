@@ -6,6 +6,7 @@ use api::classifiers::{ClassifierManager, RuleID};
 use api::packet::Packet;
 use api::parsers::ParserID;
 use api::parsers::ProtocolParserTrait;
+use api::session::field::{FieldDef, FieldRegistry, FieldType};
 use api::session::Session;
 
 mod areospike;
@@ -17,6 +18,7 @@ mod dropbox;
 mod flap;
 mod flash_policy;
 mod gh0st;
+mod ike;
 mod imap;
 mod jabber;
 mod kafka;
@@ -87,6 +89,7 @@ impl ProtocolParserTrait for ProtocolParser {
         flash_policy::register_classify_rules(self, manager)?;
         flap::register_classify_rules(self, manager)?;
         gh0st::register_classify_rules(self, manager)?;
+        ike::register_classify_rules(self, manager)?;
         imap::register_classify_rules(self, manager)?;
         jabber::register_classify_rules(self, manager)?;
         kafka::register_classify_rules(self, manager)?;
@@ -111,6 +114,20 @@ impl ProtocolParserTrait for ProtocolParser {
         Ok(())
     }
 
+    fn register_fields(&self, registry: &mut FieldRegistry) -> Result<()> {
+        ike::register_fields(registry)?;
+        // added by both rdp and user classifiers
+        registry.register(FieldDef::new(
+            "user",
+            FieldType::Str,
+            "general",
+            "User",
+            "User names seen in the session",
+        ))?;
+
+        Ok(())
+    }
+
     fn is_classified(&self) -> bool {
         self.classified
     }
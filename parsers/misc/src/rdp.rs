@@ -1,6 +1,5 @@
 use anyhow::Result;
 use hyperscan::pattern;
-use serde_json::json;
 
 use alphonse_api as api;
 use api::classifiers::{dpi, ClassifierManager, Rule, RuleType};
@@ -27,12 +26,9 @@ fn classify(ses: &mut Session, pkt: &dyn Packet) {
     {
         ses.add_protocol(&"rdp");
         if payload.len() > 30 && &payload[11..28] == b"Cookie: mstshash=" {
-            match payload[28..].windows(2).position(|win| win == b"\r\n") {
-                Some(pos) => ses.add_field(
-                    &"user",
-                    &json!(String::from_utf8_lossy(&payload[28..28 + pos]).to_string()),
-                ),
-                None => {}
+            if let Some(pos) = payload[28..].windows(2).position(|win| win == b"\r\n") {
+                let user = String::from_utf8_lossy(&payload[28..28 + pos]);
+                ses.fields.add_str("user", &user);
             }
         }
     }
@@ -69,6 +65,9 @@ mod test {
             .parse_pkt(pkt.as_ref(), Some(&pkt.rules()[0]), &mut ses)
             .unwrap();
         assert!(ses.has_protocol(&"rdp"));
-        assert_eq!(ses.fields.as_object().unwrap().get("user").unwrap(), "user");
+        assert_eq!(
+            serde_json::to_value(&ses.fields).unwrap()["user"],
+            serde_json::json!(["user"])
+        );
     }
 }
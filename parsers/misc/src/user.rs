@@ -51,7 +51,7 @@ fn classify(ses: &mut Session, pkt: &dyn Packet) {
                 String::from_utf8_lossy(&user).to_string()
             }
         };
-    ses.add_field(&"user", &serde_json::json!(user));
+    ses.fields.add_str("user", &user);
 }
 
 #[cfg(test)]
@@ -85,6 +85,9 @@ mod test {
                 .parse_pkt(pkt.as_ref(), Some(rule), &mut ses)
                 .unwrap();
         }
-        assert_eq!("\"test_user\"", ses.fields["user"].to_string());
+        assert_eq!(
+            serde_json::to_value(&ses.fields).unwrap()["user"],
+            serde_json::json!(["test_user"])
+        );
     }
 }